@@ -1,22 +1,28 @@
 //! includes database, models (like Dashboard), etc.
 
-// TODO: migrate to postgres?, and rm it
-
+use error::DashboardError;
+use layouts;
+use layouts::DashboardLayout;
+use metrics;
+use metrics::MetricPoint;
 use natord;
-use redis;
-use redis::Commands;
+use schema;
 use serde_json;
-use std::error::Error;
-use utils::{get_redis_con, from_config};
-use uuid::Uuid;
+use std::collections::BTreeMap;
+use storage;
+use storage::{Storage, Subscription};
+use tile_types;
+use tokens::{Access, ApiToken, ApiTokenInfo, TokenScope, DEFAULT_TOKEN_NAME};
+use utils::{from_config, merge_patch, timestamp_ms};
 
 
 pub struct Db {
-    connection: redis::Connection,
+    storage: Box<Storage>,
 }
 
-const DASHBOARDS_KEY: &'static str = "dashboards";
-const TILES_KEY: &'static str = "tiles";
+
+/// Longest time screens can wait for tile updates
+const PUSH_INTERVAL_MAX_MS: u32 = 60 * 1000;
 
 
 /// Returns channel for `dashboard_name` dashboard where changes are announced
@@ -29,13 +35,31 @@ pub fn get_dashboard_channel<D: AsRef<str>>(dashboard_name: D) -> String {
 }
 
 
+/// Change of dashboard announced at its channel
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum Event {
+    TileUpdated { tile_id: String },
+    TilesUpdated { tile_ids: Vec<String> },
+    TileDeleted { tile_id: String },
+    /// settings (e.g. push interval) of dashboard were saved
+    DashboardUpdated,
+    DashboardDeleted,
+    DashboardRenamed { dashboard_name: String },
+}
+
+
 /// Returns JSON with inserted `tile_id` at `"tile-id"`
 fn payload_with_tile_id(
     mut tile_data: serde_json::Value,
     tile_id: &str,
-) -> Result<String, &'static str> {
+) -> Result<String, DashboardError> {
     let mut tile_obj = match tile_data.as_object_mut() {
-        None => return Err("Payload is not an object"),
+        None => {
+            return Err(DashboardError::InvalidPayload(
+                "Payload is not an object".to_string(),
+            ))
+        }
         Some(v) => v,
     };
     tile_obj.insert(
@@ -43,83 +67,175 @@ fn payload_with_tile_id(
         serde_json::Value::String(String::from(tile_id)),
     );
     match serde_json::to_string::<serde_json::Map<String, serde_json::Value>>(tile_obj) {
-        Err(_) => Err("Failed converting to JSON"),
+        Err(_) => Err(DashboardError::InvalidPayload(
+            "Failed converting to JSON".to_string(),
+        )),
         Ok(v) => Ok(v),
     }
 }
 
+/// Parses JSON sent by client
+fn parse_payload(tile_json: &str) -> Result<serde_json::Value, DashboardError> {
+    serde_json::from_str(tile_json).map_err(|e| DashboardError::InvalidPayload(e.to_string()))
+}
+
 
 
 impl Db {
-    pub fn new() -> Result<Db, &'static str> {
-        // TODO: get from thread pool
-        let connection = get_redis_con(from_config("DASHBOARD_REDIS_IP_PORT").as_str())?;
-        let db = Db { connection: connection };
-        Ok(db)
+    /// Opens storage backend set at `DASHBOARD_STORAGE`
+    pub fn new() -> Result<Db, DashboardError> {
+        let storage = storage::open(from_config("DASHBOARD_STORAGE").as_str())?;
+        Ok(Db { storage: storage })
     }
 
-    #[cfg(test)]
-    pub fn run_cmd(&self, cmd: &str) -> Result<(), Box<Error>> {
-        Ok(redis::cmd(cmd).query(&self.connection)?)
-    }
 
-    /// Saves `Dashboard` at `Dashboard.name` in redis
+    /// Saves `Dashboard` at `Dashboard.name`
     ///
     /// # Errors
     /// Raises error when `Dashboard.name` already exists
-    pub fn create_dashboard(&self, dashboard: &Dashboard) -> Result<(), String> {
-        match self.connection
-            .hexists::<_, _, bool>(DASHBOARDS_KEY, &dashboard.name) {
-            Err(e) => return Err(e.to_string()),
-            Ok(true) => return Err(format!("Dashboard {} already exists", dashboard.name)),
-            Ok(false) => (),
-        }
-        self.upsert_dashboard(dashboard)
+    pub fn create_dashboard(&self, dashboard: &Dashboard) -> Result<(), DashboardError> {
+        self.storage.create_dashboard(dashboard)
     }
 
-    /// Inserts `dashboard` (or update when already exists)
-    pub fn upsert_dashboard(&self, dashboard: &Dashboard) -> Result<(), String> {
-        let json = serde_json::to_string(&dashboard)
-            .map_err(|e| e.to_string())?;
-        self.connection
-            .hset::<_, _, _, u64>(DASHBOARDS_KEY, &dashboard.name, &json)
-            .map(|_| ())
-            .map_err(|e| e.to_string())
+    /// Inserts `dashboard` (or update when already exists), its screens are
+    /// told about it
+    pub fn upsert_dashboard(&self, dashboard: &Dashboard) -> Result<(), DashboardError> {
+        self.storage.upsert_dashboard(dashboard)?;
+        self.publish(&dashboard.name, &Event::DashboardUpdated)
     }
 
     /// Returns sorted vector of `Dashboards`
-    pub fn get_dashboards(&self) -> Result<Vec<Dashboard>, Box<Error>> {
-        let mut collection: Vec<Dashboard> = self.connection
-            .hscan::<_, (String, Dashboard)>(DASHBOARDS_KEY)?
-            .map(|(_, dashboard_data)| dashboard_data)
-            .collect();
+    pub fn get_dashboards(&self) -> Result<Vec<Dashboard>, DashboardError> {
+        let mut collection = self.storage.get_dashboards()?;
+        for dashboard in &mut collection {
+            dashboard.upgrade_api_tokens();
+        }
         collection.sort_by(|a, b| natord::compare(&a.name, &b.name));
         Ok(collection)
     }
 
     /// Returns `Dashboard` saved at `dashboard_name`
-    pub fn get_dashboard(&self, dashboard_name: &str) -> Result<Option<Dashboard>, Box<Error>> {
-        let json_op = self.connection
-            .hget::<_, _, Option<String>>(DASHBOARDS_KEY, dashboard_name)?;
-        let json = match json_op {
-            None => return Ok(None),
-            Some(j) => j,
+    pub fn get_dashboard(&self, dashboard_name: &str) -> Result<Option<Dashboard>, DashboardError> {
+        let mut dashboard = self.storage.get_dashboard(dashboard_name)?;
+        if let Some(ref mut d) = dashboard {
+            d.upgrade_api_tokens();
+        }
+        Ok(dashboard)
+    }
+
+    /// Returns `Dashboard` at `dashboard_name` when `secret` (if any) lets
+    /// its holder read it, use of the token is noted
+    ///
+    /// # Errors
+    /// Raises error when there is no such dashboard, when token scope doesn't
+    /// allow `access` or when dashboard is private and `secret` isn't one of
+    /// its active tokens
+    pub fn get_readable_dashboard(
+        &self,
+        dashboard_name: &str,
+        secret: Option<&str>,
+        access: Access,
+    ) -> Result<Dashboard, DashboardError> {
+        let dashboard = match self.get_dashboard(dashboard_name)? {
+            None => return Err(DashboardError::NotFound("Dashboard doesn't exist".to_string())),
+            Some(v) => v,
         };
-        let dashboard: Dashboard = serde_json::from_str(&json)?;
-        Ok(Some(dashboard))
+        let api_token = secret.and_then(|s| dashboard.find_api_token(s, timestamp_ms()));
+        match api_token {
+            Some(api_token) => {
+                if !api_token.scope.allows(&access) {
+                    return Err(access.denied());
+                }
+                self.touch_api_token(dashboard_name, &api_token.name)?;
+            }
+            None if dashboard.visibility == Visibility::Private => {
+                return Err(DashboardError::AuthFailed(
+                    "Dashboard is private, token is needed".to_string(),
+                ))
+            }
+            None => (),
+        }
+        Ok(dashboard)
     }
 
-    /// Deletes `Dashboard` at `dashboard_name`
-    #[cfg(test)]
-    pub fn delete_dashboard(&self, dashboard_name: &str) -> Result<(u64), String> {
-        self.connection
-            .hdel::<_, _, u64>(DASHBOARDS_KEY, dashboard_name)
-            .map(|v| v)
-            .map_err(|e| e.to_string())
+    /// Upgrades data of storage saved by older versions in place
+    pub fn migrate_storage(&self) -> Result<(), DashboardError> {
+        self.storage.migrate()
+    }
+
+    /// Hashes plain api tokens saved by older versions in place, returns
+    /// count of changed dashboards
+    pub fn migrate_api_tokens(&self) -> Result<usize, DashboardError> {
+        let mut count = 0;
+        for mut dashboard in self.storage.get_dashboards()? {
+            if dashboard.upgrade_api_tokens() {
+                self.storage.upsert_dashboard(&dashboard)?;
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+
+    /// Notes that token called `token_name` was used just now
+    pub fn touch_api_token(
+        &self,
+        dashboard_name: &str,
+        token_name: &str,
+    ) -> Result<(), DashboardError> {
+        self.storage
+            .set_api_token_used(dashboard_name, token_name, timestamp_ms())
+    }
+
+    /// Returns tokens of `dashboard` (without secrets) with time of their
+    /// last use
+    pub fn get_api_token_infos(
+        &self,
+        dashboard: &Dashboard,
+    ) -> Result<Vec<ApiTokenInfo>, DashboardError> {
+        let used = self.storage.get_api_tokens_used(&dashboard.name)?;
+        Ok(dashboard
+            .get_api_tokens()
+            .iter()
+            .map(|token| ApiTokenInfo::new(token, used.get(&token.name).cloned()))
+            .collect())
     }
 
-    pub fn get_tile_space(&self, dashboard_name: &str, tile_id: &str) -> String {
-        format!("{}:{}", dashboard_name, tile_id)
+    /// Notes `nonce` of signed request to `dashboard_name` until `expires_at`,
+    /// returns `false` when it was used already
+    pub fn add_nonce(
+        &self,
+        dashboard_name: &str,
+        nonce: &str,
+        expires_at: i64,
+    ) -> Result<bool, DashboardError> {
+        self.storage
+            .add_nonce(dashboard_name, nonce, timestamp_ms(), expires_at)
+    }
+
+    /// Saves `dashboard` in place of the one at `dashboard_name` and moves all
+    /// its tiles under `Dashboard.name`, screens of the old dashboard are told
+    /// about new name
+    ///
+    /// # Errors
+    /// Raises error when there is no dashboard at `dashboard_name` or when
+    /// `Dashboard.name` is already taken
+    pub fn rename_dashboard(
+        &self,
+        dashboard_name: &str,
+        dashboard: &Dashboard,
+    ) -> Result<(), DashboardError> {
+        self.storage.rename_dashboard(dashboard_name, dashboard)?;
+        self.publish(
+            dashboard_name,
+            &Event::DashboardRenamed { dashboard_name: dashboard.name.clone() },
+        )
+    }
+
+    /// Deletes `Dashboard` at `dashboard_name` together with all its tiles
+    pub fn delete_dashboard(&self, dashboard_name: &str) -> Result<u64, DashboardError> {
+        let deleted = self.storage.delete_dashboard(dashboard_name)?;
+        self.publish(dashboard_name, &Event::DashboardDeleted)?;
+        Ok(deleted)
     }
 
     /// Returns `tile` data for `tile_id` from `dashboard_name`
@@ -127,10 +243,33 @@ impl Db {
         &self,
         dashboard_name: &str,
         tile_id: &str,
-    ) -> Result<Option<String>, Box<Error>> {
-        let json = self.connection
-            .hget::<_, _, Option<String>>(TILES_KEY, self.get_tile_space(dashboard_name, tile_id))?;
-        Ok(json)
+    ) -> Result<Option<String>, DashboardError> {
+        Ok(self.storage
+            .get_tile(dashboard_name, tile_id)?
+            .map(|tile| tile.data))
+    }
+
+    /// Returns tile at `tile_id` from `dashboard_name` with its version
+    pub fn get_stored_tile(
+        &self,
+        dashboard_name: &str,
+        tile_id: &str,
+    ) -> Result<Option<StoredTile>, DashboardError> {
+        self.storage.get_tile(dashboard_name, tile_id)
+    }
+
+    /// Returns all tiles of `dashboard_name` sorted by `tile_id`
+    pub fn get_tiles(&self, dashboard_name: &str) -> Result<Vec<StoredTile>, DashboardError> {
+        let mut tiles = self.storage.get_tiles(dashboard_name)?;
+        tiles.sort_by(|a, b| natord::compare(&a.tile_id, &b.tile_id));
+        Ok(tiles)
+    }
+
+    /// Returns data of all tiles of `dashboard_name` sorted by `tile_id`
+    pub fn get_tiles_data(&self, dashboard_name: &str) -> Result<Vec<String>, DashboardError> {
+        let mut tiles = self.storage.get_tiles_data(dashboard_name)?;
+        tiles.sort_by(|a, b| natord::compare(&a.0, &b.0));
+        Ok(tiles.into_iter().map(|(_, data)| data).collect())
     }
 
     /// Adds `tile_json` at `tile_id` for `dashboard_name` (or update if already exists)
@@ -139,16 +278,298 @@ impl Db {
         dashboard_name: &str,
         tile_id: &str,
         tile_json: &str,
-    ) -> Result<(), Box<Error>> {
-        let space = format!("{}:{}", dashboard_name, tile_id);
-        let tile_data = serde_json::from_str::<serde_json::Value>(tile_json)?;
+    ) -> Result<(), DashboardError> {
+        let tile_data = parse_payload(tile_json)?;
         let tile_json_with_id: String = payload_with_tile_id(tile_data, tile_id)?;
-        self.connection
-            .hset::<_, _, _, u64>(TILES_KEY, &space, tile_json_with_id)?;
-        self.connection
-            .publish::<_, _, ()>(get_dashboard_channel(dashboard_name), tile_id)?;
+        self.save_tile(dashboard_name, tile_id, &tile_json_with_id, None)?;
         Ok(())
     }
+
+    /// Works like `upsert_tile`, but tile is normalised by its type (see
+    /// `Dashboard.prepare_tile`) and saved only when it matches its schema and
+    /// `condition` holds for its current state (`None` for missing tile)
+    pub fn upsert_tile_if<F: Fn(Option<&StoredTile>) -> bool>(
+        &self,
+        dashboard: &Dashboard,
+        tile_id: &str,
+        tile_json: &str,
+        condition: F,
+    ) -> Result<TileWrite, DashboardError> {
+        let dashboard_name = dashboard.name.as_str();
+        let tile_data = parse_payload(tile_json)?;
+        let tile_data = dashboard.prepare_tile(tile_id, tile_data)?;
+        let tile_json_with_id: String = payload_with_tile_id(tile_data, tile_id)?;
+        // tile may change between reading its version and writing, then the
+        // condition is checked again
+        loop {
+            let current = self.storage.get_tile(dashboard_name, tile_id)?;
+            if !condition(current.as_ref()) {
+                return Ok(TileWrite::ConditionFailed);
+            }
+            let expected = Some(current.map_or(0, |tile| tile.version));
+            if let Some((version, updated_at)) =
+                self.save_tile(dashboard_name, tile_id, &tile_json_with_id, expected)?
+            {
+                return Ok(TileWrite::Saved {
+                    data: serde_json::from_str(&tile_json_with_id)?,
+                    version: version,
+                    updated_at: updated_at,
+                });
+            }
+        }
+    }
+
+    /// Applies JSON merge `patch` (RFC 7396) to tile at `tile_id` and saves
+    /// it like `upsert_tile` when `condition` holds for current tile
+    ///
+    /// # Errors
+    /// Raises error when patched tile isn't an object or doesn't match its
+    /// schema
+    pub fn patch_tile<F: Fn(Option<&StoredTile>) -> bool>(
+        &self,
+        dashboard: &Dashboard,
+        tile_id: &str,
+        patch: &serde_json::Value,
+        condition: F,
+    ) -> Result<TileWrite, DashboardError> {
+        let dashboard_name = dashboard.name.as_str();
+        loop {
+            let tile = match self.storage.get_tile(dashboard_name, tile_id)? {
+                None => return Ok(TileWrite::Missing),
+                Some(v) => v,
+            };
+            if !condition(Some(&tile)) {
+                return Ok(TileWrite::ConditionFailed);
+            }
+            let mut tile_data: serde_json::Value = serde_json::from_str(&tile.data)?;
+            merge_patch(&mut tile_data, patch);
+            // "tile-id" is added by server, schema describes the rest
+            if let Some(tile_obj) = tile_data.as_object_mut() {
+                tile_obj.remove("tile-id");
+            }
+            let tile_data = dashboard.prepare_tile(tile_id, tile_data)?;
+            // patch may drop or change "tile-id" as well
+            let json_with_id = payload_with_tile_id(tile_data, tile_id)?;
+            // when tile changed since it was read, patch is applied to new one
+            if let Some((version, updated_at)) =
+                self.save_tile(dashboard_name, tile_id, &json_with_id, Some(tile.version))?
+            {
+                return Ok(TileWrite::Saved {
+                    data: serde_json::from_str(&json_with_id)?,
+                    version: version,
+                    updated_at: updated_at,
+                });
+            }
+        }
+    }
+
+    /// Saves ready to serve `tile_json` (see `Storage::upsert_tile` for
+    /// `expected_version`), appends it to tile history and announces it,
+    /// returns new tile version with time of update or `None` when tile
+    /// wasn't saved
+    fn save_tile(
+        &self,
+        dashboard_name: &str,
+        tile_id: &str,
+        tile_json: &str,
+        expected_version: Option<u64>,
+    ) -> Result<Option<(u64, i64)>, DashboardError> {
+        let now = timestamp_ms();
+        let version = match self.storage.upsert_tile(
+            dashboard_name,
+            tile_id,
+            tile_json,
+            now,
+            expected_version,
+        )? {
+            None => return Ok(None),
+            Some(v) => v,
+        };
+        let sample = TileSample {
+            timestamp: now,
+            data: serde_json::from_str(tile_json)?,
+        };
+        self.storage.add_tile_sample(
+            dashboard_name,
+            tile_id,
+            &sample,
+            &Retention::from_config(),
+        )?;
+        self.publish(
+            dashboard_name,
+            &Event::TileUpdated { tile_id: tile_id.to_string() },
+        )?;
+        Ok(Some((version, now)))
+    }
+
+    /// Adds every tile from `tiles` (tile id -> payload) to `dashboard_name`
+    /// at once, records their metrics and announces them in single event,
+    /// returns result of each tile (tiles with invalid payload or not
+    /// matching their schema aren't saved)
+    pub fn upsert_tiles(
+        &self,
+        dashboard: &Dashboard,
+        tiles: &BTreeMap<String, serde_json::Value>,
+    ) -> Result<BTreeMap<String, Result<(), DashboardError>>, DashboardError> {
+        let dashboard_name = dashboard.name.as_str();
+        let now = timestamp_ms();
+        let mut results = BTreeMap::new();
+        let mut samples = Vec::with_capacity(tiles.len());
+        for (tile_id, tile_data) in tiles {
+            let sample = dashboard
+                .prepare_tile(tile_id, tile_data.clone())
+                .and_then(|data| payload_with_tile_id(data, tile_id))
+                .and_then(|json| parse_payload(&json));
+            match sample {
+                Err(e) => {
+                    results.insert(tile_id.clone(), Err(e));
+                }
+                Ok(data) => {
+                    results.insert(tile_id.clone(), Ok(()));
+                    samples.push((
+                        tile_id.clone(),
+                        TileSample {
+                            timestamp: now,
+                            data: data,
+                        },
+                    ));
+                }
+            }
+        }
+        if samples.is_empty() {
+            return Ok(results);
+        }
+        self.storage
+            .upsert_tiles(dashboard_name, &samples, &Retention::from_config())?;
+        for &(ref tile_id, ref sample) in &samples {
+            // tiles are saved already, so failed metric doesn't fail the write
+            if let Err(e) = self.record_metric(dashboard, tile_id, &sample.data) {
+                error!("Recording metric of {} FAILED ({})", tile_id, e);
+            }
+        }
+        self.publish(
+            dashboard_name,
+            &Event::TilesUpdated {
+                tile_ids: samples.into_iter().map(|(tile_id, _)| tile_id).collect(),
+            },
+        )?;
+        Ok(results)
+    }
+
+    /// Deletes tile at `tile_id` with its history and metric, returns
+    /// `false` when there was no such tile
+    pub fn delete_tile(&self, dashboard_name: &str, tile_id: &str) -> Result<bool, DashboardError> {
+        let deleted = self.storage.delete_tile(dashboard_name, tile_id)?;
+        if deleted {
+            self.publish(
+                dashboard_name,
+                &Event::TileDeleted { tile_id: tile_id.to_string() },
+            )?;
+        }
+        Ok(deleted)
+    }
+
+    /// Announces `event` at channel of `dashboard_name`
+    fn publish(&self, dashboard_name: &str, event: &Event) -> Result<(), DashboardError> {
+        self.storage.publish(
+            &get_dashboard_channel(dashboard_name),
+            &serde_json::to_string(event)?,
+        )
+    }
+
+    /// Returns at most `limit` newest samples of `tile_id` taken between
+    /// `from` and `to` (both inclusive), oldest first
+    pub fn get_tile_history(
+        &self,
+        dashboard_name: &str,
+        tile_id: &str,
+        from: i64,
+        to: i64,
+        limit: usize,
+    ) -> Result<Vec<TileSample>, DashboardError> {
+        self.storage
+            .get_tile_history(dashboard_name, tile_id, from, to, limit)
+    }
+
+    /// Records number found in `tile_data` at pointer configured for `tile_id`
+    /// in `dashboard.metrics`, does nothing when there is no such number
+    pub fn record_metric(
+        &self,
+        dashboard: &Dashboard,
+        tile_id: &str,
+        tile_data: &serde_json::Value,
+    ) -> Result<(), DashboardError> {
+        let value = match dashboard
+            .metrics
+            .get(tile_id)
+            .and_then(|pointer| metrics::extract_value(tile_data, pointer)) {
+            None => return Ok(()),
+            Some(v) => v,
+        };
+        let max_points: usize = from_config("DASHBOARD_METRICS_MAX_POINTS")
+            .parse()
+            .expect("DASHBOARD_METRICS_MAX_POINTS is not a number");
+        self.storage.add_metric_point(
+            &dashboard.name,
+            tile_id,
+            &MetricPoint::new(timestamp_ms(), value),
+            max_points,
+        )
+    }
+
+    /// Returns metric points of `tile_id` taken between `from` and `to`
+    /// (both inclusive), oldest first
+    pub fn get_metric_points(
+        &self,
+        dashboard_name: &str,
+        tile_id: &str,
+        from: i64,
+        to: i64,
+    ) -> Result<Vec<MetricPoint>, DashboardError> {
+        self.storage
+            .get_metric_points(dashboard_name, tile_id, from, to)
+    }
+
+    /// Replaces metric points of `tile_id` taken before `before` with `points`
+    pub fn compact_metric_points(
+        &self,
+        dashboard_name: &str,
+        tile_id: &str,
+        before: i64,
+        points: &[MetricPoint],
+    ) -> Result<(), DashboardError> {
+        self.storage
+            .compact_metric_points(dashboard_name, tile_id, before, points)
+    }
+
+    /// Returns (dashboard name, tile id) of every recorded metric
+    pub fn get_metric_series(&self) -> Result<Vec<(String, String)>, DashboardError> {
+        self.storage.get_metric_series()
+    }
+
+    /// Returns subscription to changes of `dashboard_name`, messages are
+    /// JSONed `Event`s
+    pub fn subscribe(&self, dashboard_name: &str) -> Result<Box<Subscription>, DashboardError> {
+        self.storage
+            .subscribe(&get_dashboard_channel(dashboard_name))
+    }
+}
+
+
+/// Who can see dashboard and read its tiles
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Visibility {
+    /// anyone
+    Public,
+    /// holders of its tokens which allow reading
+    Private,
+}
+
+impl Default for Visibility {
+    fn default() -> Visibility {
+        Visibility::Public
+    }
 }
 
 
@@ -156,9 +577,37 @@ impl Db {
 pub struct Dashboard {
     pub name: String,
     pub owner_email: String,
-    // TODO: enum?
-    pub layout: String,
+    pub layout: DashboardLayout,
+    #[serde(default)]
+    pub visibility: Visibility,
+    /// single token kept before `api_tokens`, it's moved there when dashboard
+    /// is read
+    #[serde(default, skip_serializing)]
     api_token: Option<String>,
+    #[serde(default)]
+    api_tokens: Vec<ApiToken>,
+    /// key of signatures required from writes (see `signing`), `None`
+    /// when writes don't have to be signed
+    #[serde(default)]
+    pub signing_secret: Option<String>,
+    /// token generated by `assign_api_token`, never saved
+    #[serde(skip)]
+    plain_api_token: Option<String>,
+    /// JSON pointers (RFC 6901) to numbers recorded as metrics, by tile id
+    #[serde(default)]
+    pub metrics: BTreeMap<String, String>,
+    /// JSON Schemas which tile data has to match, by tile id or tile type
+    /// (tile id is looked up first)
+    #[serde(default)]
+    pub schemas: BTreeMap<String, serde_json::Value>,
+    /// Tile types (e.g. `"chart"`), by tile id
+    #[serde(default)]
+    pub tile_types: BTreeMap<String, String>,
+    /// shortest time in milliseconds between pushes of tiles to screens,
+    /// updates in between are coalesced so only latest value of each tile is
+    /// pushed, 0 pushes every update right away
+    #[serde(default)]
+    pub push_interval_ms: u32,
 }
 
 impl Dashboard {
@@ -168,39 +617,263 @@ impl Dashboard {
         let mut d = Dashboard {
             name: name,
             owner_email: owner_email,
-            layout: layout,
+            layout: DashboardLayout::Named(layout),
+            visibility: Visibility::Public,
             api_token: None,
+            api_tokens: Vec::new(),
+            signing_secret: None,
+            plain_api_token: None,
+            metrics: BTreeMap::new(),
+            schemas: BTreeMap::new(),
+            tile_types: BTreeMap::new(),
+            push_interval_ms: 0,
         };
         d.assign_api_token();
         d
     }
 
-    /// Generates and assigns api token
+    /// Replaces all api tokens with newly generated default one, plain token
+    /// is available from `get_api_token` until dashboard is saved
     pub fn assign_api_token(&mut self) {
-        self.api_token = Some(Uuid::new_v4().to_string());
+        let (api_token, secret) =
+            ApiToken::generate(DEFAULT_TOKEN_NAME.to_string(), timestamp_ms(), None);
+        self.api_token = None;
+        self.api_tokens = vec![api_token];
+        self.plain_api_token = Some(secret);
     }
 
-    /// Returns api token
+    /// Returns plain token generated by `assign_api_token`, `None` for
+    /// dashboard read from storage
     pub fn get_api_token(&self) -> Option<&String> {
-        self.api_token.as_ref()
+        self.plain_api_token.as_ref()
+    }
+
+    /// Moves token kept in old format to `api_tokens` and hashes plain
+    /// tokens, returns `false` when there was nothing to change
+    fn upgrade_api_tokens(&mut self) -> bool {
+        let mut changed = false;
+        if let Some(token) = self.api_token.take() {
+            if !self.api_tokens.iter().any(|t| t.matches(&token)) {
+                self.api_tokens.insert(
+                    0,
+                    ApiToken::from_plain(DEFAULT_TOKEN_NAME.to_string(), &token, 0, None),
+                );
+            }
+            changed = true;
+        }
+        for api_token in &mut self.api_tokens {
+            changed |= api_token.hash_plain();
+        }
+        changed
+    }
+
+    /// Returns all api tokens, oldest first
+    pub fn get_api_tokens(&self) -> &[ApiToken] {
+        &self.api_tokens
     }
-}
 
-impl redis::FromRedisValue for Dashboard {
-    fn from_redis_value(v: &redis::Value) -> redis::RedisResult<Dashboard> {
-        match *v {
-            redis::Value::Data(ref val) => {
-                match serde_json::from_slice(val) {
-                    Err(_) => Err((redis::ErrorKind::TypeError, "Can't unjson value").into()),
-                    Ok(v) => Ok(v),
+    /// Returns token which accepts `secret` and can be used at `now`
+    pub fn find_api_token(&self, secret: &str, now: i64) -> Option<&ApiToken> {
+        // every token is checked, so time doesn't tell which one matched
+        let mut found = None;
+        for api_token in &self.api_tokens {
+            if api_token.matches(secret) && api_token.is_active(now) && found.is_none() {
+                found = Some(api_token);
+            }
+        }
+        found
+    }
+
+    /// Generates token called `name` limited to `scope` which can be used
+    /// until `expires_at`, returns plain token (it's shown only once)
+    ///
+    /// # Errors
+    /// Raises error when name is empty or taken, when token would be expired
+    /// already or when scope is invalid
+    pub fn add_api_token(
+        &mut self,
+        name: &str,
+        expires_at: Option<i64>,
+        scope: TokenScope,
+    ) -> Result<String, DashboardError> {
+        let now = timestamp_ms();
+        if name.is_empty() {
+            return Err(DashboardError::InvalidPayload(
+                "Token name can't be empty".to_string(),
+            ));
+        }
+        if self.api_tokens.iter().any(|t| t.name == name) {
+            return Err(DashboardError::Conflict(format!("Token {} already exists", name)));
+        }
+        if expires_at.map_or(false, |expires_at| expires_at <= now) {
+            return Err(DashboardError::InvalidPayload(
+                "Token would be expired already".to_string(),
+            ));
+        }
+        scope.validate()?;
+        let (mut api_token, secret) = ApiToken::generate(name.to_string(), now, expires_at);
+        api_token.scope = scope;
+        self.api_tokens.push(api_token);
+        Ok(secret)
+    }
+
+    /// Removes token called `name`, returns `false` when there was no such
+    /// token
+    pub fn revoke_api_token(&mut self, name: &str) -> bool {
+        let count = self.api_tokens.len();
+        self.api_tokens.retain(|t| t.name != name);
+        self.api_tokens.len() < count
+    }
+
+    /// Checks whether every registered schema can be used for validation
+    pub fn check_schemas(&self) -> Result<(), DashboardError> {
+        for (key, tile_schema) in &self.schemas {
+            schema::check_schema(tile_schema).map_err(|e| {
+                DashboardError::InvalidPayload(format!("Invalid schema at {} ({})", key, e))
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Returns schema registered for `tile_id` (or for its type)
+    pub fn get_tile_schema(&self, tile_id: &str) -> Option<&serde_json::Value> {
+        self.schemas.get(tile_id).or_else(|| {
+            self.tile_types
+                .get(tile_id)
+                .and_then(|tile_type| self.schemas.get(tile_type))
+        })
+    }
+
+    /// Checks whether screens wouldn't wait too long for tile updates
+    pub fn check_push_interval(&self) -> Result<(), DashboardError> {
+        if self.push_interval_ms > PUSH_INTERVAL_MAX_MS {
+            return Err(DashboardError::InvalidPayload(format!(
+                "Push interval should be at most {} milliseconds",
+                PUSH_INTERVAL_MAX_MS
+            )));
+        }
+        Ok(())
+    }
+
+    /// Checks whether named layout exists or whether layout designed for
+    /// this dashboard can be rendered
+    pub fn check_layout(&self) -> Result<(), DashboardError> {
+        match self.layout {
+            DashboardLayout::Named(ref name) => {
+                if layouts::get(name)?.is_none() {
+                    return Err(DashboardError::InvalidPayload(
+                        format!("Unknown layout: {}", name),
+                    ));
                 }
+                Ok(())
+            }
+            DashboardLayout::Grid(ref layout) => layout.validate(),
+        }
+    }
+
+    /// Checks whether every tile type is known
+    pub fn check_tile_types(&self) -> Result<(), DashboardError> {
+        for (tile_id, tile_type) in &self.tile_types {
+            if tile_types::get(tile_type).is_none() {
+                return Err(DashboardError::InvalidPayload(
+                    format!("Unknown type {} of tile {}", tile_type, tile_id),
+                ));
             }
-            _ => Err(
-                (
-                    redis::ErrorKind::ResponseError,
-                    "Response type not Dashboard compatible.",
-                ).into(),
-            ),
         }
+        Ok(())
+    }
+
+    /// Normalises `tile_data` (without `"tile-id"`) according to type of
+    /// `tile_id` and checks it against its schema, tiles without type or
+    /// schema are taken as they are
+    pub fn prepare_tile(
+        &self,
+        tile_id: &str,
+        tile_data: serde_json::Value,
+    ) -> Result<serde_json::Value, DashboardError> {
+        let tile_data = match self.tile_types.get(tile_id).and_then(|t| tile_types::get(t)) {
+            None => tile_data,
+            Some(tile_type) => tile_type.normalize(tile_data)?,
+        };
+        let tile_schema = match self.get_tile_schema(tile_id) {
+            None => return Ok(tile_data),
+            Some(v) => v,
+        };
+        let violations = schema::validate(tile_schema, &tile_data);
+        if !violations.is_empty() {
+            return Err(DashboardError::SchemaMismatch(violations));
+        }
+        Ok(tile_data)
+    }
+}
+
+
+/// Outcome of conditional tile write
+#[derive(Debug, Clone, PartialEq)]
+pub enum TileWrite {
+    /// tile was saved as `data` and has `version` now
+    Saved {
+        data: serde_json::Value,
+        version: u64,
+        /// milliseconds since unix epoch
+        updated_at: i64,
+    },
+    /// there is no tile to patch
+    Missing,
+    /// current version of tile doesn't satisfy condition
+    ConditionFailed,
+}
+
+
+/// Tile as saved in storage
+#[derive(Debug, Clone, PartialEq)]
+pub struct StoredTile {
+    pub tile_id: String,
+    /// ready to serve tile JSON
+    pub data: String,
+    /// milliseconds since unix epoch, `None` for tiles saved before it was
+    /// tracked
+    pub updated_at: Option<i64>,
+    /// bumped on every write, 0 for tiles saved before it was tracked
+    pub version: u64,
+}
+
+
+/// Tile data as it was at `timestamp`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TileSample {
+    /// milliseconds since unix epoch
+    pub timestamp: i64,
+    pub data: serde_json::Value,
+}
+
+
+/// Limits how much of tile history is kept, `None` means no limit
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Retention {
+    pub max_samples: Option<usize>,
+    /// in milliseconds
+    pub max_age: Option<i64>,
+}
+
+impl Retention {
+    /// Reads `DASHBOARD_HISTORY_MAX_SAMPLES` and `DASHBOARD_HISTORY_MAX_AGE_SECS`,
+    /// 0 turns limit off
+    pub fn from_config() -> Retention {
+        let max_samples: usize = from_config("DASHBOARD_HISTORY_MAX_SAMPLES")
+            .parse()
+            .expect("DASHBOARD_HISTORY_MAX_SAMPLES is not a number");
+        let max_age_secs: i64 = from_config("DASHBOARD_HISTORY_MAX_AGE_SECS")
+            .parse()
+            .expect("DASHBOARD_HISTORY_MAX_AGE_SECS is not a number");
+        Retention {
+            max_samples: if max_samples > 0 { Some(max_samples) } else { None },
+            max_age: if max_age_secs > 0 { Some(max_age_secs * 1000) } else { None },
+        }
+    }
+
+    /// Returns timestamp of oldest sample which is still kept when newest is at `now`
+    pub fn oldest_kept(&self, now: i64) -> Option<i64> {
+        self.max_age.map(|max_age| now - max_age)
     }
 }
@@ -0,0 +1,223 @@
+//! Errors of dashboard, every kind has its own HTTP status and code, so clients
+//! can tell failures apart
+
+use iron::IronError;
+use iron::headers::ContentType;
+use iron::prelude::*;
+use iron::status::Status;
+use redis;
+use rusqlite;
+use schema::SchemaViolation;
+use serde_json;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::sync::mpsc::RecvError;
+use utils::json_response;
+
+
+const INTERNAL_MESSAGE: &'static str = "We're working on fix";
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DashboardError {
+    /// dashboard, tile etc. doesn't exist
+    NotFound(String),
+    /// request clashes with what is saved, like taken dashboard name
+    Conflict(String),
+    /// tile version doesn't match the one from conditional request
+    PreconditionFailed(String),
+    /// payload or query params are malformed
+    InvalidPayload(String),
+    /// tile data doesn't match schema registered for the tile
+    SchemaMismatch(Vec<SchemaViolation>),
+    /// token is missing or doesn't grant access
+    AuthFailed(String),
+    /// client went over rate limit and should retry later
+    TooManyRequests(String),
+    /// storage can't be reached now
+    BackendUnavailable(String),
+    /// anything else, details are logged but not shown to clients
+    Internal(String),
+}
+
+#[derive(Serialize)]
+struct ErrorBody<'a> {
+    code: &'a str,
+    message: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    errors: Option<&'a [SchemaViolation]>,
+}
+
+impl DashboardError {
+    pub fn status(&self) -> Status {
+        match *self {
+            DashboardError::NotFound(_) => Status::NotFound,
+            DashboardError::Conflict(_) => Status::Conflict,
+            DashboardError::PreconditionFailed(_) => Status::PreconditionFailed,
+            DashboardError::InvalidPayload(_) |
+            DashboardError::SchemaMismatch(_) => Status::BadRequest,
+            DashboardError::AuthFailed(_) => Status::Forbidden,
+            DashboardError::TooManyRequests(_) => Status::TooManyRequests,
+            DashboardError::BackendUnavailable(_) => Status::ServiceUnavailable,
+            DashboardError::Internal(_) => Status::InternalServerError,
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match *self {
+            DashboardError::NotFound(_) => "not-found",
+            DashboardError::Conflict(_) => "conflict",
+            DashboardError::PreconditionFailed(_) => "precondition-failed",
+            DashboardError::InvalidPayload(_) => "invalid-payload",
+            DashboardError::SchemaMismatch(_) => "schema-mismatch",
+            DashboardError::AuthFailed(_) => "auth-failed",
+            DashboardError::TooManyRequests(_) => "too-many-requests",
+            DashboardError::BackendUnavailable(_) => "backend-unavailable",
+            DashboardError::Internal(_) => "internal",
+        }
+    }
+
+    fn message(&self) -> &str {
+        match *self {
+            DashboardError::NotFound(ref m) |
+            DashboardError::Conflict(ref m) |
+            DashboardError::PreconditionFailed(ref m) |
+            DashboardError::InvalidPayload(ref m) |
+            DashboardError::AuthFailed(ref m) |
+            DashboardError::TooManyRequests(ref m) |
+            DashboardError::BackendUnavailable(ref m) |
+            DashboardError::Internal(ref m) => m,
+            DashboardError::SchemaMismatch(_) => "Tile data doesn't match its schema",
+        }
+    }
+
+    /// Returns mismatched parts of tile data for `SchemaMismatch`
+    pub fn violations(&self) -> Option<&[SchemaViolation]> {
+        match *self {
+            DashboardError::SchemaMismatch(ref v) => Some(v),
+            _ => None,
+        }
+    }
+
+    /// Returns message which can be shown to clients
+    pub fn public_message(&self) -> &str {
+        match *self {
+            DashboardError::Internal(_) => INTERNAL_MESSAGE,
+            _ => self.message(),
+        }
+    }
+
+    /// Returns `{"code": .., "message": ..}` JSON describing error to clients,
+    /// `SchemaMismatch` lists its violations at `"errors"`
+    pub fn to_json(&self) -> String {
+        let body = ErrorBody {
+            code: self.code(),
+            message: self.public_message(),
+            errors: self.violations(),
+        };
+        serde_json::to_string(&body).unwrap_or_else(|_| "{}".to_string())
+    }
+
+    /// Returns response with status and JSON of error, server side failures
+    /// are logged
+    pub fn to_response(&self) -> IronResult<Response> {
+        self.log();
+        json_response(self.status(), &self.to_json())
+    }
+
+    /// Returns error which stops processing of request (e.g. in middleware)
+    pub fn into_iron_error(self) -> IronError {
+        self.log();
+        let modifier = (ContentType::json().0, self.status(), self.to_json());
+        IronError::new(self, modifier)
+    }
+
+    fn log(&self) {
+        match *self {
+            DashboardError::BackendUnavailable(_) |
+            DashboardError::Internal(_) => error!("Request FAILED ({})", self),
+            _ => (),
+        }
+    }
+}
+
+impl fmt::Display for DashboardError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.code(), self.message())
+    }
+}
+
+impl Error for DashboardError {
+    fn description(&self) -> &str {
+        self.message()
+    }
+}
+
+impl From<redis::RedisError> for DashboardError {
+    fn from(e: redis::RedisError) -> DashboardError {
+        match e.kind() {
+            redis::ErrorKind::IoError => DashboardError::BackendUnavailable(e.to_string()),
+            _ => DashboardError::Internal(e.to_string()),
+        }
+    }
+}
+
+impl From<rusqlite::Error> for DashboardError {
+    fn from(e: rusqlite::Error) -> DashboardError {
+        match e.sqlite_error_code() {
+            Some(rusqlite::ErrorCode::DatabaseBusy) |
+            Some(rusqlite::ErrorCode::DatabaseLocked) |
+            Some(rusqlite::ErrorCode::CannotOpen) => {
+                DashboardError::BackendUnavailable(e.to_string())
+            }
+            _ => DashboardError::Internal(e.to_string()),
+        }
+    }
+}
+
+/// Only data read from storage is unjsoned with `?`, so it's not client fault
+impl From<serde_json::Error> for DashboardError {
+    fn from(e: serde_json::Error) -> DashboardError {
+        DashboardError::Internal(e.to_string())
+    }
+}
+
+impl From<io::Error> for DashboardError {
+    fn from(e: io::Error) -> DashboardError {
+        DashboardError::Internal(e.to_string())
+    }
+}
+
+impl From<RecvError> for DashboardError {
+    fn from(e: RecvError) -> DashboardError {
+        DashboardError::Internal(e.to_string())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn to_json_has_code_and_message() {
+        let error = DashboardError::NotFound("Tile doesn't exist".to_string());
+
+        assert_eq!(
+            error.to_json(),
+            "{\"code\":\"not-found\",\"message\":\"Tile doesn't exist\"}"
+        );
+        assert_eq!(error.status(), Status::NotFound);
+    }
+
+    #[test]
+    fn to_json_hides_details_of_internal_error() {
+        let error = DashboardError::Internal("secret details".to_string());
+
+        assert_eq!(
+            error.to_json(),
+            "{\"code\":\"internal\",\"message\":\"We're working on fix\"}"
+        );
+    }
+}
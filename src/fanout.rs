@@ -0,0 +1,335 @@
+//! Pushing dashboard changes to screens
+//!
+//! Process keeps single subscription (and `Db` connection) per dashboard no
+//! matter how many screens show it. First screen subscribes, so changes made
+//! after it's registered aren't missed, and hands the subscription over to
+//! thread of dashboard. The thread coalesces updated tiles, reads them once
+//! and sends the same message to every screen. Thread stops when it finds
+//! that no screen is left or when its subscription fails, then screens are
+//! closed so they reconnect.
+
+use db;
+use error::DashboardError;
+use serde_json;
+use std::collections::{BTreeSet, HashMap};
+use std::mem;
+use std::sync::{Mutex, MutexGuard};
+use std::thread;
+use std::time::Duration;
+use storage::Subscription;
+use utils::timestamp_ms;
+use websocket::{send_err, send_or_log_err, Message};
+use ws;
+
+
+/// Milliseconds thread of dashboard waits for message before it checks if
+/// dashboard still has screens
+const IDLE_CHECK_MS: i64 = 1000;
+
+
+lazy_static! {
+    static ref SCREENS: Mutex<Screens> = Mutex::new(Screens::default());
+}
+
+
+#[derive(Default)]
+struct Screens {
+    next_id: usize,
+    /// senders of screens by their ids by dashboard name
+    by_dashboard: HashMap<String, HashMap<usize, ws::Sender>>,
+}
+
+fn lock_screens() -> MutexGuard<'static, Screens> {
+    SCREENS.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Returns screens of `dashboard_name`, forgets dashboard and returns `None`
+/// when it has none
+fn screens_of(dashboard_name: &str) -> Option<Vec<ws::Sender>> {
+    let mut screens = lock_screens();
+    let senders: Vec<ws::Sender> = screens
+        .by_dashboard
+        .get(dashboard_name)
+        .map_or(Vec::new(), |v| v.values().cloned().collect());
+    if senders.is_empty() {
+        screens.by_dashboard.remove(dashboard_name);
+        return None;
+    }
+    Some(senders)
+}
+
+/// Forgets `dashboard_name` and returns screens it had
+fn forget(dashboard_name: &str) -> Vec<ws::Sender> {
+    lock_screens()
+        .by_dashboard
+        .remove(dashboard_name)
+        .map_or(Vec::new(), |v| v.into_iter().map(|(_, sender)| sender).collect())
+}
+
+
+/// Screen registered by `watch`, it stops getting changes when dropped
+pub struct Watch {
+    dashboard_name: String,
+    screen_id: usize,
+}
+
+impl Drop for Watch {
+    fn drop(&mut self) {
+        // dashboard itself is forgotten by its thread
+        if let Some(senders) = lock_screens().by_dashboard.get_mut(&self.dashboard_name) {
+            senders.remove(&self.screen_id);
+        }
+    }
+}
+
+/// Sends changes of `dashboard_name` published from now on to `out` until
+/// returned `Watch` is dropped, first screen of dashboard subscribes to them
+/// and starts its thread
+pub fn watch(dashboard_name: &str, out: ws::Sender) -> Result<Watch, DashboardError> {
+    let mut subscription = None;
+    let mut screens = loop {
+        let screens = lock_screens();
+        // thread can't forget dashboard before screen is added, lock is held
+        if screens.by_dashboard.contains_key(dashboard_name) {
+            // subscription made meanwhile isn't needed, it's dropped
+            break screens;
+        }
+        if let Some(subscription) = subscription.take() {
+            let dashboard_name = dashboard_name.to_string();
+            thread::spawn(move || push_changes(&dashboard_name, subscription));
+            break screens;
+        }
+        // subscribing waits for storage, other screens don't wait for it
+        drop(screens);
+        subscription = Some(db::Db::new()?.subscribe(dashboard_name)?);
+    };
+    let screen_id = screens.next_id;
+    screens.next_id += 1;
+    screens
+        .by_dashboard
+        .entry(dashboard_name.to_string())
+        .or_insert_with(HashMap::new)
+        .insert(screen_id, out);
+    Ok(Watch {
+        dashboard_name: dashboard_name.to_string(),
+        screen_id: screen_id,
+    })
+}
+
+/// Returns count of screens of `dashboard_name`, `None` when it has no thread
+#[cfg(test)]
+pub fn screens_count(dashboard_name: &str) -> Option<usize> {
+    lock_screens()
+        .by_dashboard
+        .get(dashboard_name)
+        .map(|senders| senders.len())
+}
+
+
+/// Tiles updated since they were pushed last time, they are pushed at most
+/// once per interval
+struct PendingTiles {
+    /// milliseconds, 0 lets every update be pushed right away
+    interval: i64,
+    /// milliseconds since unix epoch
+    pushed_at: i64,
+    tile_ids: BTreeSet<String>,
+}
+
+impl PendingTiles {
+    fn new(interval: i64) -> PendingTiles {
+        PendingTiles {
+            interval: interval,
+            pushed_at: i64::min_value() / 2,
+            tile_ids: BTreeSet::new(),
+        }
+    }
+
+    fn set_interval(&mut self, interval: i64) {
+        self.interval = interval;
+    }
+
+    fn add(&mut self, tile_ids: Vec<String>) {
+        self.tile_ids.extend(tile_ids);
+    }
+
+    fn remove(&mut self, tile_id: &str) {
+        self.tile_ids.remove(tile_id);
+    }
+
+    /// Returns milliseconds until pending tiles can be pushed, `None` when
+    /// there are none
+    fn wait_ms(&self, now: i64) -> Option<i64> {
+        if self.tile_ids.is_empty() {
+            return None;
+        }
+        Some((self.pushed_at + self.interval - now).max(0))
+    }
+
+    /// Takes tiles which can be pushed at `now`
+    fn take_due(&mut self, now: i64) -> Vec<String> {
+        if self.wait_ms(now) != Some(0) {
+            return Vec::new();
+        }
+        self.pushed_at = now;
+        let tile_ids = mem::replace(&mut self.tile_ids, BTreeSet::new());
+        tile_ids.into_iter().collect()
+    }
+}
+
+
+/// Returns message with current data of `tile_ids`, tiles removed in the
+/// meantime are skipped
+fn tiles_message(
+    db: &db::Db,
+    dashboard_name: &str,
+    tile_ids: &[String],
+) -> Result<Option<Message>, DashboardError> {
+    let mut jsons = Vec::with_capacity(tile_ids.len());
+    for tile_id in tile_ids {
+        if let Some(json) = db.get_tile(dashboard_name, tile_id)? {
+            jsons.push(json);
+        }
+    }
+    Ok(match jsons.len() {
+        0 => None,
+        1 => Some(Message {
+            kind: String::from("tile"),
+            text: jsons.remove(0),
+        }),
+        _ => Some(Message {
+            kind: String::from("tiles"),
+            text: format!("[{}]", jsons.join(",")),
+        }),
+    })
+}
+
+fn send_to_all(screens: &[ws::Sender], msg: &Message) {
+    for out in screens {
+        send_or_log_err(out, msg);
+    }
+}
+
+/// Body of thread of `dashboard_name`, error ends it and is sent to every
+/// screen, which is closed then
+fn push_changes(dashboard_name: &str, subscription: Box<Subscription>) {
+    debug!("pushing changes of {} started", dashboard_name);
+    if let Err(e) = push_changes_while_watched(dashboard_name, subscription) {
+        for out in forget(dashboard_name) {
+            send_err(&out, &e);
+            out.close(ws::CloseCode::Error).unwrap_or_else(|e| {
+                debug!("closing screen of {} FAILED ({})", dashboard_name, e);
+            });
+        }
+    }
+    debug!("pushing changes of {} stopped", dashboard_name);
+}
+
+/// Returns push interval (milliseconds) of `dashboard_name`, 0 when it's gone
+fn get_push_interval(db: &db::Db, dashboard_name: &str) -> Result<i64, DashboardError> {
+    Ok(db.get_dashboard(dashboard_name)?
+        .map_or(0, |d| d.push_interval_ms as i64))
+}
+
+fn push_changes_while_watched(
+    dashboard_name: &str,
+    mut subscription: Box<Subscription>,
+) -> Result<(), DashboardError> {
+    let db = db::Db::new()?;
+    // read after subscribing, so no change of interval is missed
+    let mut pending = PendingTiles::new(get_push_interval(&db, dashboard_name)?);
+    loop {
+        let wait_ms = pending
+            .wait_ms(timestamp_ms())
+            .map_or(IDLE_CHECK_MS, |v| v.min(IDLE_CHECK_MS))
+            .max(1);
+        let published = subscription.get_message(Duration::from_millis(wait_ms as u64))?;
+        let screens = match screens_of(dashboard_name) {
+            None => return Ok(()),
+            Some(v) => v,
+        };
+        if let Some(published) = published {
+            match serde_json::from_str(&published)? {
+                db::Event::TileUpdated { tile_id } => pending.add(vec![tile_id]),
+                db::Event::TilesUpdated { tile_ids } => pending.add(tile_ids),
+                db::Event::TileDeleted { tile_id } => {
+                    pending.remove(&tile_id);
+                    let msg = Message {
+                        kind: String::from("tile-deleted"),
+                        text: tile_id,
+                    };
+                    send_to_all(&screens, &msg);
+                }
+                db::Event::DashboardUpdated => {
+                    pending.set_interval(get_push_interval(&db, dashboard_name)?);
+                }
+                db::Event::DashboardDeleted => {
+                    let msg = Message {
+                        kind: String::from("dashboard-deleted"),
+                        text: dashboard_name.to_string(),
+                    };
+                    send_to_all(&forget(dashboard_name), &msg);
+                    return Ok(());
+                }
+                db::Event::DashboardRenamed { dashboard_name: new_name } => {
+                    // screens should reconnect at new name
+                    let msg = Message {
+                        kind: String::from("dashboard-renamed"),
+                        text: new_name,
+                    };
+                    send_to_all(&forget(dashboard_name), &msg);
+                    return Ok(());
+                }
+            }
+        }
+
+        let tile_ids = pending.take_due(timestamp_ms());
+        if !tile_ids.is_empty() {
+            if let Some(msg) = tiles_message(&db, dashboard_name, &tile_ids)? {
+                send_to_all(&screens, &msg);
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pending_tiles_are_pushed_once_per_interval() {
+        let mut pending = PendingTiles::new(100);
+        pending.add(vec!["a".to_string()]);
+
+        assert_eq!(pending.take_due(1000), vec!["a".to_string()]);
+        pending.add(vec!["b".to_string(), "a".to_string()]);
+        pending.add(vec!["a".to_string()]);
+        assert_eq!(pending.wait_ms(1040), Some(60));
+        assert_eq!(pending.take_due(1040), Vec::<String>::new());
+        assert_eq!(pending.take_due(1100), vec!["a".to_string(), "b".to_string()]);
+        assert_eq!(pending.wait_ms(1100), None);
+    }
+
+    #[test]
+    fn pending_tiles_are_pushed_by_changed_interval() {
+        let mut pending = PendingTiles::new(1000);
+        pending.add(vec!["a".to_string()]);
+        assert_eq!(pending.take_due(1000), vec!["a".to_string()]);
+        pending.add(vec!["a".to_string()]);
+        pending.set_interval(100);
+
+        assert_eq!(pending.wait_ms(1040), Some(60));
+        assert_eq!(pending.take_due(1100), vec!["a".to_string()]);
+    }
+
+    #[test]
+    fn pending_tiles_without_interval_are_pushed_right_away() {
+        let mut pending = PendingTiles::new(0);
+        pending.add(vec!["a".to_string()]);
+        assert_eq!(pending.take_due(1000), vec!["a".to_string()]);
+        pending.add(vec!["a".to_string()]);
+
+        assert_eq!(pending.take_due(1000), vec!["a".to_string()]);
+    }
+}
@@ -1,12 +1,19 @@
 use bodyparser;
 
 use db;
-use db::Dashboard;
+use db::{Dashboard, Visibility};
+use error::DashboardError;
 use iron::prelude::*;
+use layouts;
+use layouts::{DashboardLayout, Layout};
 use iron::status::Status;
 use persistent;
+use rate_limit;
+use rest_api::{check_signature, get_authorized_dashboard};
 use router::Router;
 use serde_json;
+use tile_types;
+use tokens::Access;
 use utils::json_response;
 
 
@@ -19,12 +26,8 @@ struct ResponseMessage {
 }
 
 
-fn json_response_as_msg<M: AsRef<str>>(status: Status, details_msg: M) -> IronResult<Response> {
-    let msg = match status {
-        Status::Created => details_msg.as_ref().to_string(),
-        _ => format!("Couldn't create dashboard ({})", details_msg.as_ref()),
-    };
-    let resp_msg = ResponseMessage { message: msg };
+fn json_response_as_msg<M: AsRef<str>>(status: Status, msg: M) -> IronResult<Response> {
+    let resp_msg = ResponseMessage { message: msg.as_ref().to_string() };
     let json = serde_json::to_string(&resp_msg).unwrap_or_else(|_| "{}".to_string());
     json_response(status, &json)
 }
@@ -33,6 +36,11 @@ fn json_response_as_msg<M: AsRef<str>>(status: Status, details_msg: M) -> IronRe
 pub fn get_router() -> Chain {
     let mut router = Router::new();
     router.post("/dashboard", dashboard_post, "dashboard_post");
+    router.put("/dashboard/:dashboard_name", dashboard_put, "dashboard_put");
+    router.patch("/dashboard/:dashboard_name", dashboard_patch, "dashboard_patch");
+    router.get("/tile-types", tile_types_get, "tile_types_get");
+    router.get("/layouts", layouts_get, "layouts_get");
+    router.get("/rate-limits", rate_limits_get, "rate_limits_get");
     let mut chain = Chain::new(router);
     chain.link_before(persistent::Read::<bodyparser::MaxBodyLength>::one(
         MAX_BODY_LENGTH,
@@ -43,24 +51,37 @@ pub fn get_router() -> Chain {
 
 pub fn dashboard_post(req: &mut Request) -> IronResult<Response> {
     let mut dashboard = match req.get::<bodyparser::Struct<Dashboard>>() {
-        Err(e) => return json_response_as_msg(Status::BadRequest, e.to_string()),
-        Ok(None) => return json_response_as_msg(Status::BadRequest, "Payload is missing"),
+        Err(e) => return DashboardError::InvalidPayload(e.to_string()).to_response(),
+        Ok(None) => {
+            return DashboardError::InvalidPayload("Payload is missing".to_string()).to_response()
+        }
         Ok(Some(v)) => v,
     };
 
+    let checked = dashboard
+        .check_layout()
+        .and_then(|_| dashboard.check_schemas())
+        .and_then(|_| dashboard.check_tile_types())
+        .and_then(|_| dashboard.check_push_interval());
+    if let Err(e) = checked {
+        return e.to_response();
+    }
+
     let db = match db::Db::new() {
-        Err(e) => return json_response_as_msg(Status::InternalServerError, e.to_string()),
+        Err(e) => return e.to_response(),
         Ok(v) => v,
     };
 
     dashboard.assign_api_token();
     let api_token = match dashboard.get_api_token() {
-        None => return json_response_as_msg(Status::InternalServerError, "Can't generate token"),
+        None => {
+            return DashboardError::Internal("Can't generate token".to_string()).to_response()
+        }
         Some(v) => v,
     };
 
     if let Err(e) = db.create_dashboard(&dashboard) {
-        return json_response_as_msg(Status::BadRequest, e.to_string());
+        return e.to_response();
     }
 
     // TODO:
@@ -83,6 +104,174 @@ pub fn dashboard_post(req: &mut Request) -> IronResult<Response> {
 }
 
 
+/// Settings of dashboard which can be changed after creation
+#[derive(Clone, Deserialize)]
+struct DashboardChange {
+    name: String,
+    owner_email: String,
+    layout: DashboardLayout,
+    /// kept as it is when missing
+    visibility: Option<Visibility>,
+    /// kept as it is when missing
+    push_interval_ms: Option<u32>,
+}
+
+/// Part of `DashboardChange`, missing fields are kept as they are
+#[derive(Clone, Deserialize)]
+struct DashboardPatch {
+    name: Option<String>,
+    owner_email: Option<String>,
+    layout: Option<DashboardLayout>,
+    visibility: Option<Visibility>,
+    push_interval_ms: Option<u32>,
+}
+
+impl From<DashboardChange> for DashboardPatch {
+    fn from(change: DashboardChange) -> DashboardPatch {
+        DashboardPatch {
+            name: Some(change.name),
+            owner_email: Some(change.owner_email),
+            layout: Some(change.layout),
+            visibility: change.visibility,
+            push_interval_ms: change.push_interval_ms,
+        }
+    }
+}
+
+/// Applies `patch` to dashboard at `:dashboard_name` of owner of its token,
+/// dashboard is renamed when name differs
+fn edit_dashboard(
+    req: &mut Request,
+    patch: DashboardPatch,
+) -> Result<(Status, String), DashboardError> {
+    // body parsed into `patch` is kept by bodyparser
+    let body = match req.get::<bodyparser::Raw>() {
+        Err(e) => return Err(DashboardError::InvalidPayload(e.to_string())),
+        Ok(v) => v.unwrap_or_default(),
+    };
+    let dashboard_name = req.extensions
+        .get::<Router>()
+        .unwrap()
+        .find("dashboard_name")
+        .unwrap()
+        .to_string();
+    let db = db::Db::new()?;
+    let mut dashboard = get_authorized_dashboard(req, &db, &dashboard_name, Access::Manage)?;
+    check_signature(req, &db, &dashboard, &body)?;
+    if let Some(name) = patch.name {
+        if name.is_empty() {
+            return Err(DashboardError::InvalidPayload(
+                "Dashboard name can't be empty".to_string(),
+            ));
+        }
+        dashboard.name = name;
+    }
+    if let Some(owner_email) = patch.owner_email {
+        dashboard.owner_email = owner_email;
+    }
+    if let Some(layout) = patch.layout {
+        dashboard.layout = layout;
+    }
+    if let Some(visibility) = patch.visibility {
+        dashboard.visibility = visibility;
+    }
+    if let Some(push_interval_ms) = patch.push_interval_ms {
+        dashboard.push_interval_ms = push_interval_ms;
+    }
+    dashboard.check_layout()?;
+    dashboard.check_push_interval()?;
+
+    if dashboard.name == dashboard_name {
+        db.upsert_dashboard(&dashboard)?;
+    } else {
+        db.rename_dashboard(&dashboard_name, &dashboard)?;
+    }
+    Ok((Status::Ok, "Dashboard is saved".to_string()))
+}
+
+fn _dashboard_put(req: &mut Request) -> Result<(Status, String), DashboardError> {
+    let change = match req.get::<bodyparser::Struct<DashboardChange>>() {
+        Err(e) => return Err(DashboardError::InvalidPayload(e.to_string())),
+        Ok(None) => return Err(DashboardError::InvalidPayload("Payload is missing".to_string())),
+        Ok(Some(v)) => v,
+    };
+    edit_dashboard(req, change.into())
+}
+
+/// Replaces name, owner email and layout of dashboard
+pub fn dashboard_put(req: &mut Request) -> IronResult<Response> {
+    match _dashboard_put(req) {
+        Err(e) => e.to_response(),
+        Ok((status, msg)) => json_response_as_msg(status, msg),
+    }
+}
+
+fn _dashboard_patch(req: &mut Request) -> Result<(Status, String), DashboardError> {
+    let patch = match req.get::<bodyparser::Struct<DashboardPatch>>() {
+        Err(e) => return Err(DashboardError::InvalidPayload(e.to_string())),
+        Ok(None) => return Err(DashboardError::InvalidPayload("Payload is missing".to_string())),
+        Ok(Some(v)) => v,
+    };
+    edit_dashboard(req, patch)
+}
+
+/// Changes only given fields of `DashboardChange`
+pub fn dashboard_patch(req: &mut Request) -> IronResult<Response> {
+    match _dashboard_patch(req) {
+        Err(e) => e.to_response(),
+        Ok((status, msg)) => json_response_as_msg(status, msg),
+    }
+}
+
+
+/// Returns tile types which dashboards can declare for their tiles
+pub fn tile_types_get(_: &mut Request) -> IronResult<Response> {
+    match serde_json::to_string(tile_types::all()) {
+        Err(e) => DashboardError::from(e).to_response(),
+        Ok(json) => json_response(Status::Ok, &json),
+    }
+}
+
+
+#[derive(Serialize)]
+struct LayoutItem<'a> {
+    name: &'a str,
+    layout: &'a Layout,
+}
+
+/// Returns named layouts (sorted by name) with their grids, so they can be
+/// previewed
+pub fn layouts_get(_: &mut Request) -> IronResult<Response> {
+    let layouts = match layouts::all() {
+        Err(e) => return e.to_response(),
+        Ok(v) => v,
+    };
+    let items: Vec<LayoutItem> = layouts
+        .iter()
+        .map(|&(ref name, ref layout)| {
+            LayoutItem {
+                name: name,
+                layout: layout,
+            }
+        })
+        .collect();
+    match serde_json::to_string(&items) {
+        Err(e) => DashboardError::from(e).to_response(),
+        Ok(json) => json_response(Status::Ok, &json),
+    }
+}
+
+
+/// Returns count of REST API requests rejected by each kind of rate limit
+/// since start
+pub fn rate_limits_get(_: &mut Request) -> IronResult<Response> {
+    match serde_json::to_string(&rate_limit::hits()) {
+        Err(e) => DashboardError::from(e).to_response(),
+        Ok(json) => json_response(Status::Ok, &json),
+    }
+}
+
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -90,18 +279,18 @@ mod tests {
     use iron::Headers;
     use iron::headers::ContentType;
     use iron_test::{request, response};
-    use utils;
+    use test_utils;
 
     #[test]
     fn test_dashboard_post_creates_dashboard_when_ok() {
-        utils::load_config(None);
+        test_utils::load_config();
         // TODO: no setup/teardown bundled feature, move when possible
         let db = db::Db::new().unwrap();
         db.delete_dashboard("some-name").unwrap();
         let payload = r#"{
 			"name": "some-name",
 			"owner_email": "some-dude@some-email.com",
-			"layout": "single-tile"
+			"layout": "2x4"
 		}"#;
         let mut headers = Headers::new();
         headers.set(ContentType::json());
@@ -117,11 +306,102 @@ mod tests {
         let body = response::extract_body_to_string(resp);
         let dashboard = db.get_dashboard("some-name").unwrap().unwrap();
         assert_eq!(&dashboard.owner_email, "some-dude@some-email.com");
-        let expected = format!(
-            "{{\"message\":\"Dashboard is created! Save your personal token: {}\"}}",
-            dashboard.get_api_token().unwrap()
+        let prefix = "{\"message\":\"Dashboard is created! Save your personal token: ";
+        assert!(body.starts_with(prefix));
+        let api_token = body[prefix.len()..].trim_right_matches("\"}");
+        assert!(dashboard.find_api_token(api_token, 0).is_some());
+        assert!(!serde_json::to_string(&dashboard).unwrap().contains(api_token));
+    }
+
+    #[test]
+    fn test_dashboard_post_gives_400_when_tile_type_is_unknown() {
+        test_utils::load_config();
+        let payload = r#"{
+			"name": "some-name-with-unknown-tile-type",
+			"owner_email": "some-dude@some-email.com",
+			"layout": "2x4",
+			"tile_types": {"tile-1": "no-such-type"}
+		}"#;
+        let mut headers = Headers::new();
+        headers.set(ContentType::json());
+
+        let resp = request::post(
+            "http://localhost:3000/dashboard",
+            headers,
+            &payload,
+            &get_router(),
+        ).unwrap();
+
+        assert_eq!(resp.status, Some(Status::BadRequest));
+        let db = db::Db::new().unwrap();
+        assert!(
+            db.get_dashboard("some-name-with-unknown-tile-type")
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_dashboard_post_gives_400_when_layout_is_unknown() {
+        test_utils::load_config();
+        let payload = r#"{
+			"name": "some-name-with-unknown-layout",
+			"owner_email": "some-dude@some-email.com",
+			"layout": "single-tile"
+		}"#;
+        let mut headers = Headers::new();
+        headers.set(ContentType::json());
+
+        let resp = request::post(
+            "http://localhost:3000/dashboard",
+            headers,
+            &payload,
+            &get_router(),
+        ).unwrap();
+
+        assert_eq!(resp.status, Some(Status::BadRequest));
+        let body = response::extract_body_to_string(resp);
+        assert_eq!(
+            body,
+            "{\"code\":\"invalid-payload\",\"message\":\"Unknown layout: single-tile\"}"
+        );
+    }
+
+    #[test]
+    fn test_layouts_get_lists_layouts() {
+        test_utils::load_config();
+
+        let resp = request::get(
+            "http://localhost:3000/layouts",
+            Headers::new(),
+            &get_router(),
+        ).unwrap();
+
+        assert_eq!(resp.status, Some(Status::Ok));
+        let body = response::extract_body_to_string(resp);
+        let layouts: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(layouts[0]["name"], "2x4");
+        assert_eq!(layouts[0]["layout"]["columns"], 2);
+    }
+
+    #[test]
+    fn test_tile_types_get_lists_tile_types() {
+        let resp = request::get(
+            "http://localhost:3000/tile-types",
+            Headers::new(),
+            &get_router(),
+        ).unwrap();
+
+        assert_eq!(resp.status, Some(Status::Ok));
+        let body = response::extract_body_to_string(resp);
+        let types: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert!(
+            types
+                .as_array()
+                .unwrap()
+                .iter()
+                .any(|t| t["name"] == "gauge")
         );
-        assert_eq!(body, expected);
     }
 
 }
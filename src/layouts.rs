@@ -0,0 +1,297 @@
+//! Grid layouts of dashboards
+//!
+//! Layout is a grid of cells, every cell takes one or more grid slots and
+//! shows one tile (or rotates a few of them). Named layouts are JSON files in
+//! `DASHBOARD_LAYOUTS_PATH`, dashboards can have own layout as well. All of
+//! them are rendered by `dashboards/grid` template.
+
+use error::DashboardError;
+use natord;
+use serde_json;
+use std::fs;
+use std::fs::File;
+use std::io::ErrorKind;
+use std::path::Path;
+use utils::from_config;
+
+
+const GRID_SIZE_MAX: u32 = 12;
+const FLIP_TIME_DEFAULT: u32 = 5000;
+
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Layout {
+    pub rows: u32,
+    pub columns: u32,
+    pub cells: Vec<LayoutCell>,
+}
+
+/// Part of grid starting at `row` and `column` (both counted from 1)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LayoutCell {
+    pub row: u32,
+    pub column: u32,
+    #[serde(default = "one")]
+    pub row_span: u32,
+    #[serde(default = "one")]
+    pub column_span: u32,
+    /// tiles shown in the cell, more of them are rotated every `flip_time`
+    /// milliseconds
+    pub tiles: Vec<LayoutTile>,
+    #[serde(default = "flip_time_default")]
+    pub flip_time: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LayoutTile {
+    pub tile_id: String,
+    /// web component which renders tile, e.g. `tile-chart`
+    pub element: String,
+}
+
+fn one() -> u32 {
+    1
+}
+
+fn flip_time_default() -> u32 {
+    FLIP_TIME_DEFAULT
+}
+
+
+/// Layout of dashboard
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum DashboardLayout {
+    /// name of layout from `DASHBOARD_LAYOUTS_PATH`
+    Named(String),
+    /// layout designed for single dashboard
+    Grid(Layout),
+}
+
+impl DashboardLayout {
+    /// Returns grid of layout, `None` when there is no layout with such name
+    pub fn resolve(&self) -> Result<Option<Layout>, DashboardError> {
+        match *self {
+            DashboardLayout::Named(ref name) => get(name),
+            DashboardLayout::Grid(ref layout) => Ok(Some(layout.clone())),
+        }
+    }
+}
+
+
+impl Layout {
+    /// Checks whether cells fit in grid without overlapping and whether tiles
+    /// can be rendered
+    pub fn validate(&self) -> Result<(), DashboardError> {
+        if self.rows < 1 || self.rows > GRID_SIZE_MAX || self.columns < 1 ||
+            self.columns > GRID_SIZE_MAX
+        {
+            return Err(invalid(format!(
+                "Grid should have from 1 to {} rows and columns",
+                GRID_SIZE_MAX
+            )));
+        }
+        let mut taken = vec![false; (self.rows * self.columns) as usize];
+        let mut tile_ids = Vec::new();
+        for cell in &self.cells {
+            if cell.row < 1 || cell.column < 1 || cell.row_span < 1 || cell.column_span < 1 ||
+                cell.row_span > self.rows || cell.column_span > self.columns ||
+                cell.row > self.rows + 1 - cell.row_span ||
+                cell.column > self.columns + 1 - cell.column_span
+            {
+                return Err(invalid(format!(
+                    "Cell at row {} and column {} doesn't fit in grid",
+                    cell.row,
+                    cell.column
+                )));
+            }
+            for row in cell.row..cell.row + cell.row_span {
+                for column in cell.column..cell.column + cell.column_span {
+                    let slot = ((row - 1) * self.columns + column - 1) as usize;
+                    if taken[slot] {
+                        return Err(invalid(format!(
+                            "Cells overlap at row {} and column {}",
+                            row,
+                            column
+                        )));
+                    }
+                    taken[slot] = true;
+                }
+            }
+            if cell.tiles.is_empty() {
+                return Err(invalid(format!(
+                    "Cell at row {} and column {} has no tiles",
+                    cell.row,
+                    cell.column
+                )));
+            }
+            for tile in &cell.tiles {
+                if !is_identifier(&tile.tile_id) {
+                    return Err(invalid(format!("Invalid tile id: {}", tile.tile_id)));
+                }
+                if tile_ids.contains(&&tile.tile_id) {
+                    return Err(invalid(format!("Tile {} is placed twice", tile.tile_id)));
+                }
+                tile_ids.push(&tile.tile_id);
+                // element name goes into markup as it is
+                if !tile.element.starts_with("tile-") || !is_identifier(&tile.element) {
+                    return Err(invalid(format!("Invalid tile element: {}", tile.element)));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn invalid(msg: String) -> DashboardError {
+    DashboardError::InvalidPayload(msg)
+}
+
+/// Checks whether `name` has only ASCII letters, digits, `-` and `_`
+fn is_identifier(name: &str) -> bool {
+    !name.is_empty() &&
+        name.chars().all(|c| {
+            c.is_ascii_alphanumeric() || c == '-' || c == '_'
+        })
+}
+
+
+/// Returns layout saved as `{name}.json` in `DASHBOARD_LAYOUTS_PATH`
+pub fn get(name: &str) -> Result<Option<Layout>, DashboardError> {
+    read_layout(Path::new(&from_config("DASHBOARD_LAYOUTS_PATH")), name)
+}
+
+/// Returns every layout from `DASHBOARD_LAYOUTS_PATH` with its name, sorted by
+/// name
+pub fn all() -> Result<Vec<(String, Layout)>, DashboardError> {
+    read_layouts(Path::new(&from_config("DASHBOARD_LAYOUTS_PATH")))
+}
+
+fn read_layouts(dir: &Path) -> Result<Vec<(String, Layout)>, DashboardError> {
+    let mut names = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().map_or(true, |ext| ext != "json") {
+            continue;
+        }
+        if let Some(name) = path.file_stem().and_then(|stem| stem.to_str()) {
+            names.push(name.to_string());
+        }
+    }
+    names.sort_by(|a, b| natord::compare(a, b));
+    let mut layouts = Vec::with_capacity(names.len());
+    for name in names {
+        if let Some(layout) = read_layout(dir, &name)? {
+            layouts.push((name, layout));
+        }
+    }
+    Ok(layouts)
+}
+
+fn read_layout(dir: &Path, name: &str) -> Result<Option<Layout>, DashboardError> {
+    if !is_identifier(name) {
+        return Ok(None);
+    }
+    let path = dir.join(format!("{}.json", name));
+    let file = match File::open(&path) {
+        Err(ref e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+        Ok(v) => v,
+    };
+    let layout: Layout = serde_json::from_reader(file)?;
+    layout.validate().map_err(|e| {
+        DashboardError::Internal(format!("Layout {} is broken ({})", name, e))
+    })?;
+    Ok(Some(layout))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cell(row: u32, column: u32, tile_id: &str) -> LayoutCell {
+        LayoutCell {
+            row: row,
+            column: column,
+            row_span: 1,
+            column_span: 1,
+            tiles: vec![
+                LayoutTile {
+                    tile_id: tile_id.to_string(),
+                    element: "tile-value".to_string(),
+                },
+            ],
+            flip_time: FLIP_TIME_DEFAULT,
+        }
+    }
+
+    #[test]
+    fn validate_fails_when_cells_overlap() {
+        let mut wide = cell(1, 1, "tile-1");
+        wide.column_span = 2;
+        let layout = Layout {
+            rows: 1,
+            columns: 2,
+            cells: vec![wide, cell(1, 2, "tile-2")],
+        };
+
+        assert_eq!(
+            layout.validate(),
+            Err(DashboardError::InvalidPayload(
+                "Cells overlap at row 1 and column 2".to_string(),
+            ))
+        );
+    }
+
+    #[test]
+    fn validate_fails_when_element_is_not_tile() {
+        let mut bad = cell(1, 1, "tile-1");
+        bad.tiles[0].element = "script".to_string();
+        let layout = Layout {
+            rows: 1,
+            columns: 1,
+            cells: vec![bad],
+        };
+
+        assert!(layout.validate().is_err());
+    }
+
+    #[test]
+    fn read_layout_returns_bundled_layout() {
+        let dir = Path::new("src/layouts");
+
+        let layout = read_layout(dir, "2x4").unwrap().unwrap();
+
+        assert_eq!((layout.rows, layout.columns), (4, 2));
+        assert_eq!(read_layout(dir, "no-such-layout").unwrap(), None);
+        assert_eq!(read_layout(dir, "../2x4").unwrap(), None);
+    }
+
+    #[test]
+    fn read_layouts_returns_bundled_layouts() {
+        let layouts = read_layouts(Path::new("src/layouts")).unwrap();
+
+        assert!(layouts.iter().any(|&(ref name, _)| name == "2x4"));
+    }
+
+    #[test]
+    fn dashboard_layout_is_name_or_grid() {
+        let named: DashboardLayout = serde_json::from_str("\"2x4\"").unwrap();
+        let grid: DashboardLayout = serde_json::from_str(
+            r#"{"rows": 1, "columns": 1,
+                "cells": [{"row": 1, "column": 1,
+                           "tiles": [{"tile_id": "t", "element": "tile-value"}]}]}"#,
+        ).unwrap();
+
+        assert_eq!(named, DashboardLayout::Named("2x4".to_string()));
+        assert_eq!(
+            grid,
+            DashboardLayout::Grid(Layout {
+                rows: 1,
+                columns: 1,
+                cells: vec![cell(1, 1, "t")],
+            })
+        );
+    }
+}
@@ -5,18 +5,24 @@ extern crate params;
 extern crate dotenv;
 extern crate handlebars;
 extern crate handlebars_iron as hbs;
+extern crate hmac;
 extern crate hyper;
 extern crate iron;
 extern crate natord;
 #[cfg(test)]
 extern crate iron_test;
+#[macro_use]
+extern crate lazy_static;
 extern crate mount;
 extern crate redis;
 extern crate router;
+extern crate rusqlite;
 extern crate serde;
+#[cfg_attr(test, macro_use)]
 extern crate serde_json;
 #[macro_use]
 extern crate serde_derive;
+extern crate sha2;
 #[macro_use]
 extern crate slog;
 #[macro_use]
@@ -32,16 +38,27 @@ use slog::Drain;
 
 
 mod db;
+mod error;
+mod fanout;
 mod gui_api;
+mod layouts;
+mod metrics;
+mod rate_limit;
 mod rest_api;
 mod routing;
+mod schema;
+mod signing;
+mod storage;
 mod templating;
+mod tile_types;
+mod tokens;
 mod utils;
 mod views;
 #[cfg(test)]
 mod test_utils;
 mod websocket;
 use hyper::server::Listening;
+use metrics::run_rollup_job;
 use routing::get_mount;
 use utils::{from_config, load_config};
 use websocket::run_ws_listener;
@@ -78,6 +95,18 @@ fn main() {
     let config_path = matches.value_of("config-path");
     load_config(config_path);
 
+    db::Db::new()
+        .and_then(|db| db.migrate_storage())
+        .expect("migrating storage FAILED");
+    let migrated = db::Db::new()
+        .and_then(|db| db.migrate_api_tokens())
+        .expect("hashing plain API tokens FAILED");
+    if migrated > 0 {
+        info!("Hashed plain API tokens of {} dashboards", migrated);
+    }
+
+    run_rollup_job();
+
     // http listener
     let _listener = run_http_listener(from_config("DASHBOARD_IP_PORT").as_str());
 
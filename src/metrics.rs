@@ -0,0 +1,268 @@
+//! Numeric values picked out of tiles, their aggregation and rollups
+//!
+//! Every point keeps count/sum/min/max/last, so a point may stand for many
+//! samples (after rollup) and aggregations over it stay exact.
+
+use db;
+use serde_json;
+use std::error::Error;
+use std::str::FromStr;
+use std::thread;
+use std::time::Duration;
+use utils::{from_config, timestamp_ms};
+
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MetricPoint {
+    /// milliseconds since unix epoch, start of bucket for rolled up points
+    pub timestamp: i64,
+    pub count: u64,
+    pub sum: f64,
+    pub min: f64,
+    pub max: f64,
+    pub last: f64,
+}
+
+impl MetricPoint {
+    pub fn new(timestamp: i64, value: f64) -> MetricPoint {
+        MetricPoint {
+            timestamp: timestamp,
+            count: 1,
+            sum: value,
+            min: value,
+            max: value,
+            last: value,
+        }
+    }
+
+    /// Folds `later` point into this one
+    fn merge(&mut self, later: &MetricPoint) {
+        self.count += later.count;
+        self.sum += later.sum;
+        self.min = self.min.min(later.min);
+        self.max = self.max.max(later.max);
+        self.last = later.last;
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Aggregation {
+    Min,
+    Max,
+    Avg,
+    Sum,
+    Last,
+    Count,
+}
+
+impl Aggregation {
+    fn apply(&self, point: &MetricPoint) -> f64 {
+        match *self {
+            Aggregation::Min => point.min,
+            Aggregation::Max => point.max,
+            Aggregation::Avg => point.sum / point.count as f64,
+            Aggregation::Sum => point.sum,
+            Aggregation::Last => point.last,
+            Aggregation::Count => point.count as f64,
+        }
+    }
+}
+
+impl FromStr for Aggregation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Aggregation, String> {
+        match s {
+            "min" => Ok(Aggregation::Min),
+            "max" => Ok(Aggregation::Max),
+            "avg" => Ok(Aggregation::Avg),
+            "sum" => Ok(Aggregation::Sum),
+            "last" => Ok(Aggregation::Last),
+            "count" => Ok(Aggregation::Count),
+            _ => Err(format!(
+                "Unknown aggregation: {} (use min, max, avg, sum, last or count)",
+                s
+            )),
+        }
+    }
+}
+
+
+/// Single value of downsampled metric
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AggregatedValue {
+    /// start of bucket, milliseconds since unix epoch
+    pub timestamp: i64,
+    pub value: f64,
+}
+
+
+/// Returns number found at `pointer` (RFC 6901) in `tile_data`
+pub fn extract_value(tile_data: &serde_json::Value, pointer: &str) -> Option<f64> {
+    tile_data.pointer(pointer).and_then(|v| v.as_f64())
+}
+
+/// Merges `points` (oldest first) into buckets `step` milliseconds wide
+fn bucketize(points: &[MetricPoint], step: i64) -> Vec<MetricPoint> {
+    let mut buckets: Vec<MetricPoint> = Vec::new();
+    for point in points {
+        let bucket_start = point.timestamp - point.timestamp.rem_euclid(step);
+        let merged = match buckets.last_mut() {
+            Some(bucket) if bucket.timestamp == bucket_start => {
+                bucket.merge(point);
+                true
+            }
+            _ => false,
+        };
+        if !merged {
+            let mut bucket = point.clone();
+            bucket.timestamp = bucket_start;
+            buckets.push(bucket);
+        }
+    }
+    buckets
+}
+
+/// Returns `points` (oldest first) aggregated with `agg` in buckets `step`
+/// milliseconds wide
+pub fn downsample(points: &[MetricPoint], step: i64, agg: Aggregation) -> Vec<AggregatedValue> {
+    bucketize(points, step)
+        .iter()
+        .map(|bucket| {
+            AggregatedValue {
+                timestamp: bucket.timestamp,
+                value: agg.apply(bucket),
+            }
+        })
+        .collect()
+}
+
+
+/// How old metric points are compacted, read from config
+struct RollupConfig {
+    /// points older than it (ms) are compacted
+    age: i64,
+    /// width of bucket (ms) which old points are compacted into
+    step: i64,
+    interval: Duration,
+}
+
+impl RollupConfig {
+    fn from_config() -> RollupConfig {
+        let secs = |key: &str| -> u64 {
+            from_config(key)
+                .parse()
+                .expect(&format!("{} is not a number", key))
+        };
+        let step = secs("DASHBOARD_METRICS_ROLLUP_STEP_SECS");
+        if step == 0 {
+            panic!("DASHBOARD_METRICS_ROLLUP_STEP_SECS should be greater than 0");
+        }
+        RollupConfig {
+            age: secs("DASHBOARD_METRICS_ROLLUP_AGE_SECS") as i64 * 1000,
+            step: step as i64 * 1000,
+            interval: Duration::from_secs(secs("DASHBOARD_METRICS_ROLLUP_INTERVAL_SECS")),
+        }
+    }
+}
+
+/// Compacts points of every metric which are older than `config.age`
+fn rollup_all(config: &RollupConfig) -> Result<(), Box<Error>> {
+    let db = db::Db::new()?;
+    let before = timestamp_ms() - config.age;
+    for (dashboard_name, tile_id) in db.get_metric_series()? {
+        let points = db.get_metric_points(&dashboard_name, &tile_id, i64::min_value(), before - 1)?;
+        let rolled = bucketize(&points, config.step);
+        if rolled.len() < points.len() {
+            db.compact_metric_points(&dashboard_name, &tile_id, before, &rolled)?;
+        }
+    }
+    Ok(())
+}
+
+/// Spawns thread which periodically compacts old metric points
+pub fn run_rollup_job() {
+    let config = RollupConfig::from_config();
+    println!(
+        "Compacting metrics older than {}s every {}s",
+        config.age / 1000,
+        config.interval.as_secs()
+    );
+    thread::spawn(move || loop {
+        thread::sleep(config.interval);
+        if let Err(e) = rollup_all(&config) {
+            error!("Metrics rollup FAILED ({})", e);
+        }
+    });
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn points() -> Vec<MetricPoint> {
+        vec![
+            MetricPoint::new(0, 1.0),
+            MetricPoint::new(5, 3.0),
+            MetricPoint::new(10, 10.0),
+        ]
+    }
+
+    #[test]
+    fn extract_value_works_when_pointer_points_number() {
+        let data = json!({"tile-data": {"value": 7}});
+
+        assert_eq!(extract_value(&data, "/tile-data/value"), Some(7.0));
+        assert_eq!(extract_value(&data, "/tile-data"), None);
+        assert_eq!(extract_value(&data, "/missing"), None);
+    }
+
+    #[test]
+    fn downsample_aggregates_each_bucket() {
+        let avg = downsample(&points(), 10, Aggregation::Avg);
+        let count = downsample(&points(), 10, Aggregation::Count);
+
+        assert_eq!(
+            avg,
+            vec![
+                AggregatedValue {
+                    timestamp: 0,
+                    value: 2.0,
+                },
+                AggregatedValue {
+                    timestamp: 10,
+                    value: 10.0,
+                },
+            ]
+        );
+        assert_eq!(count[0].value, 2.0);
+    }
+
+    #[test]
+    fn downsample_of_rolled_up_points_equals_downsample_of_raw_ones() {
+        let rolled = bucketize(&points(), 5);
+
+        for agg in &[
+            Aggregation::Min,
+            Aggregation::Max,
+            Aggregation::Avg,
+            Aggregation::Sum,
+            Aggregation::Last,
+            Aggregation::Count,
+        ]
+        {
+            assert_eq!(
+                downsample(&rolled, 20, *agg),
+                downsample(&points(), 20, *agg)
+            );
+        }
+    }
+
+    #[test]
+    fn aggregation_from_str_fails_when_unknown() {
+        assert_eq!("max".parse(), Ok(Aggregation::Max));
+        assert!("median".parse::<Aggregation>().is_err());
+    }
+}
@@ -0,0 +1,324 @@
+//! Rate limiting of REST API
+//!
+//! Every request to `/api` takes one token from bucket of its client IP.
+//! Request carrying active API token of dashboard it goes to takes one from
+//! buckets of the dashboard and of the token as well, so requests with
+//! made-up tokens can't use up limits of dashboard. Buckets hold at most
+//! `DASHBOARD_RATE_LIMIT_{IP,DASHBOARD,TOKEN}_BURST` tokens and get
+//! `..._PER_SEC` back every second (0 turns the limit off). Request which
+//! finds any of its buckets empty is rejected with 429 and `Retry-After`,
+//! such hits are counted per kind of limit. Token found for the limits is
+//! kept in request (see `ResolvedToken`), so handlers don't look it up again.
+
+use db;
+use error::DashboardError;
+use iron;
+use iron::headers::Authorization;
+use iron::prelude::*;
+use iron::typemap;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use tokens::ApiToken;
+use utils::{from_config, timestamp_ms};
+
+
+/// Buckets are dropped once refilled when there are more of them
+const BUCKETS_MAX: usize = 10000;
+
+
+lazy_static! {
+    static ref RATE_LIMITER: Arc<RateLimiter> = Arc::new(RateLimiter::from_config());
+}
+
+
+/// What requests are counted together
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LimitKind {
+    Ip,
+    Dashboard,
+    Token,
+}
+
+impl LimitKind {
+    fn config_prefix(&self) -> &'static str {
+        match *self {
+            LimitKind::Ip => "DASHBOARD_RATE_LIMIT_IP",
+            LimitKind::Dashboard => "DASHBOARD_RATE_LIMIT_DASHBOARD",
+            LimitKind::Token => "DASHBOARD_RATE_LIMIT_TOKEN",
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Limit {
+    /// requests which can be made at once
+    pub burst: f64,
+    pub per_sec: f64,
+}
+
+impl Limit {
+    /// Reads limit of `kind` from config, `None` when it's turned off
+    fn from_config(kind: LimitKind) -> Option<Limit> {
+        let prefix = kind.config_prefix();
+        let per_sec: f64 = from_config(&format!("{}_PER_SEC", prefix))
+            .parse()
+            .unwrap_or_else(|_| panic!("{}_PER_SEC is not a number", prefix));
+        let burst: f64 = from_config(&format!("{}_BURST", prefix))
+            .parse()
+            .unwrap_or_else(|_| panic!("{}_BURST is not a number", prefix));
+        if per_sec > 0.0 {
+            Some(Limit {
+                burst: burst.max(1.0),
+                per_sec: per_sec,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    /// milliseconds since unix epoch
+    updated_at: i64,
+}
+
+impl Bucket {
+    fn full(limit: &Limit, now: i64) -> Bucket {
+        Bucket {
+            tokens: limit.burst,
+            updated_at: now,
+        }
+    }
+
+    /// Adds tokens which came back since last update
+    fn refill(&mut self, limit: &Limit, now: i64) {
+        let elapsed_secs = (now - self.updated_at).max(0) as f64 / 1000.0;
+        self.tokens = (self.tokens + elapsed_secs * limit.per_sec).min(limit.burst);
+        self.updated_at = now;
+    }
+
+    /// Returns milliseconds until bucket has a token
+    fn wait_ms(&self, limit: &Limit) -> i64 {
+        ((1.0 - self.tokens) / limit.per_sec * 1000.0).ceil().max(1.0) as i64
+    }
+}
+
+
+pub struct RateLimiter {
+    limits: HashMap<LimitKind, Limit>,
+    buckets: Mutex<HashMap<(LimitKind, String), Bucket>>,
+    hits: Mutex<BTreeMap<LimitKind, u64>>,
+}
+
+impl RateLimiter {
+    pub fn new(limits: HashMap<LimitKind, Limit>) -> RateLimiter {
+        RateLimiter {
+            limits: limits,
+            buckets: Mutex::new(HashMap::new()),
+            hits: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Returns limiter with limits from config
+    pub fn from_config() -> RateLimiter {
+        let mut limits = HashMap::new();
+        for kind in &[LimitKind::Ip, LimitKind::Dashboard, LimitKind::Token] {
+            if let Some(limit) = Limit::from_config(*kind) {
+                limits.insert(*kind, limit);
+            }
+        }
+        RateLimiter::new(limits)
+    }
+
+    /// Takes token from bucket of every key at `now` when all of them have
+    /// one, otherwise returns limit hit and milliseconds to wait
+    pub fn check(&self, keys: &[(LimitKind, String)], now: i64) -> Result<(), (LimitKind, i64)> {
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        for &(kind, ref key) in keys {
+            let limit = match self.limits.get(&kind) {
+                None => continue,
+                Some(v) => v,
+            };
+            let bucket = buckets
+                .entry((kind, key.clone()))
+                .or_insert_with(|| Bucket::full(limit, now));
+            bucket.refill(limit, now);
+            if bucket.tokens < 1.0 {
+                let wait_ms = bucket.wait_ms(limit);
+                *self.hits
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .entry(kind)
+                    .or_insert(0) += 1;
+                return Err((kind, wait_ms));
+            }
+        }
+        for &(kind, ref key) in keys {
+            if let Some(bucket) = buckets.get_mut(&(kind, key.clone())) {
+                bucket.tokens -= 1.0;
+            }
+        }
+        if buckets.len() > BUCKETS_MAX {
+            let limits = &self.limits;
+            buckets.retain(|&(kind, _), bucket| {
+                let mut bucket = *bucket;
+                bucket.refill(&limits[&kind], now);
+                bucket.tokens < limits[&kind].burst
+            });
+        }
+        Ok(())
+    }
+
+    /// Returns count of rejected requests by kind of limit they hit
+    pub fn hits(&self) -> BTreeMap<LimitKind, u64> {
+        self.hits.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+}
+
+
+/// Returns count of requests rejected by shared limiter by kind of limit
+pub fn hits() -> BTreeMap<LimitKind, u64> {
+    RATE_LIMITER.hits()
+}
+
+/// Returns limiter with limits from config shared by whole process
+pub fn shared_limiter() -> Arc<RateLimiter> {
+    RATE_LIMITER.clone()
+}
+
+/// Active token of request with name of dashboard it belongs to and the
+/// dashboard itself, put to request extensions by `RateLimit`
+pub struct ResolvedToken;
+
+impl typemap::Key for ResolvedToken {
+    type Value = (String, ApiToken, db::Dashboard);
+}
+
+/// Returns active token of dashboard at `dashboard_name` which `secret`
+/// belongs to with the dashboard, storage errors are left to handler of
+/// request
+fn find_token(dashboard_name: &str, secret: &str) -> Option<(ApiToken, db::Dashboard)> {
+    let dashboard = match db::Db::new().and_then(|db| db.get_dashboard(dashboard_name)) {
+        Ok(Some(v)) => v,
+        _ => return None,
+    };
+    let api_token = match dashboard.find_api_token(secret, timestamp_ms()) {
+        None => return None,
+        Some(v) => v.clone(),
+    };
+    Some((api_token, dashboard))
+}
+
+/// Returns buckets which request takes tokens from, token of the request is
+/// resolved only once and kept as `ResolvedToken`
+fn request_keys(req: &mut Request) -> Vec<(LimitKind, String)> {
+    let mut keys = vec![(LimitKind::Ip, req.remote_addr.ip().to_string())];
+    let dashboard_name = match req.url.path() {
+        ref path if path.len() >= 2 && path[0] == "dashboard" => path[1].to_string(),
+        _ => return keys,
+    };
+    let resolved = match req.headers.get::<Authorization<String>>() {
+        None => return keys,
+        Some(v) => find_token(&dashboard_name, &v.0),
+    };
+    if let Some((api_token, dashboard)) = resolved {
+        keys.push((LimitKind::Dashboard, dashboard_name.clone()));
+        keys.push((
+            LimitKind::Token,
+            format!("{}/{}", dashboard_name, api_token.name),
+        ));
+        req.extensions
+            .insert::<ResolvedToken>((dashboard_name, api_token, dashboard));
+    }
+    keys
+}
+
+
+/// Rejects requests over limits of its limiter
+pub struct RateLimit {
+    limiter: Arc<RateLimiter>,
+}
+
+impl RateLimit {
+    pub fn new(limiter: Arc<RateLimiter>) -> RateLimit {
+        RateLimit { limiter: limiter }
+    }
+}
+
+impl iron::BeforeMiddleware for RateLimit {
+    fn before(&self, request: &mut Request) -> IronResult<()> {
+        let keys = request_keys(request);
+        let (kind, wait_ms) = match self.limiter.check(&keys, timestamp_ms()) {
+            Ok(()) => return Ok(()),
+            Err(v) => v,
+        };
+        warn!(
+            "Rate limit ({:?}) hit by {} {}",
+            kind,
+            request.remote_addr,
+            request.url
+        );
+        let retry_after_secs = (wait_ms + 999) / 1000;
+        let mut error = DashboardError::TooManyRequests(format!(
+            "Too many requests, retry in {} seconds",
+            retry_after_secs
+        )).into_iron_error();
+        error.response.headers.set_raw(
+            "Retry-After",
+            vec![retry_after_secs.to_string().into_bytes()],
+        );
+        Err(error)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter(kind: LimitKind, burst: f64, per_sec: f64) -> RateLimiter {
+        let mut limits = HashMap::new();
+        limits.insert(
+            kind,
+            Limit {
+                burst: burst,
+                per_sec: per_sec,
+            },
+        );
+        RateLimiter::new(limits)
+    }
+
+    #[test]
+    fn check_fails_when_burst_is_used_until_bucket_refills() {
+        let limiter = limiter(LimitKind::Token, 2.0, 4.0);
+        let keys = vec![(LimitKind::Token, "ci".to_string())];
+
+        assert_eq!(limiter.check(&keys, 0), Ok(()));
+        assert_eq!(limiter.check(&keys, 0), Ok(()));
+        assert_eq!(limiter.check(&keys, 0), Err((LimitKind::Token, 250)));
+        assert_eq!(limiter.check(&keys, 250), Ok(()));
+        assert_eq!(limiter.hits().get(&LimitKind::Token), Some(&1));
+    }
+
+    #[test]
+    fn check_counts_keys_apart_and_skips_kinds_without_limit() {
+        let limiter = limiter(LimitKind::Dashboard, 1.0, 1.0);
+        let first = vec![
+            (LimitKind::Dashboard, "first".to_string()),
+            (LimitKind::Ip, "127.0.0.1".to_string()),
+        ];
+        let second = vec![
+            (LimitKind::Dashboard, "second".to_string()),
+            (LimitKind::Ip, "127.0.0.1".to_string()),
+        ];
+
+        assert_eq!(limiter.check(&first, 0), Ok(()));
+        assert_eq!(limiter.check(&second, 0), Ok(()));
+        assert_eq!(limiter.check(&first, 0), Err((LimitKind::Dashboard, 1000)));
+    }
+}
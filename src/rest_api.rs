@@ -1,15 +1,32 @@
 use db;
-use hyper::header::Authorization;
+use error::DashboardError;
+use hyper::header::{Authorization, ETag, EntityTag, Headers, IfMatch, IfNoneMatch};
+use iron::headers::ContentType;
 use iron;
 use iron::prelude::*;
-use iron::status;
 use iron::status::Status;
+use metrics;
+use metrics::Aggregation;
+use mount::OriginalUrl;
+use params;
+use rate_limit::ResolvedToken;
 use router::Router;
+use schema::SchemaViolation;
 use serde_json;
-use std::error::Error;
-use std::fmt;
+use signing;
+use signing::SignedRequest;
+use std::collections::BTreeMap;
 use std::io::Read;
-use utils::json_response;
+use std::str::FromStr;
+use tokens::{Access, ApiToken, TokenScope};
+use utils::{get_page_items, json_response, timestamp_ms};
+
+
+const HISTORY_LIMIT_DEFAULT: usize = 100;
+const HISTORY_LIMIT_MAX: usize = 1000;
+const METRIC_STEP_DEFAULT: i64 = 60 * 1000;
+const TILES_PER_PAGE_DEFAULT: u64 = 50;
+const TILES_PER_PAGE_MAX: u64 = 500;
 
 
 pub fn rest_router() -> Router {
@@ -19,40 +36,84 @@ pub fn rest_router() -> Router {
         tile_get,
         "tile_get",
     );
+    router.get(
+        "/dashboard/:dashboard_name/tiles",
+        tiles_get,
+        "tiles_get",
+    );
+    router.get(
+        "/dashboard/:dashboard_name/schemas",
+        schemas_get,
+        "schemas_get",
+    );
+    router.get(
+        "/dashboard/:dashboard_name/tile/:tile_id/metric",
+        tile_metric_get,
+        "tile_metric_get",
+    );
     router.post(
         "/dashboard/:dashboard_name/tile/:tile_id",
         tile_post,
         "tile_post",
     );
+    router.patch(
+        "/dashboard/:dashboard_name/tile/:tile_id",
+        tile_patch,
+        "tile_patch",
+    );
+    router.post(
+        "/dashboard/:dashboard_name/tiles",
+        tiles_post,
+        "tiles_post",
+    );
+    router.delete(
+        "/dashboard/:dashboard_name/tile/:tile_id",
+        tile_delete,
+        "tile_delete",
+    );
+    router.delete("/dashboard/:dashboard_name", dashboard_delete, "dashboard_delete");
+    router.get(
+        "/dashboard/:dashboard_name/tokens",
+        tokens_get,
+        "tokens_get",
+    );
+    router.post(
+        "/dashboard/:dashboard_name/token/:token_name",
+        token_post,
+        "token_post",
+    );
+    router.delete(
+        "/dashboard/:dashboard_name/token/:token_name",
+        token_delete,
+        "token_delete",
+    );
+    router.post(
+        "/dashboard/:dashboard_name/signing-secret",
+        signing_secret_post,
+        "signing_secret_post",
+    );
+    router.delete(
+        "/dashboard/:dashboard_name/signing-secret",
+        signing_secret_delete,
+        "signing_secret_delete",
+    );
+    router.get(
+        "/dashboard/:dashboard_name/tile/:tile_id/history",
+        tile_history_get,
+        "tile_history_get",
+    );
     router
 }
 
 pub struct AuthToken;
 
-#[derive(Debug)]
-struct StringError(String);
-
-impl fmt::Display for StringError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        fmt::Debug::fmt(self, f)
-    }
-}
-
-impl Error for StringError {
-    fn description(&self) -> &str {
-        &*self.0
-    }
-}
-
 
 /// Returns token stored in authorization header
-fn get_request_token(request: &Request) -> Result<String, IronError> {
+fn get_request_token(request: &Request) -> Result<String, DashboardError> {
     let token = request
         .headers
         .get::<Authorization<String>>()
-        .ok_or_else(|| {
-            IronError::new(StringError("Token missing".to_string()), status::Forbidden)
-        })?
+        .ok_or_else(|| DashboardError::AuthFailed("Token missing".to_string()))?
         .to_owned()
         .0;
 
@@ -63,17 +124,68 @@ fn get_request_token(request: &Request) -> Result<String, IronError> {
 impl iron::BeforeMiddleware for AuthToken {
     fn before(&self, request: &mut Request) -> IronResult<()> {
         match request.headers.get::<Authorization<String>>() {
-            None => {
-                Err(IronError::new(
-                    StringError("Token missing".to_string()),
-                    status::Forbidden,
-                ))
-            }
+            None => Err(
+                DashboardError::AuthFailed("Token missing".to_string()).into_iron_error(),
+            ),
             Some(_) => Ok(()),
         }
     }
 }
 
+/// Returns entity tag of tile with `version` saved at `updated_at`, version
+/// starts over when tile is deleted, so time of update tells such tiles apart
+fn tile_etag(version: u64, updated_at: Option<i64>) -> EntityTag {
+    match updated_at {
+        None => EntityTag::strong(version.to_string()),
+        Some(updated_at) => EntityTag::strong(format!("{}-{}", version, updated_at)),
+    }
+}
+
+fn stored_tile_etag(tile: &db::StoredTile) -> EntityTag {
+    tile_etag(tile.version, tile.updated_at)
+}
+
+/// Checks `If-Match` header against `tile` (`None` for missing tile)
+fn if_match_holds(headers: &Headers, tile: Option<&db::StoredTile>) -> bool {
+    match headers.get::<IfMatch>() {
+        None => true,
+        Some(&IfMatch::Any) => tile.is_some(),
+        Some(&IfMatch::Items(ref tags)) => {
+            tile.map_or(false, |t| {
+                tags.iter().any(|tag| tag.strong_eq(&stored_tile_etag(t)))
+            })
+        }
+    }
+}
+
+/// Checks `If-None-Match` header against `tile` (`None` for missing tile)
+fn if_none_match_holds(headers: &Headers, tile: Option<&db::StoredTile>) -> bool {
+    match headers.get::<IfNoneMatch>() {
+        None => true,
+        Some(&IfNoneMatch::Any) => tile.is_none(),
+        Some(&IfNoneMatch::Items(ref tags)) => {
+            tile.map_or(true, |t| {
+                !tags.iter().any(|tag| tag.weak_eq(&stored_tile_etag(t)))
+            })
+        }
+    }
+}
+
+/// Works like `json_response`, but adds `etag` if it's given
+fn json_response_with_etag(
+    status: Status,
+    payload: &str,
+    etag: Option<EntityTag>,
+) -> IronResult<Response> {
+    let mut response = Response::with((ContentType::json().0, status, payload));
+    if let Some(v) = etag {
+        response.headers.set(ETag(v));
+    }
+    Ok(response)
+}
+
+/// Returns tile with its version in `ETag`, responds 304 when `If-None-Match`
+/// has this version already
 pub fn tile_get(req: &mut Request) -> IronResult<Response> {
     let (dashboard_name, tile_id) = {
         let router = req.extensions.get::<Router>().unwrap();
@@ -84,17 +196,261 @@ pub fn tile_get(req: &mut Request) -> IronResult<Response> {
         )
     };
     let db = match db::Db::new() {
-        Err(e) => return json_response(Status::InternalServerError, &e.to_string()),
+        Err(e) => return e.to_response(),
         Ok(v) => v,
     };
-    match db.get_tile(dashboard_name, tile_id) {
-        Err(e) => json_response(Status::InternalServerError, &e.to_string()),
-        Ok(None) => json_response(Status::NotFound, ""),
-        Ok(Some(val)) => json_response(Status::Ok, val.as_str()),
+    if let Err(e) = get_readable_dashboard(req, &db, dashboard_name, Access::ReadTile(tile_id)) {
+        return e.to_response();
     }
+    match db.get_stored_tile(dashboard_name, tile_id) {
+        Err(e) => e.to_response(),
+        Ok(None) => DashboardError::NotFound("Tile doesn't exist".to_string()).to_response(),
+        Ok(Some(ref tile)) if !if_none_match_holds(&req.headers, Some(tile)) => {
+            json_response_with_etag(Status::NotModified, "", Some(stored_tile_etag(tile)))
+        }
+        Ok(Some(tile)) => {
+            json_response_with_etag(Status::Ok, &tile.data, Some(stored_tile_etag(&tile)))
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct TileListItem {
+    tile_id: String,
+    /// milliseconds since unix epoch
+    updated_at: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data: Option<serde_json::Value>,
 }
 
-fn _tile_post(req: &mut Request) -> Result<(Status, String), Box<Error>> {
+#[derive(Serialize)]
+struct TileList {
+    tiles: Vec<TileListItem>,
+    page: u64,
+    page_max: usize,
+}
+
+fn _tiles_get(req: &mut Request) -> Result<(Status, String), DashboardError> {
+    let dashboard_name = req.extensions
+        .get::<Router>()
+        .unwrap()
+        .find("dashboard_name")
+        .unwrap()
+        .to_string();
+    let (page, per_page, with_data) = {
+        let params = req.get_ref::<params::Params>()
+            .map_err(|e| DashboardError::InvalidPayload(e.to_string()))?;
+        let parsed = query_number(params, "page").and_then(|page| {
+            let per_page = query_number(params, "per_page")?;
+            let with_data = match params.find(&["data"]) {
+                None => false,
+                Some(&params::Value::String(ref v)) if v == "true" => true,
+                Some(&params::Value::String(ref v)) if v == "false" => false,
+                Some(_) => return Err("Query param data should be true or false".to_string()),
+            };
+            Ok((page, per_page, with_data))
+        });
+        match parsed {
+            Err(e) => return Err(DashboardError::InvalidPayload(e)),
+            Ok((page, per_page, with_data)) => (
+                page.unwrap_or(1),
+                per_page.unwrap_or(TILES_PER_PAGE_DEFAULT),
+                with_data,
+            ),
+        }
+    };
+    if per_page < 1 {
+        return Err(DashboardError::InvalidPayload(
+            "Query param per_page should be greater than 0".to_string(),
+        ));
+    }
+    let db = db::Db::new()?;
+    get_readable_dashboard(req, &db, &dashboard_name, Access::ShowDashboard)?;
+    let tiles = db.get_tiles(&dashboard_name)?;
+    let (tiles, page_max) =
+        get_page_items(tiles.into_iter(), page, per_page.min(TILES_PER_PAGE_MAX))
+            .map_err(DashboardError::InvalidPayload)?;
+    let mut items = Vec::with_capacity(tiles.len());
+    for tile in tiles {
+        let data = if with_data {
+            Some(serde_json::from_str(&tile.data)?)
+        } else {
+            None
+        };
+        items.push(TileListItem {
+            tile_id: tile.tile_id,
+            updated_at: tile.updated_at,
+            data: data,
+        });
+    }
+    let list = TileList {
+        tiles: items,
+        page: page,
+        page_max: page_max,
+    };
+    Ok((Status::Ok, serde_json::to_string(&list)?))
+}
+
+/// Returns page of tiles of dashboard (sorted by tile id) with time of their
+/// last update, `data=true` query param adds tile data as well
+pub fn tiles_get(req: &mut Request) -> IronResult<Response> {
+    let (status, msg) = match _tiles_get(req) {
+        Err(e) => return e.to_response(),
+        Ok(v) => v,
+    };
+    json_response(status, &msg)
+}
+
+#[derive(Serialize)]
+struct TileSchemas<'a> {
+    schemas: &'a BTreeMap<String, serde_json::Value>,
+    tile_types: &'a BTreeMap<String, String>,
+}
+
+fn _schemas_get(req: &mut Request) -> Result<(Status, String), DashboardError> {
+    let dashboard_name = req.extensions
+        .get::<Router>()
+        .unwrap()
+        .find("dashboard_name")
+        .unwrap();
+    let db = db::Db::new()?;
+    let dashboard = get_readable_dashboard(req, &db, dashboard_name, Access::ReadSchemas)?;
+    let schemas = TileSchemas {
+        schemas: &dashboard.schemas,
+        tile_types: &dashboard.tile_types,
+    };
+    Ok((Status::Ok, serde_json::to_string(&schemas)?))
+}
+
+/// Returns JSON Schemas registered for tile ids and tile types together with
+/// types of tiles, so feeders can validate tiles before sending them
+pub fn schemas_get(req: &mut Request) -> IronResult<Response> {
+    let (status, msg) = match _schemas_get(req) {
+        Err(e) => return e.to_response(),
+        Ok(v) => v,
+    };
+    json_response(status, &msg)
+}
+
+/// Returns `Dashboard` at `dashboard_name` with its active token which `req`
+/// has (the one resolved by rate limiting when there's one), use of the
+/// token is noted
+fn get_token_and_dashboard(
+    req: &Request,
+    db: &db::Db,
+    dashboard_name: &str,
+) -> Result<(ApiToken, db::Dashboard), DashboardError> {
+    if let Some(&(ref name, ref api_token, ref dashboard)) =
+        req.extensions.get::<ResolvedToken>()
+    {
+        if name == dashboard_name {
+            db.touch_api_token(dashboard_name, &api_token.name)?;
+            return Ok((api_token.clone(), dashboard.clone()));
+        }
+    }
+    let request_token = get_request_token(req)?;
+    let dashboard = match db.get_dashboard(dashboard_name)? {
+        None => return Err(DashboardError::NotFound("Dashboard doesn't exist".to_string())),
+        Some(v) => v,
+    };
+    let api_token = match dashboard.find_api_token(&request_token, timestamp_ms()) {
+        None => return Err(DashboardError::AuthFailed("Tokens unmatched".to_string())),
+        Some(v) => v.clone(),
+    };
+    db.touch_api_token(dashboard_name, &api_token.name)?;
+    Ok((api_token, dashboard))
+}
+
+/// Returns `Dashboard` at `dashboard_name` when `req` has its active token
+/// which allows `access`
+pub fn get_authorized_dashboard(
+    req: &Request,
+    db: &db::Db,
+    dashboard_name: &str,
+    access: Access,
+) -> Result<db::Dashboard, DashboardError> {
+    let (api_token, dashboard) = get_token_and_dashboard(req, db, dashboard_name)?;
+    if !api_token.scope.allows(&access) {
+        return Err(access.denied());
+    }
+    Ok(dashboard)
+}
+
+/// Returns `Dashboard` at `dashboard_name` which `req` can read (see
+/// `Db::get_readable_dashboard`)
+fn get_readable_dashboard(
+    req: &Request,
+    db: &db::Db,
+    dashboard_name: &str,
+    access: Access,
+) -> Result<db::Dashboard, DashboardError> {
+    let request_token = get_request_token(req)?;
+    db.get_readable_dashboard(dashboard_name, Some(&request_token), access)
+}
+
+/// Returns first value of header called `name`
+fn get_raw_header(req: &Request, name: &str) -> Option<String> {
+    req.headers
+        .get_raw(name)
+        .and_then(|values| values.first())
+        .and_then(|value| String::from_utf8(value.clone()).ok())
+}
+
+/// Checks signature of write when `dashboard` requires it (see
+/// `signing`), nonce of the request can't be used again
+pub fn check_signature(
+    req: &Request,
+    db: &db::Db,
+    dashboard: &db::Dashboard,
+    body: &str,
+) -> Result<(), DashboardError> {
+    let secret = match dashboard.signing_secret {
+        None => return Ok(()),
+        Some(ref v) => v,
+    };
+    let headers = (
+        get_raw_header(req, signing::TIMESTAMP_HEADER),
+        get_raw_header(req, signing::NONCE_HEADER),
+        get_raw_header(req, signing::SIGNATURE_HEADER),
+    );
+    let (timestamp, nonce, signature) = match headers {
+        (Some(timestamp), Some(nonce), Some(signature)) => (timestamp, nonce, signature),
+        _ => return Err(DashboardError::AuthFailed("Request has to be signed".to_string())),
+    };
+    let timestamp = timestamp.trim().parse().map_err(|_| {
+        DashboardError::AuthFailed(format!("{} should be a number", signing::TIMESTAMP_HEADER))
+    })?;
+    // path as sent by feeder, before mount stripped its prefix
+    let url = req.extensions
+        .get::<OriginalUrl>()
+        .unwrap_or(&req.url)
+        .as_ref();
+    let path = match url.query() {
+        None => url.path().to_string(),
+        Some(query) => format!("{}?{}", url.path(), query),
+    };
+    let method = req.method.to_string();
+    let signed = SignedRequest {
+        method: &method,
+        path: &path,
+        timestamp: timestamp,
+        nonce: &nonce,
+        body: body,
+    };
+    if !signed.verify(secret, signature.trim()) {
+        return Err(DashboardError::AuthFailed("Signature mismatch".to_string()));
+    }
+    let window = signing::window_from_config();
+    if !signed.is_fresh(timestamp_ms(), window) {
+        return Err(DashboardError::AuthFailed("Request timestamp is stale".to_string()));
+    }
+    if !db.add_nonce(&dashboard.name, &nonce, timestamp + window)? {
+        return Err(DashboardError::AuthFailed("Nonce was used already".to_string()));
+    }
+    Ok(())
+}
+
+fn _tile_post(req: &mut Request) -> Result<(Status, String, Option<EntityTag>), DashboardError> {
     let (dashboard_name, tile_id) = {
         let router = req.extensions.get::<Router>().unwrap();
         (
@@ -102,50 +458,520 @@ fn _tile_post(req: &mut Request) -> Result<(Status, String), Box<Error>> {
             router.find("tile_id").unwrap(),
         )
     };
-    let request_token = get_request_token(req)?;
     let db = db::Db::new()?;
-    let dashboard = match db.get_dashboard(dashboard_name)? {
-        None => return Ok((Status::NotFound, "Dashboard doesn't exist".to_string())),
-        Some(v) => v,
+    let dashboard =
+        get_authorized_dashboard(req, &db, dashboard_name, Access::WriteTile(tile_id))?;
+
+    let mut json = String::new();
+    req.body.read_to_string(&mut json)?;
+    check_signature(req, &db, &dashboard, &json)?;
+
+    let written = db.upsert_tile_if(&dashboard, tile_id, &json, |tile| {
+        if_match_holds(&req.headers, tile) && if_none_match_holds(&req.headers, tile)
+    })?;
+    let (tile_data, etag) = match written {
+        db::TileWrite::Saved {
+            data,
+            version,
+            updated_at,
+        } => (data, tile_etag(version, Some(updated_at))),
+        _ => {
+            return Err(DashboardError::PreconditionFailed(
+                "Tile version doesn't match".to_string(),
+            ))
+        }
+    };
+    db.record_metric(&dashboard, tile_id, &tile_data)?;
+    Ok((Status::Created, "".to_string(), Some(etag)))
+}
+
+/// Saves tile, `If-Match`/`If-None-Match` headers make it conditional on
+/// current tile version (412 when they don't hold), new version is in `ETag`
+pub fn tile_post(req: &mut Request) -> IronResult<Response> {
+    let (status, msg, etag) = match _tile_post(req) {
+        Err(e) => return e.to_response(),
+        Ok(v) => v,
+    };
+    json_response_with_etag(status, &msg, etag)
+}
+
+fn _tile_patch(req: &mut Request) -> Result<(Status, String, Option<EntityTag>), DashboardError> {
+    let (dashboard_name, tile_id) = {
+        let router = req.extensions.get::<Router>().unwrap();
+        (
+            router.find("dashboard_name").unwrap().to_string(),
+            router.find("tile_id").unwrap().to_string(),
+        )
     };
-    let dashboard_api_token = match dashboard.get_api_token() {
-        None => {
-            return Ok((
-                Status::InternalServerError,
-                "Dashboard doens't have API Token, contact webpage administrators".to_string(),
+    let db = db::Db::new()?;
+    let dashboard =
+        get_authorized_dashboard(req, &db, &dashboard_name, Access::WriteTile(&tile_id))?;
+
+    let mut json = String::new();
+    req.body.read_to_string(&mut json)?;
+    check_signature(req, &db, &dashboard, &json)?;
+    // patch which isn't an object would replace whole tile, and tile has to
+    // stay an object
+    let patch = match serde_json::from_str::<serde_json::Value>(&json) {
+        Err(e) => {
+            return Err(DashboardError::InvalidPayload(
+                format!("Unable to unjson payload: ({})", e),
             ))
         }
-        Some(v) => v,
+        Ok(ref v) if !v.is_object() => {
+            return Err(DashboardError::InvalidPayload(
+                "Merge patch should be an object".to_string(),
+            ))
+        }
+        Ok(v) => v,
     };
-    if &request_token != dashboard_api_token {
-        return Ok((Status::Forbidden, "Tokens unmatched".to_string()));
-    }
+
+    let written = db.patch_tile(&dashboard, &tile_id, &patch, |tile| {
+        if_match_holds(&req.headers, tile) && if_none_match_holds(&req.headers, tile)
+    })?;
+    let (tile_data, etag) = match written {
+        db::TileWrite::Saved {
+            data,
+            version,
+            updated_at,
+        } => (data, tile_etag(version, Some(updated_at))),
+        db::TileWrite::Missing => {
+            return Err(DashboardError::NotFound("Tile doesn't exist".to_string()))
+        }
+        db::TileWrite::ConditionFailed => {
+            return Err(DashboardError::PreconditionFailed(
+                "Tile version doesn't match".to_string(),
+            ))
+        }
+    };
+    db.record_metric(&dashboard, &tile_id, &tile_data)?;
+    Ok((
+        Status::Ok,
+        serde_json::to_string(&tile_data)?,
+        Some(etag),
+    ))
+}
+
+/// Updates part of tile with JSON merge patch (RFC 7396) and responds with
+/// the whole patched tile, `null` in patch removes field. Conditional headers
+/// work like in `tile_post`.
+pub fn tile_patch(req: &mut Request) -> IronResult<Response> {
+    let (status, msg, etag) = match _tile_patch(req) {
+        Err(e) => return e.to_response(),
+        Ok(v) => v,
+    };
+    json_response_with_etag(status, &msg, etag)
+}
+
+/// Outcome of single tile from batch upload
+#[derive(Serialize)]
+struct TileStatus {
+    status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    errors: Option<Vec<SchemaViolation>>,
+}
+
+fn _tiles_post(req: &mut Request) -> Result<(Status, String), DashboardError> {
+    let dashboard_name = req.extensions
+        .get::<Router>()
+        .unwrap()
+        .find("dashboard_name")
+        .unwrap()
+        .to_string();
+    let db = db::Db::new()?;
+    let (api_token, dashboard) = get_token_and_dashboard(req, &db, &dashboard_name)?;
 
     let mut json = String::new();
-    if let Err(e) = req.body.read_to_string(&mut json) {
-        return Ok((
-            Status::InternalServerError,
-            format!("Reading payload FAILED ({})", e),
-        ));
+    req.body.read_to_string(&mut json)?;
+    check_signature(req, &db, &dashboard, &json)?;
+    let mut tiles = match serde_json::from_str::<BTreeMap<String, serde_json::Value>>(&json) {
+        Err(e) => {
+            return Err(DashboardError::InvalidPayload(
+                format!("Payload should map tile ids to tile data ({})", e),
+            ))
+        }
+        Ok(v) => v,
+    };
+
+    // tiles out of token scope are skipped, the rest is saved
+    let mut statuses = BTreeMap::new();
+    let denied: Vec<String> = tiles
+        .keys()
+        .filter(|tile_id| !api_token.scope.allows(&Access::WriteTile(tile_id)))
+        .cloned()
+        .collect();
+    for tile_id in denied {
+        let e = Access::WriteTile(&tile_id).denied();
+        statuses.insert(
+            tile_id.clone(),
+            TileStatus {
+                status: e.status().to_u16(),
+                message: Some(e.public_message().to_string()),
+                errors: None,
+            },
+        );
+        tiles.remove(&tile_id);
+    }
+
+    let results = db.upsert_tiles(&dashboard, &tiles)?;
+    for (tile_id, result) in results {
+        let status = match result {
+            Err(e) => TileStatus {
+                status: e.status().to_u16(),
+                message: Some(e.public_message().to_string()),
+                errors: e.violations().map(|v| v.to_vec()),
+            },
+            Ok(()) => TileStatus {
+                status: Status::Created.to_u16(),
+                message: None,
+                errors: None,
+            },
+        };
+        statuses.insert(tile_id, status);
     }
+    Ok((Status::Ok, serde_json::to_string(&statuses)?))
+}
+
+/// Saves many tiles (JSON object mapping tile id to tile data) at once, open
+/// screens get them in single message, responds with status of each tile
+pub fn tiles_post(req: &mut Request) -> IronResult<Response> {
+    let (status, msg) = match _tiles_post(req) {
+        Err(e) => return e.to_response(),
+        Ok(v) => v,
+    };
+    json_response(status, &msg)
+}
+
+/// Returns number passed in query string as `key`
+fn query_number<T: FromStr>(params: &params::Map, key: &str) -> Result<Option<T>, String> {
+    match params.find(&[key]) {
+        None => Ok(None),
+        Some(&params::Value::String(ref v)) => {
+            v.parse()
+                .map(Some)
+                .map_err(|_| format!("Query param {} should be a number ({})", key, v))
+        }
+        Some(_) => Err(format!("Query param {} should be a number", key)),
+    }
+}
+
+fn _tile_history_get(req: &mut Request) -> Result<(Status, String), DashboardError> {
+    let (dashboard_name, tile_id) = {
+        let router = req.extensions.get::<Router>().unwrap();
+        (
+            router.find("dashboard_name").unwrap().to_string(),
+            router.find("tile_id").unwrap().to_string(),
+        )
+    };
+    let (from, to, limit) = {
+        let params = req.get_ref::<params::Params>()
+            .map_err(|e| DashboardError::InvalidPayload(e.to_string()))?;
+        let parsed = query_number(params, "from").and_then(|from| {
+            let to = query_number(params, "to")?;
+            let limit = query_number(params, "limit")?;
+            Ok((from, to, limit))
+        });
+        match parsed {
+            Err(e) => return Err(DashboardError::InvalidPayload(e)),
+            Ok((from, to, limit)) => (
+                from.unwrap_or(0),
+                to.unwrap_or(i64::max_value()),
+                limit.unwrap_or(HISTORY_LIMIT_DEFAULT).min(HISTORY_LIMIT_MAX),
+            ),
+        }
+    };
+    let db = db::Db::new()?;
+    get_readable_dashboard(req, &db, &dashboard_name, Access::ReadHistory(&tile_id))?;
+    let samples = db.get_tile_history(&dashboard_name, &tile_id, from, to, limit)?;
+    Ok((Status::Ok, serde_json::to_string(&samples)?))
+}
 
-    // TODO: delegate this check to modeule db, this needs better error
-    // propagation. Now it relays on Box which is hard to inspect. Replace it
-    // with own custom error like DbError or something
-    if let Err(e) = serde_json::from_str::<serde_json::Value>(&json) {
-        return Ok((
-            Status::BadRequest,
-            format!("Unable to unjson payload: ({})", e),
+/// Returns samples of tile taken between `from` and `to` query params
+/// (milliseconds since unix epoch), at most `limit` newest ones
+pub fn tile_history_get(req: &mut Request) -> IronResult<Response> {
+    let (status, msg) = match _tile_history_get(req) {
+        Err(e) => return e.to_response(),
+        Ok(v) => v,
+    };
+    json_response(status, &msg)
+}
+
+fn _tile_metric_get(req: &mut Request) -> Result<(Status, String), DashboardError> {
+    let (dashboard_name, tile_id) = {
+        let router = req.extensions.get::<Router>().unwrap();
+        (
+            router.find("dashboard_name").unwrap().to_string(),
+            router.find("tile_id").unwrap().to_string(),
+        )
+    };
+    let (from, to, step, agg) = {
+        let params = req.get_ref::<params::Params>()
+            .map_err(|e| DashboardError::InvalidPayload(e.to_string()))?;
+        let parsed = query_number(params, "from").and_then(|from| {
+            let to = query_number(params, "to")?;
+            let step = query_number(params, "step")?;
+            let agg = match params.find(&["agg"]) {
+                None => Aggregation::Avg,
+                Some(&params::Value::String(ref v)) => v.parse()?,
+                Some(_) => return Err("Query param agg should be a string".to_string()),
+            };
+            Ok((from, to, step, agg))
+        });
+        match parsed {
+            Err(e) => return Err(DashboardError::InvalidPayload(e)),
+            Ok((from, to, step, agg)) => (
+                from.unwrap_or(0),
+                to.unwrap_or(i64::max_value()),
+                step.unwrap_or(METRIC_STEP_DEFAULT),
+                agg,
+            ),
+        }
+    };
+    if step <= 0 {
+        return Err(DashboardError::InvalidPayload(
+            "Query param step should be greater than 0".to_string(),
         ));
     }
+    let db = db::Db::new()?;
+    get_readable_dashboard(req, &db, &dashboard_name, Access::ReadHistory(&tile_id))?;
+    let points = db.get_metric_points(&dashboard_name, &tile_id, from, to)?;
+    let values = metrics::downsample(&points, step, agg);
+    Ok((Status::Ok, serde_json::to_string(&values)?))
+}
 
-    db.upsert_tile(dashboard_name, tile_id, &json)?;
-    Ok((Status::Created, "".to_string()))
+/// Returns tile metric between `from` and `to` query params (milliseconds
+/// since unix epoch) aggregated with `agg` in buckets `step` milliseconds wide
+pub fn tile_metric_get(req: &mut Request) -> IronResult<Response> {
+    let (status, msg) = match _tile_metric_get(req) {
+        Err(e) => return e.to_response(),
+        Ok(v) => v,
+    };
+    json_response(status, &msg)
 }
 
-pub fn tile_post(req: &mut Request) -> IronResult<Response> {
-    let (status, msg) = match _tile_post(req) {
-        Err(_) => return json_response(Status::InternalServerError, "We're working on fix"),
+fn _tile_delete(req: &mut Request) -> Result<(Status, String), DashboardError> {
+    let (dashboard_name, tile_id) = {
+        let router = req.extensions.get::<Router>().unwrap();
+        (
+            router.find("dashboard_name").unwrap(),
+            router.find("tile_id").unwrap(),
+        )
+    };
+    let db = db::Db::new()?;
+    let dashboard =
+        get_authorized_dashboard(req, &db, dashboard_name, Access::WriteTile(tile_id))?;
+    check_signature(req, &db, &dashboard, "")?;
+    if !db.delete_tile(dashboard_name, tile_id)? {
+        return Err(DashboardError::NotFound("Tile doesn't exist".to_string()));
+    }
+    Ok((Status::NoContent, "".to_string()))
+}
+
+/// Deletes tile with its history, open screens are told to drop it
+pub fn tile_delete(req: &mut Request) -> IronResult<Response> {
+    let (status, msg) = match _tile_delete(req) {
+        Err(e) => return e.to_response(),
+        Ok(v) => v,
+    };
+    json_response(status, &msg)
+}
+
+fn _dashboard_delete(req: &mut Request) -> Result<(Status, String), DashboardError> {
+    let dashboard_name = req.extensions
+        .get::<Router>()
+        .unwrap()
+        .find("dashboard_name")
+        .unwrap();
+    let db = db::Db::new()?;
+    let dashboard = get_authorized_dashboard(req, &db, dashboard_name, Access::Manage)?;
+    check_signature(req, &db, &dashboard, "")?;
+    db.delete_dashboard(dashboard_name)?;
+    Ok((Status::NoContent, "".to_string()))
+}
+
+/// Deletes dashboard with all its tiles, open screens are told about it
+pub fn dashboard_delete(req: &mut Request) -> IronResult<Response> {
+    let (status, msg) = match _dashboard_delete(req) {
+        Err(e) => return e.to_response(),
+        Ok(v) => v,
+    };
+    json_response(status, &msg)
+}
+
+fn _tokens_get(req: &mut Request) -> Result<(Status, String), DashboardError> {
+    let dashboard_name = req.extensions
+        .get::<Router>()
+        .unwrap()
+        .find("dashboard_name")
+        .unwrap();
+    let db = db::Db::new()?;
+    let dashboard = get_authorized_dashboard(req, &db, dashboard_name, Access::Manage)?;
+    let infos = db.get_api_token_infos(&dashboard)?;
+    Ok((Status::Ok, serde_json::to_string(&infos)?))
+}
+
+/// Returns tokens of dashboard (without their secrets) with time of creation,
+/// expiry and last use
+pub fn tokens_get(req: &mut Request) -> IronResult<Response> {
+    let (status, msg) = match _tokens_get(req) {
+        Err(e) => return e.to_response(),
+        Ok(v) => v,
+    };
+    json_response(status, &msg)
+}
+
+#[derive(Default, Deserialize)]
+struct TokenRequest {
+    /// milliseconds since unix epoch, token never expires when missing
+    expires_at: Option<i64>,
+    #[serde(default)]
+    scope: TokenScope,
+}
+
+/// Newly generated token, the only place where plain token is shown
+#[derive(Serialize)]
+struct CreatedToken<'a> {
+    name: &'a str,
+    token: &'a str,
+    created_at: i64,
+    expires_at: Option<i64>,
+    scope: &'a TokenScope,
+}
+
+fn _token_post(req: &mut Request) -> Result<(Status, String), DashboardError> {
+    let (dashboard_name, token_name) = {
+        let router = req.extensions.get::<Router>().unwrap();
+        (
+            router.find("dashboard_name").unwrap().to_string(),
+            router.find("token_name").unwrap().to_string(),
+        )
+    };
+    let db = db::Db::new()?;
+    let mut dashboard = get_authorized_dashboard(req, &db, &dashboard_name, Access::Manage)?;
+
+    let mut json = String::new();
+    req.body.read_to_string(&mut json)?;
+    check_signature(req, &db, &dashboard, &json)?;
+    let token_request: TokenRequest = if json.trim().is_empty() {
+        TokenRequest::default()
+    } else {
+        serde_json::from_str(&json).map_err(|e| DashboardError::InvalidPayload(e.to_string()))?
+    };
+
+    let secret = dashboard.add_api_token(
+        &token_name,
+        token_request.expires_at,
+        token_request.scope,
+    )?;
+    db.upsert_dashboard(&dashboard)?;
+    let created = match dashboard.get_api_tokens().last() {
+        None => return Err(DashboardError::Internal("Can't generate token".to_string())),
+        Some(v) => {
+            CreatedToken {
+                name: &v.name,
+                token: &secret,
+                created_at: v.created_at,
+                expires_at: v.expires_at,
+                scope: &v.scope,
+            }
+        }
+    };
+    Ok((Status::Created, serde_json::to_string(&created)?))
+}
+
+/// Generates new token for dashboard (with full access unless `scope` is
+/// given), its secret is shown only in this response
+pub fn token_post(req: &mut Request) -> IronResult<Response> {
+    let (status, msg) = match _token_post(req) {
+        Err(e) => return e.to_response(),
+        Ok(v) => v,
+    };
+    json_response(status, &msg)
+}
+
+fn _token_delete(req: &mut Request) -> Result<(Status, String), DashboardError> {
+    let (dashboard_name, token_name) = {
+        let router = req.extensions.get::<Router>().unwrap();
+        (
+            router.find("dashboard_name").unwrap(),
+            router.find("token_name").unwrap(),
+        )
+    };
+    let db = db::Db::new()?;
+    let mut dashboard = get_authorized_dashboard(req, &db, dashboard_name, Access::Manage)?;
+    check_signature(req, &db, &dashboard, "")?;
+    if !dashboard.revoke_api_token(token_name) {
+        return Err(DashboardError::NotFound("Token doesn't exist".to_string()));
+    }
+    db.upsert_dashboard(&dashboard)?;
+    Ok((Status::NoContent, "".to_string()))
+}
+
+/// Revokes token of dashboard, the one used by request can be revoked too
+pub fn token_delete(req: &mut Request) -> IronResult<Response> {
+    let (status, msg) = match _token_delete(req) {
+        Err(e) => return e.to_response(),
+        Ok(v) => v,
+    };
+    json_response(status, &msg)
+}
+
+#[derive(Serialize)]
+struct CreatedSigningSecret<'a> {
+    signing_secret: &'a str,
+}
+
+fn _signing_secret_post(req: &mut Request) -> Result<(Status, String), DashboardError> {
+    let dashboard_name = req.extensions
+        .get::<Router>()
+        .unwrap()
+        .find("dashboard_name")
+        .unwrap();
+    let db = db::Db::new()?;
+    let mut dashboard = get_authorized_dashboard(req, &db, dashboard_name, Access::Manage)?;
+    let mut body = String::new();
+    req.body.read_to_string(&mut body)?;
+    check_signature(req, &db, &dashboard, &body)?;
+    let secret = signing::generate_secret();
+    dashboard.signing_secret = Some(secret.clone());
+    db.upsert_dashboard(&dashboard)?;
+    let created = CreatedSigningSecret { signing_secret: &secret };
+    Ok((Status::Created, serde_json::to_string(&created)?))
+}
+
+/// Generates new signing secret for dashboard (replacing old one), from now on
+/// its writes have to be signed
+pub fn signing_secret_post(req: &mut Request) -> IronResult<Response> {
+    let (status, msg) = match _signing_secret_post(req) {
+        Err(e) => return e.to_response(),
+        Ok(v) => v,
+    };
+    json_response(status, &msg)
+}
+
+fn _signing_secret_delete(req: &mut Request) -> Result<(Status, String), DashboardError> {
+    let dashboard_name = req.extensions
+        .get::<Router>()
+        .unwrap()
+        .find("dashboard_name")
+        .unwrap();
+    let db = db::Db::new()?;
+    let mut dashboard = get_authorized_dashboard(req, &db, dashboard_name, Access::Manage)?;
+    check_signature(req, &db, &dashboard, "")?;
+    if dashboard.signing_secret.take().is_none() {
+        return Err(DashboardError::NotFound("Signing secret doesn't exist".to_string()));
+    }
+    db.upsert_dashboard(&dashboard)?;
+    Ok((Status::NoContent, "".to_string()))
+}
+
+/// Removes signing secret of dashboard (request has to be signed by it), its
+/// writes don't have to be signed anymore
+pub fn signing_secret_delete(req: &mut Request) -> IronResult<Response> {
+    let (status, msg) = match _signing_secret_delete(req) {
+        Err(e) => return e.to_response(),
         Ok(v) => v,
     };
     json_response(status, &msg)
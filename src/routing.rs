@@ -1,17 +1,26 @@
 use gui_api;
 use iron::Chain;
 use mount::Mount;
+use rate_limit;
+use rate_limit::RateLimiter;
 use rest_api;
 use staticfile::Static;
 use std::path::Path;
+use std::sync::Arc;
 use utils;
 use views;
 
 
 pub fn get_mount() -> Mount {
+    get_mount_with_limiter(rate_limit::shared_limiter())
+}
+
+/// Works like `get_mount`, but REST API is limited by `limiter`
+fn get_mount_with_limiter(limiter: Arc<RateLimiter>) -> Mount {
     let views_handler = views::get_handler();
 
     let mut rest_chain = Chain::new(rest_api::rest_router());
+    rest_chain.link_before(rate_limit::RateLimit::new(limiter));
     rest_chain.link_before(rest_api::AuthToken);
 
     let mut mount = Mount::new();
@@ -32,15 +41,24 @@ pub fn get_mount() -> Mount {
 mod tests {
     use super::*;
     use db;
-    use hyper::header::ContentType;
+    use hyper::header::{ContentType, ETag, EntityTag, IfMatch, IfNoneMatch};
     use iron::{Headers, status};
     use iron::headers::Authorization;
     use iron::mime;
     use iron::prelude::*;
     use iron::status::Status;
     use iron_test::{request, response};
+    use rate_limit::{Limit, LimitKind};
+    use serde_json;
+    use serde_json::Value;
+    use std::collections::HashMap;
     use std::error::Error;
+    use std::thread;
+    use std::time::Duration;
+    use signing;
+    use signing::SignedRequest;
     use test_utils;
+    use tokens::TokenScope;
 
 
     fn _get_data(url: &str) -> Response {
@@ -49,12 +67,95 @@ mod tests {
         request::get(url, headers, &get_mount()).unwrap()
     }
 
+    fn _get_data_with_token(url: &str, api_key: &str) -> Response {
+        let mut headers = Headers::new();
+        headers.set(Authorization(api_key.to_owned()));
+        request::get(url, headers, &get_mount()).unwrap()
+    }
+
     fn _post_data(url: String, api_key: &str, data: &str) -> Response {
         let mut headers = Headers::new();
         headers.set(Authorization(api_key.to_owned()));
         request::post(&url, headers, data, &get_mount()).unwrap()
     }
 
+    /// Returns headers of `method` request to `url` signed with `secret`
+    fn _signed_headers(
+        method: &str,
+        url: &str,
+        api_key: &str,
+        secret: &str,
+        nonce: &str,
+        data: &str,
+    ) -> Headers {
+        let timestamp = utils::timestamp_ms();
+        let signed = SignedRequest {
+            method: method,
+            path: &url["http://localhost:8000".len()..],
+            timestamp: timestamp,
+            nonce: nonce,
+            body: data,
+        };
+        let mut headers = Headers::new();
+        headers.set(Authorization(api_key.to_owned()));
+        headers.set_raw(signing::TIMESTAMP_HEADER, vec![timestamp.to_string().into_bytes()]);
+        headers.set_raw(signing::NONCE_HEADER, vec![nonce.as_bytes().to_vec()]);
+        headers.set_raw(signing::SIGNATURE_HEADER, vec![signed.sign(secret).into_bytes()]);
+        headers
+    }
+
+    fn _post_signed(url: &str, api_key: &str, secret: &str, nonce: &str, data: &str) -> Response {
+        let headers = _signed_headers("POST", url, api_key, secret, nonce, data);
+        request::post(url, headers, data, &get_mount()).unwrap()
+    }
+
+    fn _patch_data(url: &str, api_key: &str, data: &str) -> Response {
+        let mut headers = Headers::new();
+        headers.set(Authorization(api_key.to_owned()));
+        request::patch(url, headers, data, &get_mount()).unwrap()
+    }
+
+    fn _put_json(url: &str, api_key: &str, data: &str) -> Response {
+        let mut headers = Headers::new();
+        headers.set(Authorization(api_key.to_owned()));
+        headers.set(ContentType::json());
+        request::put(url, headers, data, &get_mount()).unwrap()
+    }
+
+    fn _patch_json(url: &str, api_key: &str, data: &str) -> Response {
+        let mut headers = Headers::new();
+        headers.set(Authorization(api_key.to_owned()));
+        headers.set(ContentType::json());
+        request::patch(url, headers, data, &get_mount()).unwrap()
+    }
+
+    fn _delete_data(url: &str, api_key: &str) -> Response {
+        let mut headers = Headers::new();
+        headers.set(Authorization(api_key.to_owned()));
+        request::delete(url, headers, &get_mount()).unwrap()
+    }
+
+    /// Returns mount whose REST API allows single request per second of
+    /// `kind`
+    fn mount_limited_by(kind: LimitKind) -> Mount {
+        let mut limits = HashMap::new();
+        limits.insert(
+            kind,
+            Limit {
+                burst: 1.0,
+                per_sec: 1.0,
+            },
+        );
+        get_mount_with_limiter(Arc::new(RateLimiter::new(limits)))
+    }
+
+    fn _get_limited(mount: &Mount, url: &str, api_key: &str) -> Response {
+        let mut headers = Headers::new();
+        headers.set(Authorization(api_key.to_owned()));
+        // response of middleware comes as error
+        request::get(url, headers, mount).unwrap_or_else(|e| e.response)
+    }
+
     fn assert_json(response: Response) {
         assert_eq!(
             response.headers.get::<ContentType>().unwrap().0,
@@ -64,7 +165,7 @@ mod tests {
 
     #[test]
     fn dashboard_show_returns_200() {
-        utils::load_config(None);
+        test_utils::load_config();
 
         let response = request::get("http://localhost:8000/", Headers::new(), &get_mount())
             .unwrap();
@@ -77,7 +178,7 @@ mod tests {
 
     #[test]
     fn test_dashboard_shows_created_dashboard() {
-        utils::load_config(None);
+        test_utils::load_config();
         let dashboard_name = "Uber-dashboard-name".to_string();
         let db = db::Db::new().unwrap();
         db.delete_dashboard(&dashboard_name).unwrap();
@@ -100,7 +201,7 @@ mod tests {
     }
 
     fn tile_get_setup() {
-        utils::load_config(None);
+        test_utils::load_config();
         let db = db::Db::new().unwrap();
         let dashboard_name = "dashboard-test";
         test_utils::upsert_dashboard(&db, &dashboard_name);
@@ -119,6 +220,20 @@ mod tests {
         assert_json(response);
     }
 
+    #[test]
+    fn tile_get_returns_304_when_if_none_match_has_tile_version() {
+        tile_get_setup();
+        let url = "http://localhost:8000/api/dashboard/dashboard-test/tile/tile-test";
+        let etag = _get_data(url).headers.get::<ETag>().unwrap().clone();
+        let mut headers = Headers::new();
+        headers.set(Authorization("change-me".to_owned()));
+        headers.set(IfNoneMatch::Items(vec![etag.0]));
+
+        let response = request::get(url, headers, &get_mount()).unwrap();
+
+        assert_eq!(response.status.unwrap(), status::NotModified);
+    }
+
     #[test]
     fn tile_get_returns_404_when_dashboard_is_missing() {
         tile_get_setup();
@@ -145,7 +260,7 @@ mod tests {
 
     #[test]
     fn tile_post_returns_201() {
-        utils::load_config(None);
+        test_utils::load_config();
         let db = db::Db::new().unwrap();
         let dashboard = test_utils::upsert_dashboard(&db, "dashboard-test");
         let url = format!(
@@ -160,9 +275,77 @@ mod tests {
         assert_json(response);
     }
 
+    #[test]
+    fn tile_post_returns_412_when_if_match_has_stale_version() {
+        test_utils::load_config();
+        let db = db::Db::new().unwrap();
+        let dashboard = test_utils::upsert_dashboard(&db, "dashboard-if-match");
+        db.upsert_tile(&dashboard.name, "tile-test", "{\"value\": 1}")
+            .unwrap();
+        let url = format!(
+            "http://localhost:8000/api/dashboard/{}/tile/tile-test",
+            dashboard.name
+        );
+        let mut headers = Headers::new();
+        headers.set(Authorization(dashboard.get_api_token().unwrap().to_owned()));
+        headers.set(IfMatch::Items(vec![EntityTag::strong("0".to_string())]));
+
+        let response = request::post(&url, headers, "{\"value\": 2}", &get_mount()).unwrap();
+
+        assert_eq!(response.status.unwrap(), Status::PreconditionFailed);
+        let saved = db.get_tile(&dashboard.name, "tile-test").unwrap().unwrap();
+        assert_eq!(serde_json::from_str::<Value>(&saved).unwrap()["value"], 1);
+    }
+
+    #[test]
+    fn tile_post_returns_new_version_in_etag() {
+        test_utils::load_config();
+        let db = db::Db::new().unwrap();
+        let dashboard = test_utils::upsert_dashboard(&db, "dashboard-etag");
+        let url = format!(
+            "http://localhost:8000/api/dashboard/{}/tile/tile-test",
+            dashboard.name
+        );
+        let api_key = dashboard.get_api_token().unwrap();
+
+        let first = _post_data(url.clone(), api_key, "{}");
+        let second = _post_data(url, api_key, "{}");
+
+        assert_ne!(
+            first.headers.get::<ETag>().unwrap(),
+            second.headers.get::<ETag>().unwrap()
+        );
+    }
+
+    #[test]
+    fn tile_get_returns_200_when_if_none_match_has_etag_of_deleted_tile() {
+        test_utils::load_config();
+        let db = db::Db::new().unwrap();
+        let dashboard = test_utils::upsert_dashboard(&db, "dashboard-etag-recreated");
+        let url = format!(
+            "http://localhost:8000/api/dashboard/{}/tile/tile-test",
+            dashboard.name
+        );
+        let api_key = dashboard.get_api_token().unwrap();
+        let deleted = _post_data(url.clone(), api_key, "{\"value\": 1}");
+        _delete_data(&url, api_key);
+        // recreated tile starts at the same version
+        thread::sleep(Duration::from_millis(2));
+        _post_data(url.clone(), api_key, "{\"value\": 2}");
+        let mut headers = Headers::new();
+        headers.set(Authorization(api_key.to_owned()));
+        headers.set(IfNoneMatch::Items(
+            vec![deleted.headers.get::<ETag>().unwrap().0.clone()],
+        ));
+
+        let response = request::get(&url, headers, &get_mount()).unwrap();
+
+        assert_eq!(response.status.unwrap(), status::Ok);
+    }
+
     #[test]
     fn tile_post_saves_tile_id_in_tile_data() {
-        utils::load_config(None);
+        test_utils::load_config();
         let db = db::Db::new().unwrap();
         let dashboard = test_utils::upsert_dashboard(&db, "dashboard-test");
         let url = format!(
@@ -182,7 +365,7 @@ mod tests {
 
     #[test]
     fn tile_post_returns_400_when_json_invalid() {
-        utils::load_config(None);
+        test_utils::load_config();
         let db = db::Db::new().unwrap();
         let dashboard = test_utils::upsert_dashboard(&db, "dashboard-test");
         let url = format!(
@@ -197,9 +380,744 @@ mod tests {
         assert_json(response);
     }
 
+    #[test]
+    fn tile_patch_merges_patch_and_keeps_tile_id() {
+        test_utils::load_config();
+        let db = db::Db::new().unwrap();
+        let dashboard = test_utils::upsert_dashboard(&db, "dashboard-patch");
+        db.upsert_tile(
+            &dashboard.name,
+            "tile-test",
+            "{\"tile-data\": {\"header\": \"h\", \"value\": 1}}",
+        ).unwrap();
+        let url = format!(
+            "http://localhost:8000/api/dashboard/{}/tile/tile-test",
+            dashboard.name
+        );
+
+        let response = _patch_data(
+            &url,
+            dashboard.get_api_token().unwrap(),
+            "{\"tile-id\": null, \"tile-data\": {\"value\": 2}}",
+        );
+
+        assert_eq!(response.status.unwrap(), status::Ok);
+        let saved: Value =
+            serde_json::from_str(&db.get_tile(&dashboard.name, "tile-test").unwrap().unwrap())
+                .unwrap();
+        assert_eq!(
+            saved,
+            json!({"tile-id": "tile-test", "tile-data": {"header": "h", "value": 2}})
+        );
+    }
+
+    #[test]
+    fn tile_patch_returns_400_when_patch_is_not_object() {
+        test_utils::load_config();
+        let db = db::Db::new().unwrap();
+        let dashboard = test_utils::upsert_dashboard(&db, "dashboard-test");
+        db.upsert_tile(&dashboard.name, "tile-test", "{}").unwrap();
+        let url = format!(
+            "http://localhost:8000/api/dashboard/{}/tile/tile-test",
+            dashboard.name
+        );
+
+        let response = _patch_data(&url, dashboard.get_api_token().unwrap(), "[]");
+
+        assert_eq!(response.status.unwrap(), status::BadRequest);
+        assert_json(response);
+    }
+
+    #[test]
+    fn tile_patch_returns_404_when_tile_is_missing() {
+        test_utils::load_config();
+        let db = db::Db::new().unwrap();
+        let dashboard = test_utils::upsert_dashboard(&db, "dashboard-test");
+        let url = format!(
+            "http://localhost:8000/api/dashboard/{}/tile/tile-missing",
+            dashboard.name
+        );
+
+        let response = _patch_data(&url, dashboard.get_api_token().unwrap(), "{}");
+
+        assert_eq!(response.status.unwrap(), status::NotFound);
+    }
+
+    /// Returns dashboard whose `tile-test` has to have numeric `value`
+    fn upsert_dashboard_with_schema(db: &db::Db, dashboard_name: &str) -> db::Dashboard {
+        let mut dashboard = test_utils::upsert_dashboard(db, dashboard_name);
+        dashboard.tile_types.insert("tile-test".to_string(), "value".to_string());
+        dashboard.schemas.insert(
+            "value".to_string(),
+            serde_json::from_str(
+                "{\"type\": \"object\", \"required\": [\"value\"], \
+                 \"properties\": {\"value\": {\"type\": \"number\"}}}",
+            ).unwrap(),
+        );
+        db.upsert_dashboard(&dashboard).unwrap();
+        dashboard
+    }
+
+    #[test]
+    fn tile_post_returns_400_with_paths_when_tile_mismatches_schema() {
+        test_utils::load_config();
+        let db = db::Db::new().unwrap();
+        let dashboard = upsert_dashboard_with_schema(&db, "dashboard-schema");
+        let url = format!(
+            "http://localhost:8000/api/dashboard/{}/tile/tile-test",
+            dashboard.name
+        );
+
+        let response = _post_data(url, dashboard.get_api_token().unwrap(), "{\"value\": \"1\"}");
+
+        assert_eq!(response.status.unwrap(), status::BadRequest);
+        let body = response::extract_body_to_string(response);
+        let error: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(error["code"], "schema-mismatch");
+        assert_eq!(error["errors"][0]["path"], "/value");
+        assert_eq!(db.get_tile(&dashboard.name, "tile-test").unwrap(), None);
+    }
+
+    #[test]
+    fn tile_post_normalizes_tile_of_declared_type() {
+        test_utils::load_config();
+        let db = db::Db::new().unwrap();
+        let mut dashboard = test_utils::upsert_dashboard(&db, "dashboard-tile-types");
+        dashboard.tile_types.insert("tile-test".to_string(), "number".to_string());
+        db.upsert_dashboard(&dashboard).unwrap();
+        let url = format!(
+            "http://localhost:8000/api/dashboard/{}/tile/tile-test",
+            dashboard.name
+        );
+
+        let response = _post_data(url, dashboard.get_api_token().unwrap(), "{\"value\": \"7\"}");
+
+        assert_eq!(response.status.unwrap(), status::Created);
+        let saved = db.get_tile(&dashboard.name, "tile-test").unwrap().unwrap();
+        let saved: Value = serde_json::from_str(&saved).unwrap();
+        assert_eq!(saved["value"], 7.0);
+        assert_eq!(saved["unit"], "");
+    }
+
+    #[test]
+    fn schemas_get_returns_registered_schemas() {
+        test_utils::load_config();
+        let db = db::Db::new().unwrap();
+        let dashboard = upsert_dashboard_with_schema(&db, "dashboard-schemas-get");
+
+        let response = _get_data(&format!(
+            "http://localhost:8000/api/dashboard/{}/schemas",
+            dashboard.name
+        ));
+
+        assert_eq!(response.status.unwrap(), status::Ok);
+        let body = response::extract_body_to_string(response);
+        let schemas: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(schemas["tile_types"]["tile-test"], "value");
+        assert_eq!(schemas["schemas"]["value"]["required"][0], "value");
+    }
+
+    #[test]
+    fn schemas_get_of_private_dashboard_needs_its_token() {
+        test_utils::load_config();
+        let db = db::Db::new().unwrap();
+        let mut dashboard = upsert_dashboard_with_schema(&db, "dashboard-schemas-private");
+        dashboard.visibility = db::Visibility::Private;
+        let write_token = dashboard
+            .add_api_token("feeder", None, TokenScope::Write { tiles: "*".to_string() })
+            .unwrap();
+        db.upsert_dashboard(&dashboard).unwrap();
+        let url = format!(
+            "http://localhost:8000/api/dashboard/{}/schemas",
+            dashboard.name
+        );
+
+        let anonymous = _get_data(&url);
+        let with_token = _get_data_with_token(&url, &write_token);
+
+        assert_eq!(anonymous.status, Some(status::Forbidden));
+        assert_eq!(with_token.status, Some(status::Ok));
+    }
+
+    #[test]
+    fn tiles_post_saves_valid_tiles_and_reports_invalid_ones() {
+        test_utils::load_config();
+        let db = db::Db::new().unwrap();
+        let dashboard = test_utils::upsert_dashboard(&db, "dashboard-batch");
+        let url = format!("http://localhost:8000/api/dashboard/{}/tiles", dashboard.name);
+        let api_key = dashboard.get_api_token().unwrap();
+
+        let response = _post_data(url, api_key, "{\"tile-a\": {}, \"tile-b\": 1}");
+
+        assert_eq!(response.status.unwrap(), status::Ok);
+        let body = response::extract_body_to_string(response);
+        let statuses: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(statuses["tile-a"]["status"], 201);
+        assert_eq!(statuses["tile-b"]["status"], 400);
+        assert!(db.get_tile(&dashboard.name, "tile-a").unwrap().is_some());
+        assert_eq!(db.get_tile(&dashboard.name, "tile-b").unwrap(), None);
+    }
+
+    #[test]
+    fn tiles_post_returns_400_when_payload_is_not_map() {
+        test_utils::load_config();
+        let db = db::Db::new().unwrap();
+        let dashboard = test_utils::upsert_dashboard(&db, "dashboard-test");
+        let url = format!("http://localhost:8000/api/dashboard/{}/tiles", dashboard.name);
+        let api_key = dashboard.get_api_token().unwrap();
+
+        let response = _post_data(url, api_key, "[]");
+
+        assert_eq!(response.status.unwrap(), status::BadRequest);
+        assert_json(response);
+    }
+
+    #[test]
+    fn tile_history_returns_posted_samples() {
+        test_utils::load_config();
+        let db = db::Db::new().unwrap();
+        test_utils::upsert_dashboard(&db, "dashboard-history");
+        db.upsert_tile("dashboard-history", "tile-test", "{\"value\": 1}")
+            .unwrap();
+        db.upsert_tile("dashboard-history", "tile-test", "{\"value\": 2}")
+            .unwrap();
+
+        let response = _get_data(
+            "http://localhost:8000/api/dashboard/dashboard-history/tile/tile-test/history?limit=1",
+        );
+
+        assert_eq!(response.status.unwrap(), status::Ok);
+        let body = response::extract_body_to_string(response);
+        let samples: Vec<db::TileSample> = serde_json::from_str(&body).unwrap();
+        assert_eq!(samples.len(), 1);
+        assert_eq!(samples[0].data["value"], 2);
+    }
+
+    #[test]
+    fn tile_history_returns_400_when_limit_is_not_number() {
+        test_utils::load_config();
+
+        let response = _get_data(
+            "http://localhost:8000/api/dashboard/dashboard-test/tile/tile-test/history?limit=many",
+        );
+
+        assert_eq!(response.status.unwrap(), status::BadRequest);
+        assert_json(response);
+    }
+
+    #[test]
+    fn tiles_get_lists_tiles_of_dashboard() {
+        test_utils::load_config();
+        let db = db::Db::new().unwrap();
+        test_utils::upsert_dashboard(&db, "dashboard-tiles");
+        db.upsert_tile("dashboard-tiles", "tile-10", "{}").unwrap();
+        db.upsert_tile("dashboard-tiles", "tile-2", "{\"value\": 2}")
+            .unwrap();
+
+        let response = _get_data(
+            "http://localhost:8000/api/dashboard/dashboard-tiles/tiles?per_page=1&data=true",
+        );
+
+        assert_eq!(response.status.unwrap(), status::Ok);
+        let body = response::extract_body_to_string(response);
+        let list: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(list["page_max"], 2);
+        assert_eq!(list["tiles"][0]["tile_id"], "tile-2");
+        assert_eq!(list["tiles"][0]["data"]["value"], 2);
+        assert!(list["tiles"][0]["updated_at"].is_i64());
+    }
+
+    #[test]
+    fn tiles_get_returns_404_when_dashboard_is_missing() {
+        test_utils::load_config();
+
+        let response = _get_data("http://localhost:8000/api/dashboard/dashboard-missing/tiles");
+
+        assert_eq!(response.status.unwrap(), status::NotFound);
+        assert_json(response);
+    }
+
+    #[test]
+    fn tile_metric_aggregates_posted_values() {
+        test_utils::load_config();
+        let db = db::Db::new().unwrap();
+        let mut dashboard = test_utils::upsert_dashboard(&db, "dashboard-metric");
+        dashboard
+            .metrics
+            .insert("tile-test".to_string(), "/value".to_string());
+        db.upsert_dashboard(&dashboard).unwrap();
+        let url = "http://localhost:8000/api/dashboard/dashboard-metric/tile/tile-test";
+        let api_key = dashboard.get_api_token().unwrap();
+        for value in &["1", "5", "3"] {
+            _post_data(url.to_string(), api_key, &format!("{{\"value\": {}}}", value));
+        }
+
+        // step wider than any timestamp, so all values land in single bucket
+        let response = _get_data(&format!("{}/metric?agg=max&step={}", url, i64::max_value()));
+
+        assert_eq!(response.status.unwrap(), status::Ok);
+        let body = response::extract_body_to_string(response);
+        let values: Vec<Value> = serde_json::from_str(&body).unwrap();
+        assert_eq!(values.len(), 1);
+        assert_eq!(values[0]["value"], 5.0);
+    }
+
+    #[test]
+    fn tiles_post_records_metric_of_normalised_tile() {
+        test_utils::load_config();
+        let db = db::Db::new().unwrap();
+        let mut dashboard = test_utils::upsert_dashboard(&db, "dashboard-batch-metric");
+        dashboard
+            .metrics
+            .insert("tile-test".to_string(), "/value".to_string());
+        dashboard
+            .tile_types
+            .insert("tile-test".to_string(), "number".to_string());
+        db.upsert_dashboard(&dashboard).unwrap();
+        let url = "http://localhost:8000/api/dashboard/dashboard-batch-metric";
+        let api_key = dashboard.get_api_token().unwrap();
+
+        // number sent as string is stored as number, so metric gets it too
+        let posted = _post_data(
+            format!("{}/tiles", url),
+            api_key,
+            r#"{"tile-test": {"value": "7"}}"#,
+        );
+        let response = _get_data(&format!(
+            "{}/tile/tile-test/metric?agg=max&step={}",
+            url,
+            i64::max_value()
+        ));
+
+        assert_eq!(posted.status, Some(status::Ok));
+        let body = response::extract_body_to_string(response);
+        let values: Vec<Value> = serde_json::from_str(&body).unwrap();
+        assert_eq!(values.len(), 1);
+        assert_eq!(values[0]["value"], 7.0);
+    }
+
+    #[test]
+    fn tile_metric_returns_400_when_agg_is_unknown() {
+        test_utils::load_config();
+
+        let response = _get_data(
+            "http://localhost:8000/api/dashboard/dashboard-test/tile/tile-test/metric?agg=median",
+        );
+
+        assert_eq!(response.status.unwrap(), status::BadRequest);
+        assert_json(response);
+    }
+
+    #[test]
+    fn tile_delete_removes_tile() {
+        test_utils::load_config();
+        let db = db::Db::new().unwrap();
+        let dashboard = test_utils::upsert_dashboard(&db, "dashboard-delete-tile");
+        db.upsert_tile(&dashboard.name, "tile-test", "{}").unwrap();
+        let url = "http://localhost:8000/api/dashboard/dashboard-delete-tile/tile/tile-test";
+
+        let response = _delete_data(url, dashboard.get_api_token().unwrap());
+
+        assert_eq!(response.status.unwrap(), status::NoContent);
+        assert_eq!(db.get_tile(&dashboard.name, "tile-test").unwrap(), None);
+    }
+
+    #[test]
+    fn tile_delete_returns_404_when_tile_is_missing() {
+        test_utils::load_config();
+        let db = db::Db::new().unwrap();
+        let dashboard = test_utils::upsert_dashboard(&db, "dashboard-test");
+        let url = "http://localhost:8000/api/dashboard/dashboard-test/tile/tile-missing";
+
+        let response = _delete_data(url, dashboard.get_api_token().unwrap());
+
+        assert_eq!(response.status.unwrap(), status::NotFound);
+        assert_json(response);
+    }
+
+    #[test]
+    fn dashboard_delete_removes_dashboard_with_tiles() {
+        test_utils::load_config();
+        let db = db::Db::new().unwrap();
+        let dashboard = test_utils::upsert_dashboard(&db, "dashboard-delete");
+        db.upsert_tile(&dashboard.name, "tile-test", "{}").unwrap();
+
+        let response = _delete_data(
+            "http://localhost:8000/api/dashboard/dashboard-delete",
+            dashboard.get_api_token().unwrap(),
+        );
+
+        assert_eq!(response.status.unwrap(), status::NoContent);
+        assert!(db.get_dashboard(&dashboard.name).unwrap().is_none());
+        assert_eq!(db.get_tile(&dashboard.name, "tile-test").unwrap(), None);
+    }
+
+    #[test]
+    fn dashboard_delete_gives_403_when_token_is_different() {
+        test_utils::load_config();
+        let db = db::Db::new().unwrap();
+        let dashboard = test_utils::upsert_dashboard(&db, "dashboard-not-deleted");
+
+        let response = _delete_data(
+            "http://localhost:8000/api/dashboard/dashboard-not-deleted",
+            "incorrect-token",
+        );
+
+        assert_eq!(response.status, Some(status::Forbidden));
+        assert!(db.get_dashboard(&dashboard.name).unwrap().is_some());
+    }
+
+    #[test]
+    fn dashboard_put_renames_dashboard_with_tiles() {
+        test_utils::load_config();
+        let db = db::Db::new().unwrap();
+        db.delete_dashboard("dashboard-renamed").unwrap();
+        let dashboard = test_utils::upsert_dashboard(&db, "dashboard-before-rename");
+        db.upsert_tile(&dashboard.name, "tile-test", "{}").unwrap();
+        let data = r#"{"name": "dashboard-renamed", "owner_email": "new@email.com",
+                       "layout": "2x4"}"#;
+
+        let response = _put_json(
+            "http://localhost:8000/gui-api/dashboard/dashboard-before-rename",
+            dashboard.get_api_token().unwrap(),
+            data,
+        );
+
+        assert_eq!(response.status, Some(status::Ok));
+        assert!(db.get_dashboard("dashboard-before-rename").unwrap().is_none());
+        let renamed = db.get_dashboard("dashboard-renamed").unwrap().unwrap();
+        assert_eq!(renamed.owner_email, "new@email.com");
+        assert!(
+            renamed
+                .find_api_token(dashboard.get_api_token().unwrap(), 0)
+                .is_some()
+        );
+        assert_eq!(db.get_tile("dashboard-before-rename", "tile-test").unwrap(), None);
+        assert!(db.get_tile("dashboard-renamed", "tile-test").unwrap().is_some());
+    }
+
+    #[test]
+    fn dashboard_patch_changes_only_given_fields() {
+        test_utils::load_config();
+        let db = db::Db::new().unwrap();
+        let dashboard = test_utils::upsert_dashboard(&db, "dashboard-patched");
+
+        let response = _patch_json(
+            "http://localhost:8000/gui-api/dashboard/dashboard-patched",
+            dashboard.get_api_token().unwrap(),
+            r#"{"owner_email": "new@email.com"}"#,
+        );
+
+        assert_eq!(response.status, Some(status::Ok));
+        let patched = db.get_dashboard("dashboard-patched").unwrap().unwrap();
+        assert_eq!(patched.owner_email, "new@email.com");
+        assert_eq!(patched.layout, dashboard.layout);
+    }
+
+    #[test]
+    fn dashboard_put_and_patch_of_signed_dashboard_have_to_be_signed() {
+        test_utils::load_config();
+        let db = db::Db::new().unwrap();
+        let mut dashboard = test_utils::upsert_dashboard(&db, "dashboard-signed-edit");
+        let token = dashboard.get_api_token().unwrap().clone();
+        dashboard.signing_secret = Some("secret".to_string());
+        db.upsert_dashboard(&dashboard).unwrap();
+        let url = "http://localhost:8000/gui-api/dashboard/dashboard-signed-edit";
+        let data = r#"{"owner_email": "new@email.com"}"#;
+
+        let put = _put_json(
+            url,
+            &token,
+            r#"{"name": "dashboard-stolen", "owner_email": "new@email.com",
+                "layout": "2x4", "visibility": "public"}"#,
+        );
+        let patched = _patch_json(url, &token, data);
+        let mut headers = _signed_headers("PATCH", url, &token, "secret", "nonce-1", data);
+        headers.set(ContentType::json());
+        let signed = request::patch(url, headers, data, &get_mount()).unwrap();
+
+        assert_eq!(put.status, Some(status::Forbidden));
+        assert_eq!(patched.status, Some(status::Forbidden));
+        assert_eq!(signed.status, Some(status::Ok));
+        assert!(db.get_dashboard("dashboard-stolen").unwrap().is_none());
+        let saved = db.get_dashboard("dashboard-signed-edit").unwrap().unwrap();
+        assert_eq!(saved.owner_email, "new@email.com");
+    }
+
+    #[test]
+    fn dashboard_patch_gives_409_when_name_is_taken() {
+        test_utils::load_config();
+        let db = db::Db::new().unwrap();
+        let dashboard = test_utils::upsert_dashboard(&db, "dashboard-not-renamed");
+        test_utils::upsert_dashboard(&db, "dashboard-name-taken");
+
+        let response = _patch_json(
+            "http://localhost:8000/gui-api/dashboard/dashboard-not-renamed",
+            dashboard.get_api_token().unwrap(),
+            r#"{"name": "dashboard-name-taken"}"#,
+        );
+
+        assert_eq!(response.status, Some(status::Conflict));
+        assert!(db.get_dashboard("dashboard-not-renamed").unwrap().is_some());
+    }
+
+    #[test]
+    fn dashboard_patch_gives_403_when_token_is_different() {
+        test_utils::load_config();
+        let db = db::Db::new().unwrap();
+        test_utils::upsert_dashboard(&db, "dashboard-not-patched");
+
+        let response = _patch_json(
+            "http://localhost:8000/gui-api/dashboard/dashboard-not-patched",
+            "incorrect-token",
+            r#"{"owner_email": "new@email.com"}"#,
+        );
+
+        assert_eq!(response.status, Some(status::Forbidden));
+        let dashboard = db.get_dashboard("dashboard-not-patched").unwrap().unwrap();
+        assert_eq!(dashboard.owner_email, "login@email.com");
+    }
+
+    #[test]
+    fn token_post_creates_token_accepted_by_tile_post() {
+        test_utils::load_config();
+        let db = db::Db::new().unwrap();
+        let dashboard = test_utils::upsert_dashboard(&db, "dashboard-tokens");
+
+        let response = _post_data(
+            "http://localhost:8000/api/dashboard/dashboard-tokens/token/ci".to_string(),
+            dashboard.get_api_token().unwrap(),
+            "",
+        );
+
+        assert_eq!(response.status, Some(status::Created));
+        let created: Value = serde_json::from_str(&response::extract_body_to_string(response))
+            .unwrap();
+        let ci_token = created["token"].as_str().unwrap();
+        let response = _post_data(
+            "http://localhost:8000/api/dashboard/dashboard-tokens/tile/tile-ci".to_string(),
+            ci_token,
+            "{}",
+        );
+        assert_eq!(response.status, Some(status::Created));
+        let response = _get_data_with_token(
+            "http://localhost:8000/api/dashboard/dashboard-tokens/tokens",
+            ci_token,
+        );
+        let tokens: Value = serde_json::from_str(&response::extract_body_to_string(response))
+            .unwrap();
+        assert_eq!(tokens[1]["name"], "ci");
+        assert!(tokens[1]["last_used_at"].is_number());
+        assert!(tokens[1].get("token").is_none());
+    }
+
+    #[test]
+    fn token_delete_revokes_token() {
+        test_utils::load_config();
+        let db = db::Db::new().unwrap();
+        let mut dashboard = test_utils::upsert_dashboard(&db, "dashboard-token-revoked");
+        let revoked = dashboard
+            .add_api_token("revoked", None, TokenScope::Full)
+            .unwrap();
+        db.upsert_dashboard(&dashboard).unwrap();
+
+        let response = _delete_data(
+            "http://localhost:8000/api/dashboard/dashboard-token-revoked/token/revoked",
+            dashboard.get_api_token().unwrap(),
+        );
+
+        assert_eq!(response.status, Some(status::NoContent));
+        let response = _post_data(
+            "http://localhost:8000/api/dashboard/dashboard-token-revoked/tile/tile-test"
+                .to_string(),
+            &revoked,
+            "{}",
+        );
+        assert_eq!(response.status, Some(status::Forbidden));
+    }
+
+    #[test]
+    fn tile_post_gives_403_when_token_has_expired() {
+        test_utils::load_config();
+        let db = db::Db::new().unwrap();
+        let mut dashboard = test_utils::upsert_dashboard(&db, "dashboard-token-expired");
+        let expiring = dashboard
+            .add_api_token(
+                "expiring",
+                Some(utils::timestamp_ms() + 50),
+                TokenScope::Full,
+            )
+            .unwrap();
+        db.upsert_dashboard(&dashboard).unwrap();
+        ::std::thread::sleep(::std::time::Duration::from_millis(100));
+
+        let response = _post_data(
+            "http://localhost:8000/api/dashboard/dashboard-token-expired/tile/tile-test"
+                .to_string(),
+            &expiring,
+            "{}",
+        );
+
+        assert_eq!(response.status, Some(status::Forbidden));
+    }
+
+    #[test]
+    fn write_scoped_token_writes_only_matching_tiles() {
+        test_utils::load_config();
+        let db = db::Db::new().unwrap();
+        let mut dashboard = test_utils::upsert_dashboard(&db, "dashboard-shared");
+        let ci_token = dashboard
+            .add_api_token("ci", None, TokenScope::Write { tiles: "ci-*".to_string() })
+            .unwrap();
+        db.upsert_dashboard(&dashboard).unwrap();
+        let url = "http://localhost:8000/api/dashboard/dashboard-shared".to_string();
+
+        let own = _post_data(format!("{}/tile/ci-build", url), &ci_token, "{}");
+        let other = _post_data(format!("{}/tile/deploy", url), &ci_token, "{}");
+        let many = _post_data(
+            format!("{}/tiles", url),
+            &ci_token,
+            r#"{"ci-test": {}, "deploy": {}}"#,
+        );
+        let deleted = _delete_data(&url, &ci_token);
+
+        assert_eq!(own.status, Some(status::Created));
+        assert_eq!(other.status, Some(status::Forbidden));
+        let statuses: Value = serde_json::from_str(&response::extract_body_to_string(many))
+            .unwrap();
+        assert_eq!(statuses["ci-test"]["status"], 201);
+        assert_eq!(statuses["deploy"]["status"], 403);
+        assert_eq!(db.get_tile(&dashboard.name, "deploy").unwrap(), None);
+        assert_eq!(deleted.status, Some(status::Forbidden));
+    }
+
+    #[test]
+    fn read_scoped_token_can_not_write() {
+        test_utils::load_config();
+        let db = db::Db::new().unwrap();
+        let mut dashboard = test_utils::upsert_dashboard(&db, "dashboard-read-only");
+        let read_token = dashboard
+            .add_api_token("viewer", None, TokenScope::Read)
+            .unwrap();
+        let ci_token = dashboard
+            .add_api_token("ci", None, TokenScope::Write { tiles: "ci-*".to_string() })
+            .unwrap();
+        db.upsert_dashboard(&dashboard).unwrap();
+        db.upsert_tile(&dashboard.name, "deploy", "{}").unwrap();
+        let url = "http://localhost:8000/api/dashboard/dashboard-read-only".to_string();
+
+        let read = _get_data_with_token(&format!("{}/tile/deploy", url), &read_token);
+        let written = _post_data(format!("{}/tile/deploy", url), &read_token, "{}");
+        let history = _get_data_with_token(&format!("{}/tile/deploy/history", url), &ci_token);
+
+        assert_eq!(read.status, Some(status::Ok));
+        assert_eq!(written.status, Some(status::Forbidden));
+        assert_eq!(history.status, Some(status::Forbidden));
+    }
+
+    #[test]
+    fn private_dashboard_tiles_need_read_token() {
+        test_utils::load_config();
+        let db = db::Db::new().unwrap();
+        let mut dashboard = test_utils::upsert_dashboard(&db, "dashboard-private");
+        dashboard.visibility = db::Visibility::Private;
+        let read_token = dashboard
+            .add_api_token("viewer", None, TokenScope::Read)
+            .unwrap();
+        db.upsert_dashboard(&dashboard).unwrap();
+        db.upsert_tile(&dashboard.name, "tile-test", "{}").unwrap();
+        let url = "http://localhost:8000/api/dashboard/dashboard-private".to_string();
+
+        let anonymous = _get_data(&format!("{}/tile/tile-test", url));
+        let listed = _get_data(&format!("{}/tiles", url));
+        let read = _get_data_with_token(&format!("{}/tile/tile-test", url), &read_token);
+
+        assert_eq!(anonymous.status, Some(status::Forbidden));
+        assert_eq!(listed.status, Some(status::Forbidden));
+        assert_eq!(read.status, Some(status::Ok));
+    }
+
+    #[test]
+    fn signed_dashboard_accepts_each_signed_write_once() {
+        test_utils::load_config();
+        let db = db::Db::new().unwrap();
+        let mut dashboard = test_utils::upsert_dashboard(&db, "dashboard-signed");
+        let token = dashboard.get_api_token().unwrap().clone();
+        dashboard.signing_secret = Some("secret".to_string());
+        db.upsert_dashboard(&dashboard).unwrap();
+        let url = "http://localhost:8000/api/dashboard/dashboard-signed/tile/tile-test";
+
+        let unsigned = _post_data(url.to_string(), &token, "{}");
+        let forged = _post_signed(url, &token, "other-secret", "nonce-0", "{}");
+        let signed = _post_signed(url, &token, "secret", "nonce-1", "{}");
+        let replayed = _post_signed(url, &token, "secret", "nonce-1", "{}");
+
+        assert_eq!(unsigned.status, Some(status::Forbidden));
+        assert_eq!(forged.status, Some(status::Forbidden));
+        assert_eq!(signed.status, Some(status::Created));
+        assert_eq!(replayed.status, Some(status::Forbidden));
+    }
+
+    #[test]
+    fn request_over_token_limit_returns_429_with_retry_after() {
+        test_utils::load_config();
+        let db = db::Db::new().unwrap();
+        let dashboard = test_utils::upsert_dashboard(&db, "dashboard-limited");
+        let token = dashboard.get_api_token().unwrap().clone();
+        let mount = mount_limited_by(LimitKind::Token);
+        let url = "http://localhost:8000/api/dashboard/dashboard-limited/tiles";
+
+        let allowed = _get_limited(&mount, url, &token);
+        let limited = _get_limited(&mount, url, &token);
+
+        assert_eq!(allowed.status, Some(status::Ok));
+        assert_eq!(limited.status, Some(status::TooManyRequests));
+        assert_eq!(
+            limited.headers.get_raw("Retry-After"),
+            Some(&[b"1".to_vec()][..])
+        );
+    }
+
+    #[test]
+    fn requests_with_unknown_token_dont_use_dashboard_limit() {
+        test_utils::load_config();
+        let db = db::Db::new().unwrap();
+        let dashboard = test_utils::upsert_dashboard(&db, "dashboard-limited-junk");
+        let token = dashboard.get_api_token().unwrap().clone();
+        let mount = mount_limited_by(LimitKind::Dashboard);
+        let url = "http://localhost:8000/api/dashboard/dashboard-limited-junk/tokens";
+
+        let junk = _get_limited(&mount, url, "junk");
+        let more_junk = _get_limited(&mount, url, "more-junk");
+        let allowed = _get_limited(&mount, url, &token);
+
+        assert_eq!(junk.status, Some(status::Forbidden));
+        assert_eq!(more_junk.status, Some(status::Forbidden));
+        assert_eq!(allowed.status, Some(status::Ok));
+    }
+
+    #[test]
+    fn signing_secret_delete_of_signed_dashboard_has_to_be_signed() {
+        test_utils::load_config();
+        let db = db::Db::new().unwrap();
+        let mut dashboard = test_utils::upsert_dashboard(&db, "dashboard-signed-delete");
+        let token = dashboard.get_api_token().unwrap().clone();
+        dashboard.signing_secret = Some("secret".to_string());
+        db.upsert_dashboard(&dashboard).unwrap();
+        let url = "http://localhost:8000/api/dashboard/dashboard-signed-delete/signing-secret";
+
+        let unsigned = _delete_data(url, &token);
+        let reposted = _post_data(url.to_string(), &token, "");
+
+        assert_eq!(unsigned.status, Some(status::Forbidden));
+        assert_eq!(reposted.status, Some(status::Forbidden));
+        let stored = db.get_dashboard("dashboard-signed-delete").unwrap().unwrap();
+        assert_eq!(stored.signing_secret, Some("secret".to_string()));
+    }
+
     #[test]
     fn static_url_gives_200() {
-        utils::load_config(None);
+        test_utils::load_config();
 
         let response = request::get(
             "http://localhost:8000/static/elements.html",
@@ -220,7 +1138,7 @@ mod tests {
 
     #[test]
     fn tile_post_gives_err_when_token_missing() {
-        utils::load_config(None);
+        test_utils::load_config();
 
         let response = request::post(
             "http://localhost:8000/api/dashboard/dashboard-test/tile/tile-test",
@@ -236,7 +1154,7 @@ mod tests {
 
     #[test]
     fn tile_post_gives_err_when_token_is_different() {
-        utils::load_config(None);
+        test_utils::load_config();
         let db = db::Db::new().unwrap();
         let dashboard_name = "dashboard-test";
         test_utils::upsert_dashboard(&db, &dashboard_name);
@@ -253,6 +1171,9 @@ mod tests {
 
         assert_eq!(response.status, Some(status::Forbidden));
         let body = response::extract_body_to_string(response);
-        assert_eq!(body, "Tokens unmatched");
+        assert_eq!(
+            body,
+            "{\"code\":\"auth-failed\",\"message\":\"Tokens unmatched\"}"
+        );
     }
 }
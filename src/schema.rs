@@ -0,0 +1,325 @@
+//! Validation of tile data against JSON Schemas registered by dashboards
+//!
+//! Only structural keywords are supported: `type`, `enum`, `const`,
+//! `properties`, `required`, `additionalProperties`, `items`, `minItems`,
+//! `maxItems`, `minimum`, `maximum`, `exclusiveMinimum`, `exclusiveMaximum`,
+//! `minLength` and `maxLength`. Schemas with other keywords (but ones which
+//! only describe data, like `title`) are rejected, so none of them looks
+//! enforced without being so.
+
+use serde_json;
+use serde_json::Value;
+
+
+/// Part of data which doesn't match schema
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SchemaViolation {
+    /// JSON pointer (RFC 6901) to the mismatched value, `""` for whole data
+    pub path: String,
+    pub message: String,
+}
+
+
+/// Keywords checked by `validate`
+const SUPPORTED_KEYWORDS: &'static [&'static str] = &[
+    "type",
+    "enum",
+    "const",
+    "properties",
+    "required",
+    "additionalProperties",
+    "items",
+    "minItems",
+    "maxItems",
+    "minimum",
+    "maximum",
+    "exclusiveMinimum",
+    "exclusiveMaximum",
+    "minLength",
+    "maxLength",
+];
+
+/// Keywords which don't take part in validation
+const ANNOTATION_KEYWORDS: &'static [&'static str] = &[
+    "$schema",
+    "$id",
+    "$comment",
+    "title",
+    "description",
+    "default",
+    "examples",
+    "readOnly",
+    "writeOnly",
+    "deprecated",
+];
+
+
+/// Checks whether `schema` can be used for validation, error names
+/// unsupported keywords with paths to them
+pub fn check_schema(schema: &Value) -> Result<(), String> {
+    let mut unsupported = Vec::new();
+    check_schema_at(schema, "", &mut unsupported)?;
+    if !unsupported.is_empty() {
+        return Err(format!("Unsupported keywords: {}", unsupported.join(", ")));
+    }
+    Ok(())
+}
+
+fn check_schema_at(
+    schema: &Value,
+    path: &str,
+    unsupported: &mut Vec<String>,
+) -> Result<(), String> {
+    let schema = match *schema {
+        Value::Bool(_) => return Ok(()),
+        Value::Object(ref v) => v,
+        _ => {
+            return Err(format!(
+                "Schema at '{}' should be an object or a boolean",
+                path
+            ))
+        }
+    };
+    for (keyword, value) in schema {
+        let keyword_path = child_path(path, keyword);
+        match keyword.as_str() {
+            "properties" => {
+                let properties = value.as_object().ok_or_else(|| {
+                    format!("Value at '{}' should be an object", keyword_path)
+                })?;
+                for (key, sub_schema) in properties {
+                    check_schema_at(sub_schema, &child_path(&keyword_path, key), unsupported)?;
+                }
+            }
+            "additionalProperties" | "items" => {
+                check_schema_at(value, &keyword_path, unsupported)?
+            }
+            k if SUPPORTED_KEYWORDS.contains(&k) || ANNOTATION_KEYWORDS.contains(&k) => (),
+            _ => unsupported.push(format!("'{}'", keyword_path)),
+        }
+    }
+    Ok(())
+}
+
+/// Returns every place where `data` doesn't match `schema`, empty when it
+/// matches
+pub fn validate(schema: &Value, data: &Value) -> Vec<SchemaViolation> {
+    let mut violations = Vec::new();
+    validate_at(schema, data, "", &mut violations);
+    violations
+}
+
+fn validate_at(schema: &Value, data: &Value, path: &str, violations: &mut Vec<SchemaViolation>) {
+    let schema = match *schema {
+        Value::Bool(true) => return,
+        Value::Bool(false) => {
+            violate(violations, path, "No value is allowed here".to_string());
+            return;
+        }
+        Value::Object(ref v) => v,
+        _ => return,
+    };
+
+    if let Some(expected) = schema.get("type") {
+        let names: Vec<&str> = match *expected {
+            Value::String(ref name) => vec![name.as_str()],
+            Value::Array(ref names) => names.iter().filter_map(|n| n.as_str()).collect(),
+            _ => vec![],
+        };
+        if !names.is_empty() && !names.iter().any(|name| has_type(data, name)) {
+            violate(
+                violations,
+                path,
+                format!("Expected {}, found {}", names.join(" or "), type_name(data)),
+            );
+            // checks below assume type matches
+            return;
+        }
+    }
+    if let Some(&Value::Array(ref allowed)) = schema.get("enum") {
+        if !allowed.contains(data) {
+            violate(violations, path, "Value isn't one of allowed ones".to_string());
+        }
+    }
+    if let Some(expected) = schema.get("const") {
+        if expected != data {
+            violate(violations, path, format!("Expected {}", expected));
+        }
+    }
+
+    match *data {
+        Value::Object(ref obj) => {
+            if let Some(&Value::Array(ref required)) = schema.get("required") {
+                for key in required.iter().filter_map(|k| k.as_str()) {
+                    if !obj.contains_key(key) {
+                        violate(
+                            violations,
+                            &child_path(path, key),
+                            "Required field is missing".to_string(),
+                        );
+                    }
+                }
+            }
+            let properties = schema.get("properties").and_then(|p| p.as_object());
+            for (key, value) in obj {
+                let sub_schema = match properties.and_then(|p| p.get(key)) {
+                    Some(v) => v,
+                    None => match schema.get("additionalProperties") {
+                        Some(v) => v,
+                        None => continue,
+                    },
+                };
+                validate_at(sub_schema, value, &child_path(path, key), violations);
+            }
+        }
+        Value::Array(ref items) => {
+            check_bound(schema, "minItems", items.len() as f64, path, violations);
+            check_bound(schema, "maxItems", items.len() as f64, path, violations);
+            if let Some(item_schema) = schema.get("items") {
+                for (idx, item) in items.iter().enumerate() {
+                    validate_at(item_schema, item, &child_path(path, &idx.to_string()), violations);
+                }
+            }
+        }
+        Value::String(ref s) => {
+            let length = s.chars().count() as f64;
+            check_bound(schema, "minLength", length, path, violations);
+            check_bound(schema, "maxLength", length, path, violations);
+        }
+        Value::Number(ref n) => {
+            let number = n.as_f64().unwrap_or(0.0);
+            for keyword in &["minimum", "maximum", "exclusiveMinimum", "exclusiveMaximum"] {
+                check_bound(schema, keyword, number, path, violations);
+            }
+        }
+        _ => (),
+    }
+}
+
+/// Checks `value` against limit at `keyword` (when schema has it)
+fn check_bound(
+    schema: &serde_json::Map<String, Value>,
+    keyword: &str,
+    value: f64,
+    path: &str,
+    violations: &mut Vec<SchemaViolation>,
+) {
+    let limit = match schema.get(keyword).and_then(|l| l.as_f64()) {
+        None => return,
+        Some(v) => v,
+    };
+    let (holds, relation) = match keyword {
+        "minimum" | "minItems" | "minLength" => (value >= limit, "at least"),
+        "maximum" | "maxItems" | "maxLength" => (value <= limit, "at most"),
+        "exclusiveMinimum" => (value > limit, "greater than"),
+        "exclusiveMaximum" => (value < limit, "less than"),
+        _ => (true, ""),
+    };
+    if !holds {
+        let what = match keyword {
+            "minItems" | "maxItems" => "Number of items",
+            "minLength" | "maxLength" => "Length",
+            _ => "Value",
+        };
+        violate(
+            violations,
+            path,
+            format!("{} should be {} {}", what, relation, limit),
+        );
+    }
+}
+
+fn violate(violations: &mut Vec<SchemaViolation>, path: &str, message: String) {
+    violations.push(SchemaViolation {
+        path: path.to_string(),
+        message: message,
+    });
+}
+
+/// Returns JSON pointer to `key` of value at `path`
+fn child_path(path: &str, key: &str) -> String {
+    format!("{}/{}", path, key.replace('~', "~0").replace('/', "~1"))
+}
+
+fn has_type(data: &Value, name: &str) -> bool {
+    match name {
+        "integer" => data.as_f64().map_or(false, |n| n.fract() == 0.0),
+        "number" => data.is_number(),
+        _ => type_name(data) == name,
+    }
+}
+
+fn type_name(data: &Value) -> &'static str {
+    match *data {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_schema_names_unsupported_keywords() {
+        let schema = json_value(
+            r#"{"type": "object", "title": "Status", "oneOf": [],
+                "properties": {"name": {"type": "string", "pattern": "^a"}}}"#,
+        );
+
+        assert_eq!(
+            check_schema(&schema),
+            Err("Unsupported keywords: '/oneOf', '/properties/name/pattern'".to_string())
+        );
+        assert_eq!(check_schema(&json_value(r#"{"items": {"minimum": 0}}"#)), Ok(()));
+    }
+
+    #[test]
+    fn validate_returns_nothing_when_data_matches() {
+        let schema = json_value(
+            r#"{"type": "object", "required": ["value"],
+                "properties": {"value": {"type": "number", "minimum": 0}}}"#,
+        );
+
+        let violations = validate(&schema, &json_value(r#"{"value": 3, "extra": true}"#));
+
+        assert_eq!(violations, vec![]);
+    }
+
+    #[test]
+    fn validate_returns_paths_of_mismatched_fields() {
+        let schema = json_value(
+            r#"{"type": "object", "required": ["title"], "additionalProperties": false,
+                "properties": {"data": {"type": "array", "items": {"type": "integer"}}}}"#,
+        );
+
+        let violations = validate(&schema, &json_value(r#"{"data": [1, "2"], "x": 1}"#));
+
+        assert_eq!(
+            violations,
+            vec![
+                SchemaViolation {
+                    path: "/title".to_string(),
+                    message: "Required field is missing".to_string(),
+                },
+                SchemaViolation {
+                    path: "/data/1".to_string(),
+                    message: "Expected integer, found string".to_string(),
+                },
+                SchemaViolation {
+                    path: "/x".to_string(),
+                    message: "No value is allowed here".to_string(),
+                },
+            ]
+        );
+    }
+
+    fn json_value(json: &str) -> Value {
+        serde_json::from_str(json).unwrap()
+    }
+}
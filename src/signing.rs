@@ -0,0 +1,131 @@
+//! Signing of ingest requests
+//!
+//! Token sent in bare `Authorization` header can be replayed by anyone who
+//! sees the request, so dashboard can require every write made with its
+//! tokens (tiles, tokens, signing secret and the dashboard itself) to be
+//! signed with its signing secret as well. Feeder sends `X-Dashboard-Timestamp`
+//! (milliseconds since unix epoch), `X-Dashboard-Nonce` (unique per request)
+//! and `X-Dashboard-Signature`: hex of HMAC-SHA256 of
+//! `{method}\n{path}\n{timestamp}\n{nonce}\n{body}` keyed with the secret,
+//! where path includes query string. Requests with timestamp further than
+//! `DASHBOARD_SIGNATURE_WINDOW_SECS` from now are rejected and nonces are
+//! remembered for that long, so request can't be sent twice.
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use utils::{constant_time_eq, from_config, to_hex};
+use uuid::Uuid;
+
+
+pub const TIMESTAMP_HEADER: &'static str = "X-Dashboard-Timestamp";
+pub const NONCE_HEADER: &'static str = "X-Dashboard-Nonce";
+pub const SIGNATURE_HEADER: &'static str = "X-Dashboard-Signature";
+
+
+/// Parts of request covered by signature
+#[derive(Debug, Clone, PartialEq)]
+pub struct SignedRequest<'a> {
+    pub method: &'a str,
+    pub path: &'a str,
+    /// milliseconds since unix epoch
+    pub timestamp: i64,
+    pub nonce: &'a str,
+    pub body: &'a str,
+}
+
+impl<'a> SignedRequest<'a> {
+    /// Returns hex of HMAC-SHA256 of request keyed with `secret`
+    pub fn sign(&self, secret: &str) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+            .expect("HMAC takes key of any size");
+        mac.update(
+            format!(
+                "{}\n{}\n{}\n{}\n",
+                self.method,
+                self.path,
+                self.timestamp,
+                self.nonce
+            ).as_bytes(),
+        );
+        mac.update(self.body.as_bytes());
+        to_hex(&mac.finalize().into_bytes())
+    }
+
+    /// Checks whether `signature` is signature of request made with `secret`
+    pub fn verify(&self, secret: &str, signature: &str) -> bool {
+        constant_time_eq(
+            self.sign(secret).as_bytes(),
+            signature.to_lowercase().as_bytes(),
+        )
+    }
+
+    /// Checks whether request was signed within `window` milliseconds from
+    /// `now`
+    pub fn is_fresh(&self, now: i64, window: i64) -> bool {
+        (now - self.timestamp).abs() <= window
+    }
+}
+
+
+/// Returns newly generated signing secret
+pub fn generate_secret() -> String {
+    format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple())
+}
+
+/// Reads `DASHBOARD_SIGNATURE_WINDOW_SECS` as milliseconds
+pub fn window_from_config() -> i64 {
+    let window_secs: i64 = from_config("DASHBOARD_SIGNATURE_WINDOW_SECS")
+        .parse()
+        .expect("DASHBOARD_SIGNATURE_WINDOW_SECS is not a number");
+    window_secs * 1000
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(body: &str) -> SignedRequest {
+        SignedRequest {
+            method: "POST",
+            path: "/api/dashboard/shared/tile/ci-build",
+            timestamp: 1000,
+            nonce: "n1",
+            body: body,
+        }
+    }
+
+    #[test]
+    fn verify_fails_when_request_was_changed() {
+        let signature = request("{}").sign("secret");
+
+        assert!(request("{}").verify("secret", &signature));
+        assert!(request("{}").verify("secret", &signature.to_uppercase()));
+        assert!(!request("{\"x\": 1}").verify("secret", &signature));
+        assert!(!request("{}").verify("other-secret", &signature));
+    }
+
+    #[test]
+    fn sign_matches_reference_hmac() {
+        // printf 'POST\n/p\n1\nn\n{}' | openssl dgst -sha256 -hmac key
+        let signed = SignedRequest {
+            method: "POST",
+            path: "/p",
+            timestamp: 1,
+            nonce: "n",
+            body: "{}",
+        };
+
+        assert_eq!(
+            signed.sign("key"),
+            "c730043c562b8af1d8c450ef86a320fc5798f1abc0f09c0527eda8276c752c36"
+        );
+    }
+
+    #[test]
+    fn is_fresh_fails_outside_window() {
+        assert!(request("").is_fresh(1500, 500));
+        assert!(request("").is_fresh(500, 500));
+        assert!(!request("").is_fresh(1501, 500));
+    }
+}
@@ -0,0 +1,111 @@
+//! In-process pub/sub for backends which don't have their own
+
+use error::DashboardError;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::sync::mpsc;
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender};
+use std::time::Duration;
+use storage::Subscription;
+
+
+#[derive(Default)]
+pub struct Broker {
+    subscribers: Mutex<HashMap<String, Vec<Sender<String>>>>,
+}
+
+impl Broker {
+    pub fn new() -> Broker {
+        Broker::default()
+    }
+
+    /// Sends `message` to every live subscriber of `channel`
+    pub fn publish(&self, channel: &str, message: &str) -> Result<(), DashboardError> {
+        let mut subscribers = self.subscribers
+            .lock()
+            .map_err(|_| DashboardError::Internal("Broker is poisoned".to_string()))?;
+        if let Some(senders) = subscribers.get_mut(channel) {
+            // receivers which are gone are dropped here
+            senders.retain(|sender| sender.send(message.to_string()).is_ok());
+        }
+        Ok(())
+    }
+
+    /// Registers new subscriber of `channel`
+    pub fn subscribe(&self, channel: &str) -> Result<Box<Subscription>, DashboardError> {
+        let (sender, receiver) = mpsc::channel();
+        self.subscribers
+            .lock()
+            .map_err(|_| DashboardError::Internal("Broker is poisoned".to_string()))?
+            .entry(channel.to_string())
+            .or_insert_with(Vec::new)
+            .push(sender);
+        Ok(Box::new(BrokerSubscription { receiver: receiver }))
+    }
+
+    #[cfg(test)]
+    fn subscribers_count(&self, channel: &str) -> usize {
+        self.subscribers
+            .lock()
+            .unwrap()
+            .get(channel)
+            .map_or(0, |senders| senders.len())
+    }
+}
+
+
+struct BrokerSubscription {
+    receiver: Receiver<String>,
+}
+
+impl Subscription for BrokerSubscription {
+    fn get_message(&mut self, timeout: Duration) -> Result<Option<String>, DashboardError> {
+        match self.receiver.recv_timeout(timeout) {
+            Ok(v) => Ok(Some(v)),
+            Err(RecvTimeoutError::Timeout) => Ok(None),
+            Err(RecvTimeoutError::Disconnected) => Err(DashboardError::Internal(
+                "Broker is gone".to_string(),
+            )),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn subscription_gets_published_message() {
+        let broker = Broker::new();
+        let mut subscription = broker.subscribe("channel").unwrap();
+
+        broker.publish("channel", "tile-id").unwrap();
+
+        assert_eq!(
+            subscription.get_message(Duration::from_millis(10)).unwrap(),
+            Some("tile-id".to_string())
+        );
+    }
+
+    #[test]
+    fn get_message_returns_none_after_timeout() {
+        let broker = Broker::new();
+        let mut subscription = broker.subscribe("channel").unwrap();
+
+        assert_eq!(
+            subscription.get_message(Duration::from_millis(10)).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn publish_forgets_dropped_subscriptions() {
+        let broker = Broker::new();
+        drop(broker.subscribe("channel").unwrap());
+
+        broker.publish("channel", "tile-id").unwrap();
+
+        assert_eq!(broker.subscribers_count("channel"), 0);
+    }
+}
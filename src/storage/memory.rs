@@ -0,0 +1,752 @@
+//! In-process backend, nothing survives restart
+//!
+//! Handy for demos and tests, pub/sub is done by `Broker`.
+
+use db::{Dashboard, Retention, StoredTile, TileSample};
+use error::DashboardError;
+use metrics::MetricPoint;
+use serde_json;
+#[cfg(test)]
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+use storage::{Storage, Subscription};
+use storage::broker::Broker;
+
+
+/// Values keyed by dashboard name and then by their own key, so everything of
+/// dashboard is found without scanning other dashboards
+type ByDashboard<T> = HashMap<String, HashMap<String, T>>;
+
+#[derive(Default)]
+struct State {
+    dashboards: HashMap<String, Dashboard>,
+    /// tiles by tile id
+    tiles: ByDashboard<StoredTile>,
+    /// tile samples by tile id, oldest first
+    history: ByDashboard<Vec<TileSample>>,
+    /// metric points by tile id, oldest first
+    metrics: ByDashboard<Vec<MetricPoint>>,
+    /// time of last token use by token name
+    api_tokens_used: ByDashboard<i64>,
+    /// expiry of nonces of signed requests by nonce
+    nonces: ByDashboard<i64>,
+}
+
+
+lazy_static! {
+    static ref SHARED_STATE: Arc<Mutex<State>> = Arc::new(Mutex::new(State::default()));
+    static ref SHARED_BROKER: Arc<Broker> = Arc::new(Broker::new());
+}
+
+#[cfg(test)]
+thread_local! {
+    /// storage of test which keeps its data apart from other tests
+    static OWN: RefCell<Option<MemoryStorage>> = RefCell::new(None);
+}
+
+
+/// Appends `sample` to `samples` (oldest first) and drops ones which don't fit
+/// in `retention`
+fn push_sample(samples: &mut Vec<TileSample>, sample: &TileSample, retention: &Retention) {
+    samples.push(sample.clone());
+    if let Some(oldest) = retention.oldest_kept(sample.timestamp) {
+        samples.retain(|s| s.timestamp >= oldest);
+    }
+    if let Some(max_samples) = retention.max_samples {
+        if samples.len() > max_samples {
+            let excess = samples.len() - max_samples;
+            samples.drain(..excess);
+        }
+    }
+}
+
+
+/// Returns value at `key` of `dashboard_name`
+fn get<'a, T>(map: &'a ByDashboard<T>, dashboard_name: &str, key: &str) -> Option<&'a T> {
+    map.get(dashboard_name).and_then(|values| values.get(key))
+}
+
+/// Returns values of `dashboard_name`, empty ones are added when it has none
+fn values_mut<'a, T>(
+    map: &'a mut ByDashboard<T>,
+    dashboard_name: &str,
+) -> &'a mut HashMap<String, T> {
+    map.entry(dashboard_name.to_string()).or_default()
+}
+
+/// Removes value at `key` of `dashboard_name`, dashboard is forgotten when
+/// it's left without values
+fn remove<T>(map: &mut ByDashboard<T>, dashboard_name: &str, key: &str) -> Option<T> {
+    let (removed, is_empty) = match map.get_mut(dashboard_name) {
+        None => return None,
+        Some(values) => (values.remove(key), values.is_empty()),
+    };
+    if is_empty {
+        map.remove(dashboard_name);
+    }
+    removed
+}
+
+/// Moves values of `from` to `to`
+fn move_values<T>(map: &mut ByDashboard<T>, from: &str, to: &str) {
+    if let Some(values) = map.remove(from) {
+        map.insert(to.to_string(), values);
+    }
+}
+
+
+#[derive(Clone)]
+pub struct MemoryStorage {
+    state: Arc<Mutex<State>>,
+    broker: Arc<Broker>,
+}
+
+impl MemoryStorage {
+    /// Returns storage shared by whole process (or own storage of thread, see
+    /// `use_own`)
+    pub fn shared() -> MemoryStorage {
+        #[cfg(test)]
+        {
+            if let Some(own) = OWN.with(|own| own.borrow().clone()) {
+                return own;
+            }
+        }
+        MemoryStorage {
+            state: SHARED_STATE.clone(),
+            broker: SHARED_BROKER.clone(),
+        }
+    }
+
+    /// Returns storage which isn't shared with anyone
+    #[cfg(test)]
+    pub fn new() -> MemoryStorage {
+        MemoryStorage {
+            state: Arc::new(Mutex::new(State::default())),
+            broker: Arc::new(Broker::new()),
+        }
+    }
+
+    /// Makes `shared` return new storage in calling thread, so test running
+    /// in it neither sees data of other tests nor changes it
+    #[cfg(test)]
+    pub fn use_own() {
+        OWN.with(|own| *own.borrow_mut() = Some(MemoryStorage::new()));
+    }
+
+    fn state(&self) -> Result<MutexGuard<State>, DashboardError> {
+        self.state.lock().map_err(|_| {
+            DashboardError::Internal("Memory storage is poisoned".to_string())
+        })
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn create_dashboard(&self, dashboard: &Dashboard) -> Result<(), DashboardError> {
+        let mut state = self.state()?;
+        if state.dashboards.contains_key(&dashboard.name) {
+            return Err(DashboardError::Conflict(
+                format!("Dashboard {} already exists", dashboard.name),
+            ));
+        }
+        state
+            .dashboards
+            .insert(dashboard.name.clone(), dashboard.clone());
+        Ok(())
+    }
+
+    fn upsert_dashboard(&self, dashboard: &Dashboard) -> Result<(), DashboardError> {
+        self.state()?
+            .dashboards
+            .insert(dashboard.name.clone(), dashboard.clone());
+        Ok(())
+    }
+
+    fn get_dashboards(&self) -> Result<Vec<Dashboard>, DashboardError> {
+        Ok(self.state()?.dashboards.values().cloned().collect())
+    }
+
+    fn get_dashboard(&self, dashboard_name: &str) -> Result<Option<Dashboard>, DashboardError> {
+        Ok(self.state()?.dashboards.get(dashboard_name).cloned())
+    }
+
+    fn rename_dashboard(
+        &self,
+        dashboard_name: &str,
+        dashboard: &Dashboard,
+    ) -> Result<(), DashboardError> {
+        let mut state = self.state()?;
+        if !state.dashboards.contains_key(dashboard_name) {
+            return Err(DashboardError::NotFound("Dashboard doesn't exist".to_string()));
+        }
+        if state.dashboards.contains_key(&dashboard.name) {
+            return Err(DashboardError::Conflict(
+                format!("Dashboard {} already exists", dashboard.name),
+            ));
+        }
+        state.dashboards.remove(dashboard_name);
+        state
+            .dashboards
+            .insert(dashboard.name.clone(), dashboard.clone());
+        move_values(&mut state.tiles, dashboard_name, &dashboard.name);
+        move_values(&mut state.history, dashboard_name, &dashboard.name);
+        move_values(&mut state.metrics, dashboard_name, &dashboard.name);
+        move_values(&mut state.api_tokens_used, dashboard_name, &dashboard.name);
+        move_values(&mut state.nonces, dashboard_name, &dashboard.name);
+        Ok(())
+    }
+
+    fn delete_dashboard(&self, dashboard_name: &str) -> Result<u64, DashboardError> {
+        let mut state = self.state()?;
+        state.tiles.remove(dashboard_name);
+        state.history.remove(dashboard_name);
+        state.metrics.remove(dashboard_name);
+        state.api_tokens_used.remove(dashboard_name);
+        state.nonces.remove(dashboard_name);
+        match state.dashboards.remove(dashboard_name) {
+            None => Ok(0),
+            Some(_) => Ok(1),
+        }
+    }
+
+    fn get_tile(
+        &self,
+        dashboard_name: &str,
+        tile_id: &str,
+    ) -> Result<Option<StoredTile>, DashboardError> {
+        Ok(get(&self.state()?.tiles, dashboard_name, tile_id).cloned())
+    }
+
+    fn get_tiles(&self, dashboard_name: &str) -> Result<Vec<StoredTile>, DashboardError> {
+        Ok(self.state()?
+            .tiles
+            .get(dashboard_name)
+            .map_or_else(Vec::new, |tiles| tiles.values().cloned().collect()))
+    }
+
+    fn upsert_tile(
+        &self,
+        dashboard_name: &str,
+        tile_id: &str,
+        tile_json: &str,
+        updated_at: i64,
+        expected_version: Option<u64>,
+    ) -> Result<Option<u64>, DashboardError> {
+        let mut state = self.state()?;
+        let tiles = values_mut(&mut state.tiles, dashboard_name);
+        let version = tiles.get(tile_id).map_or(0, |tile| tile.version);
+        if expected_version.map_or(false, |expected| expected != version) {
+            return Ok(None);
+        }
+        tiles.insert(
+            tile_id.to_string(),
+            StoredTile {
+                tile_id: tile_id.to_string(),
+                data: tile_json.to_string(),
+                updated_at: Some(updated_at),
+                version: version + 1,
+            },
+        );
+        Ok(Some(version + 1))
+    }
+
+    fn upsert_tiles(
+        &self,
+        dashboard_name: &str,
+        tiles: &[(String, TileSample)],
+        retention: &Retention,
+    ) -> Result<(), DashboardError> {
+        let mut jsons = Vec::with_capacity(tiles.len());
+        for &(_, ref sample) in tiles {
+            jsons.push(serde_json::to_string(&sample.data)?);
+        }
+        let mut state = self.state()?;
+        for (&(ref tile_id, ref sample), json) in tiles.iter().zip(jsons) {
+            let stored = values_mut(&mut state.tiles, dashboard_name);
+            let version = stored.get(tile_id).map_or(0, |tile| tile.version);
+            stored.insert(
+                tile_id.clone(),
+                StoredTile {
+                    tile_id: tile_id.clone(),
+                    data: json,
+                    updated_at: Some(sample.timestamp),
+                    version: version + 1,
+                },
+            );
+            push_sample(
+                values_mut(&mut state.history, dashboard_name)
+                    .entry(tile_id.clone())
+                    .or_insert_with(Vec::new),
+                sample,
+                retention,
+            );
+        }
+        Ok(())
+    }
+
+    fn delete_tile(&self, dashboard_name: &str, tile_id: &str) -> Result<bool, DashboardError> {
+        let mut state = self.state()?;
+        remove(&mut state.history, dashboard_name, tile_id);
+        remove(&mut state.metrics, dashboard_name, tile_id);
+        Ok(remove(&mut state.tiles, dashboard_name, tile_id).is_some())
+    }
+
+    fn add_tile_sample(
+        &self,
+        dashboard_name: &str,
+        tile_id: &str,
+        sample: &TileSample,
+        retention: &Retention,
+    ) -> Result<(), DashboardError> {
+        let mut state = self.state()?;
+        push_sample(
+            values_mut(&mut state.history, dashboard_name)
+                .entry(tile_id.to_string())
+                .or_insert_with(Vec::new),
+            sample,
+            retention,
+        );
+        Ok(())
+    }
+
+    fn get_tile_history(
+        &self,
+        dashboard_name: &str,
+        tile_id: &str,
+        from: i64,
+        to: i64,
+        limit: usize,
+    ) -> Result<Vec<TileSample>, DashboardError> {
+        let state = self.state()?;
+        let in_range: Vec<&TileSample> = match get(&state.history, dashboard_name, tile_id) {
+            None => return Ok(Vec::new()),
+            Some(samples) => {
+                samples
+                    .iter()
+                    .filter(|s| s.timestamp >= from && s.timestamp <= to)
+                    .collect()
+            }
+        };
+        let to_skip = in_range.len().saturating_sub(limit);
+        Ok(in_range.into_iter().skip(to_skip).cloned().collect())
+    }
+
+    fn add_metric_point(
+        &self,
+        dashboard_name: &str,
+        tile_id: &str,
+        point: &MetricPoint,
+        max_points: usize,
+    ) -> Result<(), DashboardError> {
+        let mut state = self.state()?;
+        let points = values_mut(&mut state.metrics, dashboard_name)
+            .entry(tile_id.to_string())
+            .or_insert_with(Vec::new);
+        points.push(point.clone());
+        if points.len() > max_points {
+            let excess = points.len() - max_points;
+            points.drain(..excess);
+        }
+        Ok(())
+    }
+
+    fn get_metric_points(
+        &self,
+        dashboard_name: &str,
+        tile_id: &str,
+        from: i64,
+        to: i64,
+    ) -> Result<Vec<MetricPoint>, DashboardError> {
+        let state = self.state()?;
+        Ok(get(&state.metrics, dashboard_name, tile_id).map_or_else(Vec::new, |points| {
+            points
+                .iter()
+                .filter(|p| p.timestamp >= from && p.timestamp <= to)
+                .cloned()
+                .collect()
+        }))
+    }
+
+    fn compact_metric_points(
+        &self,
+        dashboard_name: &str,
+        tile_id: &str,
+        before: i64,
+        points: &[MetricPoint],
+    ) -> Result<(), DashboardError> {
+        let mut state = self.state()?;
+        let stored = values_mut(&mut state.metrics, dashboard_name)
+            .entry(tile_id.to_string())
+            .or_insert_with(Vec::new);
+        stored.retain(|p| p.timestamp >= before);
+        let mut compacted = points.to_vec();
+        compacted.extend(stored.drain(..));
+        *stored = compacted;
+        Ok(())
+    }
+
+    fn get_metric_series(&self) -> Result<Vec<(String, String)>, DashboardError> {
+        let state = self.state()?;
+        let mut series = Vec::new();
+        for (dashboard_name, points) in &state.metrics {
+            for tile_id in points.keys() {
+                series.push((dashboard_name.clone(), tile_id.clone()));
+            }
+        }
+        Ok(series)
+    }
+
+    fn set_api_token_used(
+        &self,
+        dashboard_name: &str,
+        token_name: &str,
+        used_at: i64,
+    ) -> Result<(), DashboardError> {
+        let mut state = self.state()?;
+        values_mut(&mut state.api_tokens_used, dashboard_name)
+            .insert(token_name.to_string(), used_at);
+        Ok(())
+    }
+
+    fn get_api_tokens_used(
+        &self,
+        dashboard_name: &str,
+    ) -> Result<HashMap<String, i64>, DashboardError> {
+        Ok(self.state()?
+            .api_tokens_used
+            .get(dashboard_name)
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    fn add_nonce(
+        &self,
+        dashboard_name: &str,
+        nonce: &str,
+        now: i64,
+        expires_at: i64,
+    ) -> Result<bool, DashboardError> {
+        let mut state = self.state()?;
+        for nonces in state.nonces.values_mut() {
+            nonces.retain(|_, nonce_expires_at| *nonce_expires_at > now);
+        }
+        state.nonces.retain(|_, nonces| !nonces.is_empty());
+        let nonces = values_mut(&mut state.nonces, dashboard_name);
+        if nonces.contains_key(nonce) {
+            return Ok(false);
+        }
+        nonces.insert(nonce.to_string(), expires_at);
+        Ok(true)
+    }
+
+    fn publish(&self, channel: &str, message: &str) -> Result<(), DashboardError> {
+        self.broker.publish(channel, message)
+    }
+
+    fn subscribe(&self, channel: &str) -> Result<Box<Subscription>, DashboardError> {
+        self.broker.subscribe(channel)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn dashboard(name: &str) -> Dashboard {
+        Dashboard::new(
+            name.to_string(),
+            "login@email.com".to_string(),
+            "2x4".to_string(),
+        )
+    }
+
+    #[test]
+    fn create_dashboard_fails_when_name_is_taken() {
+        let storage = MemoryStorage::new();
+        storage.create_dashboard(&dashboard("taken")).unwrap();
+
+        let result = storage.create_dashboard(&dashboard("taken"));
+
+        assert_eq!(
+            result,
+            Err(DashboardError::Conflict(
+                "Dashboard taken already exists".to_string(),
+            ))
+        );
+    }
+
+    #[test]
+    fn get_tile_returns_upserted_tile() {
+        let storage = MemoryStorage::new();
+
+        storage.upsert_tile("dashboard", "tile", "{}", 1, None).unwrap();
+
+        assert_eq!(
+            storage.get_tile("dashboard", "tile").unwrap().map(|tile| tile.data),
+            Some("{}".to_string())
+        );
+        assert_eq!(storage.get_tile("dashboard", "other").unwrap(), None);
+    }
+
+    #[test]
+    fn upsert_tile_skips_write_when_version_is_not_expected_one() {
+        let storage = MemoryStorage::new();
+        assert_eq!(
+            storage.upsert_tile("dashboard", "tile", "{}", 1, Some(0)).unwrap(),
+            Some(1)
+        );
+
+        let result = storage.upsert_tile("dashboard", "tile", "{\"a\":1}", 2, Some(0));
+
+        assert_eq!(result.unwrap(), None);
+        assert_eq!(
+            storage.get_tile("dashboard", "tile").unwrap().map(|tile| tile.data),
+            Some("{}".to_string())
+        );
+        assert_eq!(
+            storage.upsert_tile("dashboard", "tile", "{}", 3, Some(1)).unwrap(),
+            Some(2)
+        );
+    }
+
+    #[test]
+    fn get_tiles_returns_only_tiles_of_dashboard() {
+        let storage = MemoryStorage::new();
+        storage.upsert_tile("dashboard", "tile", "{}", 7, None).unwrap();
+        storage.upsert_tile("other", "tile", "{}", 8, None).unwrap();
+
+        let tiles = storage.get_tiles("dashboard").unwrap();
+
+        assert_eq!(
+            tiles,
+            vec![
+                StoredTile {
+                    tile_id: "tile".to_string(),
+                    data: "{}".to_string(),
+                    updated_at: Some(7),
+                    version: 1,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn upsert_tiles_saves_tiles_with_their_samples() {
+        let storage = MemoryStorage::new();
+        let retention = Retention {
+            max_samples: None,
+            max_age: None,
+        };
+        let sample = TileSample {
+            timestamp: 5,
+            data: json!({"tile-id": "tile"}),
+        };
+
+        storage
+            .upsert_tiles("dashboard", &[("tile".to_string(), sample.clone())], &retention)
+            .unwrap();
+
+        assert_eq!(
+            storage.get_tile("dashboard", "tile").unwrap().map(|tile| tile.data),
+            Some("{\"tile-id\":\"tile\"}".to_string())
+        );
+        assert_eq!(
+            storage
+                .get_tile_history("dashboard", "tile", 0, 10, 10)
+                .unwrap(),
+            vec![sample]
+        );
+    }
+
+    #[test]
+    fn subscription_gets_published_message() {
+        let storage = MemoryStorage::new();
+        let mut subscription = storage.subscribe("channel").unwrap();
+
+        storage.publish("channel", "tile-id").unwrap();
+
+        assert_eq!(
+            subscription.get_message(Duration::from_millis(10)).unwrap(),
+            Some("tile-id".to_string())
+        );
+    }
+
+    fn sample(timestamp: i64) -> TileSample {
+        TileSample {
+            timestamp: timestamp,
+            data: json!({"value": timestamp}),
+        }
+    }
+
+    #[test]
+    fn add_tile_sample_keeps_at_most_max_samples() {
+        let storage = MemoryStorage::new();
+        let retention = Retention {
+            max_samples: Some(2),
+            max_age: None,
+        };
+
+        for timestamp in 1..4 {
+            storage
+                .add_tile_sample("dashboard", "tile", &sample(timestamp), &retention)
+                .unwrap();
+        }
+
+        let history = storage
+            .get_tile_history("dashboard", "tile", 0, 100, 10)
+            .unwrap();
+        assert_eq!(history, vec![sample(2), sample(3)]);
+    }
+
+    #[test]
+    fn add_tile_sample_drops_too_old_samples() {
+        let storage = MemoryStorage::new();
+        let retention = Retention {
+            max_samples: None,
+            max_age: Some(10),
+        };
+
+        storage
+            .add_tile_sample("dashboard", "tile", &sample(1), &retention)
+            .unwrap();
+        storage
+            .add_tile_sample("dashboard", "tile", &sample(20), &retention)
+            .unwrap();
+
+        let history = storage
+            .get_tile_history("dashboard", "tile", 0, 100, 10)
+            .unwrap();
+        assert_eq!(history, vec![sample(20)]);
+    }
+
+    #[test]
+    fn get_tile_history_returns_newest_samples_in_range() {
+        let storage = MemoryStorage::new();
+        let retention = Retention {
+            max_samples: None,
+            max_age: None,
+        };
+        for timestamp in 1..10 {
+            storage
+                .add_tile_sample("dashboard", "tile", &sample(timestamp), &retention)
+                .unwrap();
+        }
+
+        let history = storage
+            .get_tile_history("dashboard", "tile", 2, 6, 3)
+            .unwrap();
+
+        assert_eq!(history, vec![sample(4), sample(5), sample(6)]);
+    }
+
+    #[test]
+    fn compact_metric_points_replaces_only_old_points() {
+        let storage = MemoryStorage::new();
+        for timestamp in 1..5 {
+            storage
+                .add_metric_point(
+                    "dashboard",
+                    "tile",
+                    &MetricPoint::new(timestamp, 1.0),
+                    10,
+                )
+                .unwrap();
+        }
+        let mut rolled = MetricPoint::new(0, 1.0);
+        rolled.count = 2;
+
+        storage
+            .compact_metric_points("dashboard", "tile", 3, &[rolled.clone()])
+            .unwrap();
+
+        let points = storage
+            .get_metric_points("dashboard", "tile", 0, 10)
+            .unwrap();
+        let timestamps: Vec<i64> = points.iter().map(|p| p.timestamp).collect();
+        assert_eq!(timestamps, vec![0, 3, 4]);
+        assert_eq!(points[0], rolled);
+    }
+
+    #[test]
+    fn rename_dashboard_moves_tiles() {
+        let storage = MemoryStorage::new();
+        storage.create_dashboard(&dashboard("old")).unwrap();
+        storage.upsert_tile("old", "tile", "{}", 1, None).unwrap();
+        storage
+            .add_metric_point("old", "tile", &MetricPoint::new(1, 1.0), 10)
+            .unwrap();
+
+        storage.rename_dashboard("old", &dashboard("new")).unwrap();
+
+        assert!(storage.get_dashboard("old").unwrap().is_none());
+        assert!(storage.get_dashboard("new").unwrap().is_some());
+        assert_eq!(storage.get_tile("old", "tile").unwrap(), None);
+        assert_eq!(
+            storage.get_tile("new", "tile").unwrap().map(|tile| tile.version),
+            Some(1)
+        );
+        assert_eq!(
+            storage.get_metric_series().unwrap(),
+            vec![("new".to_string(), "tile".to_string())]
+        );
+    }
+
+    #[test]
+    fn get_api_tokens_used_returns_last_use_of_each_token() {
+        let storage = MemoryStorage::new();
+        storage.set_api_token_used("dashboard", "ci", 1).unwrap();
+        storage.set_api_token_used("dashboard", "ci", 2).unwrap();
+        storage.set_api_token_used("other", "ci", 3).unwrap();
+
+        let used = storage.get_api_tokens_used("dashboard").unwrap();
+
+        assert_eq!(used.len(), 1);
+        assert_eq!(used.get("ci"), Some(&2));
+    }
+
+    #[test]
+    fn add_nonce_fails_until_nonce_expires() {
+        let storage = MemoryStorage::new();
+
+        assert!(storage.add_nonce("dashboard", "n1", 0, 10).unwrap());
+        assert!(!storage.add_nonce("dashboard", "n1", 5, 15).unwrap());
+        assert!(storage.add_nonce("other", "n1", 5, 15).unwrap());
+        assert!(storage.add_nonce("dashboard", "n1", 10, 20).unwrap());
+    }
+
+    #[test]
+    fn rename_dashboard_fails_when_name_is_taken() {
+        let storage = MemoryStorage::new();
+        storage.create_dashboard(&dashboard("old")).unwrap();
+        storage.create_dashboard(&dashboard("taken")).unwrap();
+        storage.upsert_tile("old", "tile", "{}", 1, None).unwrap();
+
+        let result = storage.rename_dashboard("old", &dashboard("taken"));
+
+        assert_eq!(
+            result,
+            Err(DashboardError::Conflict(
+                "Dashboard taken already exists".to_string(),
+            ))
+        );
+        assert!(storage.get_tile("old", "tile").unwrap().is_some());
+    }
+
+    #[test]
+    fn delete_dashboard_deletes_its_tiles_only() {
+        let storage = MemoryStorage::new();
+        storage.create_dashboard(&dashboard("gone")).unwrap();
+        storage.upsert_tile("gone", "tile", "{}", 1, None).unwrap();
+        storage.upsert_tile("kept", "tile", "{}", 1, None).unwrap();
+
+        assert_eq!(storage.delete_dashboard("gone"), Ok(1));
+
+        assert_eq!(storage.get_tile("gone", "tile").unwrap(), None);
+        assert_eq!(
+            storage.get_tile("kept", "tile").unwrap().map(|tile| tile.data),
+            Some("{}".to_string())
+        );
+    }
+}
@@ -0,0 +1,266 @@
+//! Storage backends for dashboards and tiles
+//!
+//! `db::Db` talks only to the `Storage` trait, backend is picked by
+//! `DASHBOARD_STORAGE` config key (`redis`, `memory` or `sqlite`).
+
+use db::{Dashboard, Retention, StoredTile, TileSample};
+use error::DashboardError;
+use metrics::MetricPoint;
+use std::collections::HashMap;
+use std::time::Duration;
+
+mod broker;
+mod memory;
+mod redis;
+mod sqlite;
+
+pub use self::memory::MemoryStorage;
+pub use self::redis::RedisStorage;
+pub use self::sqlite::SqliteStorage;
+
+
+/// Stream of messages published on a single channel, it can be handed over
+/// to other thread
+pub trait Subscription: Send {
+    /// Waits at most `timeout` for next message, `None` when none came
+    fn get_message(&mut self, timeout: Duration) -> Result<Option<String>, DashboardError>;
+}
+
+
+/// Operations every dashboard/tile backend has to provide
+pub trait Storage {
+    /// Saves `dashboard`
+    ///
+    /// # Errors
+    /// Raises error when `Dashboard.name` already exists
+    fn create_dashboard(&self, dashboard: &Dashboard) -> Result<(), DashboardError>;
+
+    /// Inserts `dashboard` (or update when already exists)
+    fn upsert_dashboard(&self, dashboard: &Dashboard) -> Result<(), DashboardError>;
+
+    /// Returns all `Dashboards` in no particular order
+    fn get_dashboards(&self) -> Result<Vec<Dashboard>, DashboardError>;
+
+    /// Returns `Dashboard` saved at `dashboard_name`
+    fn get_dashboard(&self, dashboard_name: &str) -> Result<Option<Dashboard>, DashboardError>;
+
+    /// Saves `dashboard` in place of the one at `dashboard_name` and moves
+    /// tiles of it (with their history and metrics) and token uses under
+    /// `Dashboard.name`, all at once
+    ///
+    /// # Errors
+    /// Raises error when there is no dashboard at `dashboard_name` or when
+    /// `Dashboard.name` is already taken
+    fn rename_dashboard(&self, dashboard_name: &str, dashboard: &Dashboard)
+        -> Result<(), DashboardError>;
+
+    /// Deletes `Dashboard` at `dashboard_name` together with its tiles (and
+    /// their history and metrics) and token uses, returns count of deleted
+    /// dashboards
+    fn delete_dashboard(&self, dashboard_name: &str) -> Result<u64, DashboardError>;
+
+    /// Returns tile at `tile_id` from `dashboard_name`
+    fn get_tile(&self, dashboard_name: &str, tile_id: &str)
+        -> Result<Option<StoredTile>, DashboardError>;
+
+    /// Returns all tiles of `dashboard_name` in no particular order
+    fn get_tiles(&self, dashboard_name: &str) -> Result<Vec<StoredTile>, DashboardError>;
+
+    /// Returns ids and data of all tiles of `dashboard_name` in no particular
+    /// order, backends can skip reading versions and times of update
+    fn get_tiles_data(
+        &self,
+        dashboard_name: &str,
+    ) -> Result<Vec<(String, String)>, DashboardError> {
+        Ok(self.get_tiles(dashboard_name)?
+            .into_iter()
+            .map(|tile| (tile.tile_id, tile.data))
+            .collect())
+    }
+
+    /// Saves ready to serve `tile_json` at `tile_id` for `dashboard_name`,
+    /// `updated_at` is in milliseconds since unix epoch
+    ///
+    /// When `expected_version` is given tile is saved only if its current
+    /// version (0 for missing tile) equals it. Returns new version of tile or
+    /// `None` when it wasn't saved.
+    fn upsert_tile(
+        &self,
+        dashboard_name: &str,
+        tile_id: &str,
+        tile_json: &str,
+        updated_at: i64,
+        expected_version: Option<u64>,
+    ) -> Result<Option<u64>, DashboardError>;
+
+    /// Saves every (tile id, sample) pair at once (in single transaction when
+    /// backend has them): sample data becomes ready to serve tile data and
+    /// sample is appended to tile history like in `add_tile_sample`, version
+    /// of every tile is bumped
+    fn upsert_tiles(
+        &self,
+        dashboard_name: &str,
+        tiles: &[(String, TileSample)],
+        retention: &Retention,
+    ) -> Result<(), DashboardError>;
+
+    /// Deletes tile at `tile_id` with its history and metric, returns `false`
+    /// when there was no such tile
+    fn delete_tile(&self, dashboard_name: &str, tile_id: &str) -> Result<bool, DashboardError>;
+
+    /// Appends `sample` to history of `tile_id` and drops samples which
+    /// don't fit in `retention` any more
+    fn add_tile_sample(
+        &self,
+        dashboard_name: &str,
+        tile_id: &str,
+        sample: &TileSample,
+        retention: &Retention,
+    ) -> Result<(), DashboardError>;
+
+    /// Returns at most `limit` newest samples of `tile_id` taken between
+    /// `from` and `to` (both inclusive), oldest first
+    fn get_tile_history(
+        &self,
+        dashboard_name: &str,
+        tile_id: &str,
+        from: i64,
+        to: i64,
+        limit: usize,
+    ) -> Result<Vec<TileSample>, DashboardError>;
+
+    /// Appends `point` to metric of `tile_id`, keeps at most `max_points`
+    /// newest ones
+    fn add_metric_point(
+        &self,
+        dashboard_name: &str,
+        tile_id: &str,
+        point: &MetricPoint,
+        max_points: usize,
+    ) -> Result<(), DashboardError>;
+
+    /// Returns metric points of `tile_id` taken between `from` and `to`
+    /// (both inclusive), oldest first
+    fn get_metric_points(
+        &self,
+        dashboard_name: &str,
+        tile_id: &str,
+        from: i64,
+        to: i64,
+    ) -> Result<Vec<MetricPoint>, DashboardError>;
+
+    /// Replaces metric points of `tile_id` taken before `before` with `points`
+    fn compact_metric_points(
+        &self,
+        dashboard_name: &str,
+        tile_id: &str,
+        before: i64,
+        points: &[MetricPoint],
+    ) -> Result<(), DashboardError>;
+
+    /// Returns (dashboard name, tile id) of every recorded metric
+    fn get_metric_series(&self) -> Result<Vec<(String, String)>, DashboardError>;
+
+    /// Saves `used_at` (milliseconds since unix epoch) as time of last use of
+    /// token called `token_name`
+    fn set_api_token_used(&self, dashboard_name: &str, token_name: &str, used_at: i64)
+        -> Result<(), DashboardError>;
+
+    /// Returns time of last use by token name for `dashboard_name`
+    fn get_api_tokens_used(&self, dashboard_name: &str)
+        -> Result<HashMap<String, i64>, DashboardError>;
+
+    /// Saves `nonce` of signed request to `dashboard_name` until `expires_at`
+    /// (milliseconds since unix epoch), returns `false` when it was saved
+    /// already and hasn't expired at `now`
+    fn add_nonce(&self, dashboard_name: &str, nonce: &str, now: i64, expires_at: i64)
+        -> Result<bool, DashboardError>;
+
+    /// Upgrades data saved by older versions in place, called on startup
+    fn migrate(&self) -> Result<(), DashboardError> {
+        Ok(())
+    }
+
+    /// Announces `message` to every subscriber of `channel`
+    fn publish(&self, channel: &str, message: &str) -> Result<(), DashboardError>;
+
+    /// Starts listening on `channel`
+    fn subscribe(&self, channel: &str) -> Result<Box<Subscription>, DashboardError>;
+}
+
+
+/// Returns storage backend named `kind`
+pub fn open(kind: &str) -> Result<Box<Storage>, DashboardError> {
+    match kind {
+        "memory" => Ok(Box::new(MemoryStorage::shared())),
+        "redis" => Ok(Box::new(RedisStorage::new()?)),
+        "sqlite" => Ok(Box::new(SqliteStorage::new()?)),
+        _ => Err(DashboardError::Internal(
+            format!("Unknown storage backend: {}", kind),
+        )),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_utils;
+    use utils::timestamp_ms;
+
+    /// Checks that nonces of renamed dashboard move with it, so dashboard
+    /// which reuses old name can use them again
+    fn check_rename_dashboard_moves_nonces(storage: &Storage) {
+        let (old, new) = ("dashboard-nonces-old", "dashboard-nonces-new");
+        let now = timestamp_ms();
+        // redis may keep them from previous run
+        storage.delete_dashboard(old).unwrap();
+        storage.delete_dashboard(new).unwrap();
+        let dashboard = |name: &str| {
+            Dashboard::new(
+                name.to_string(),
+                "login@email.com".to_string(),
+                "2x4".to_string(),
+            )
+        };
+        storage.create_dashboard(&dashboard(old)).unwrap();
+        assert!(storage.add_nonce(old, "nonce", now, now + 60000).unwrap());
+
+        storage.rename_dashboard(old, &dashboard(new)).unwrap();
+
+        assert!(!storage.add_nonce(new, "nonce", now, now + 60000).unwrap());
+        assert!(storage.add_nonce(old, "nonce", now, now + 60000).unwrap());
+    }
+
+    #[test]
+    fn rename_dashboard_moves_nonces_in_memory() {
+        check_rename_dashboard_moves_nonces(&MemoryStorage::new());
+    }
+
+    #[test]
+    fn rename_dashboard_moves_nonces_in_sqlite() {
+        let storage = SqliteStorage::open(":memory:").unwrap();
+        storage.migrate().unwrap();
+
+        check_rename_dashboard_moves_nonces(&storage);
+    }
+
+    #[test]
+    fn rename_dashboard_moves_nonces_in_redis() {
+        test_utils::load_config();
+
+        check_rename_dashboard_moves_nonces(&RedisStorage::new().unwrap());
+    }
+
+    #[test]
+    fn open_fails_when_backend_is_unknown() {
+        let storage = open("no-such-backend");
+
+        assert_eq!(
+            storage.err(),
+            Some(DashboardError::Internal(
+                "Unknown storage backend: no-such-backend".to_string(),
+            ))
+        );
+    }
+}
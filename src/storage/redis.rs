@@ -0,0 +1,718 @@
+//! Redis backend, dashboards and tiles are kept in two hashes, ids of tiles
+//! of every dashboard in its own set
+
+use db::{Dashboard, Retention, StoredTile, TileSample};
+use error::DashboardError;
+use metrics::MetricPoint;
+use redis;
+use redis::{Commands, PipelineCommands};
+use serde_json;
+use std::collections::HashMap;
+use std::time::Duration;
+use storage::{Storage, Subscription};
+use utils::{get_redis_con, from_config, redis_url};
+use uuid::Uuid;
+
+
+const DASHBOARDS_KEY: &'static str = "dashboards";
+const TILES_KEY: &'static str = "tiles";
+/// tile space -> milliseconds since unix epoch of last tile update
+const TILES_UPDATED_KEY: &'static str = "tiles_updated";
+/// tile space -> version of tile, bumped on every write
+const TILES_VERSION_KEY: &'static str = "tiles_version";
+const HISTORY_KEY: &'static str = "history";
+const METRICS_KEY: &'static str = "metrics";
+/// set of JSONed (dashboard name, tile id) pairs which have metrics
+const METRIC_SERIES_KEY: &'static str = "metric_series";
+/// prefix of per dashboard hashes: token name -> milliseconds since unix
+/// epoch of its last use
+const API_TOKENS_USED_KEY: &'static str = "api_tokens_used";
+/// prefix of per dashboard sorted sets with nonces of signed requests scored
+/// by their expiry
+const NONCES_KEY: &'static str = "nonces";
+/// prefix of per dashboard sets with ids of its tiles
+const TILE_IDS_KEY: &'static str = "tile_ids";
+/// set once sets at `TILE_IDS_KEY` were built for tiles saved before them
+const TILE_IDS_BUILT_KEY: &'static str = "tile_ids_built";
+/// times transaction aborted by change of watched key is tried again
+const TRANSACTION_ATTEMPTS: usize = 5;
+
+/// Saves tile at space ARGV[1] when its version equals ARGV[4] (or ARGV[4] is
+/// empty) and adds ARGV[5] to tile ids at KEYS[4], returns new version or 0
+/// when tile wasn't saved
+const UPSERT_TILE_SCRIPT: &'static str = r"
+local version = tonumber(redis.call('HGET', KEYS[3], ARGV[1]) or '0')
+if ARGV[4] ~= '' and tonumber(ARGV[4]) ~= version then
+    return 0
+end
+redis.call('HSET', KEYS[1], ARGV[1], ARGV[2])
+redis.call('HSET', KEYS[2], ARGV[1], ARGV[3])
+redis.call('SADD', KEYS[4], ARGV[5])
+return redis.call('HINCRBY', KEYS[3], ARGV[1], 1)
+";
+
+/// Adds nonce ARGV[1] expiring at ARGV[3] to sorted set KEYS[1] after it
+/// drops nonces expired at ARGV[2], returns 0 when nonce is there already
+const ADD_NONCE_SCRIPT: &'static str = r"
+redis.call('ZREMRANGEBYSCORE', KEYS[1], '-inf', ARGV[2])
+if redis.call('ZSCORE', KEYS[1], ARGV[1]) then
+    return 0
+end
+redis.call('ZADD', KEYS[1], ARGV[3], ARGV[1])
+local last = redis.call('ZRANGE', KEYS[1], -1, -1, 'WITHSCORES')
+redis.call('PEXPIREAT', KEYS[1], last[2])
+return 1
+";
+
+/// Moves dashboard ARGV[1] to ARGV[2] (saving ARGV[3] as its JSON) with its
+/// tiles, their history and metrics, token uses and nonces, returns 1 when
+/// there is no dashboard ARGV[1], 2 when ARGV[2] is taken, 3 when tiles of
+/// the dashboard aren't the given ones and 0 when it's moved
+///
+/// KEYS[1..5] are dashboards, tiles, tiles updated, tiles version and metric
+/// series keys, KEYS[6..11] are old and new tile ids, token uses and nonces
+/// keys. Every tile adds its id and old and new metric series members to
+/// ARGV and its old and new history and metrics keys to KEYS.
+const RENAME_DASHBOARD_SCRIPT: &'static str = r"
+local old, new = ARGV[1], ARGV[2]
+if redis.call('HEXISTS', KEYS[1], old) == 0 then
+    return 1
+end
+if redis.call('HEXISTS', KEYS[1], new) == 1 then
+    return 2
+end
+local count = (#ARGV - 3) / 3
+if redis.call('SCARD', KEYS[6]) ~= count then
+    return 3
+end
+for i = 0, count - 1 do
+    if redis.call('SISMEMBER', KEYS[6], ARGV[4 + 3 * i]) == 0 then
+        return 3
+    end
+end
+local function move(from, to)
+    if redis.call('EXISTS', from) == 1 then
+        redis.call('RENAME', from, to)
+    end
+end
+redis.call('HDEL', KEYS[1], old)
+redis.call('HSET', KEYS[1], new, ARGV[3])
+for i = 0, count - 1 do
+    local tile_id = ARGV[4 + 3 * i]
+    local old_space, new_space = old .. ':' .. tile_id, new .. ':' .. tile_id
+    for k = 2, 4 do
+        local value = redis.call('HGET', KEYS[k], old_space)
+        if value then
+            redis.call('HDEL', KEYS[k], old_space)
+            redis.call('HSET', KEYS[k], new_space, value)
+        end
+    end
+    if redis.call('SREM', KEYS[5], ARGV[5 + 3 * i]) == 1 then
+        redis.call('SADD', KEYS[5], ARGV[6 + 3 * i])
+    end
+    move(KEYS[12 + 4 * i], KEYS[13 + 4 * i])
+    move(KEYS[14 + 4 * i], KEYS[15 + 4 * i])
+end
+move(KEYS[6], KEYS[7])
+move(KEYS[8], KEYS[9])
+move(KEYS[10], KEYS[11])
+return 0
+";
+
+
+pub struct RedisStorage {
+    connection: redis::Connection,
+}
+
+impl RedisStorage {
+    pub fn new() -> Result<RedisStorage, DashboardError> {
+        // TODO: get from thread pool
+        let connection = get_redis_con(from_config("DASHBOARD_REDIS_IP_PORT").as_str())
+            .map_err(|e| DashboardError::BackendUnavailable(e.to_string()))?;
+        Ok(RedisStorage { connection: connection })
+    }
+
+    fn get_tile_space(&self, dashboard_name: &str, tile_id: &str) -> String {
+        format!("{}:{}", dashboard_name, tile_id)
+    }
+
+    /// Returns key of set with ids of tiles of `dashboard_name`
+    fn get_tile_ids_key(&self, dashboard_name: &str) -> String {
+        format!("{}:{}", TILE_IDS_KEY, dashboard_name)
+    }
+
+    /// Queues removal of tile at `tile_id` with its history and metric,
+    /// only result of the last queued command (count of deleted tiles) isn't
+    /// ignored
+    fn pipe_delete_tile(
+        &self,
+        pipe: &mut redis::Pipeline,
+        dashboard_name: &str,
+        tile_id: &str,
+    ) -> Result<(), DashboardError> {
+        let series = serde_json::to_string(&(dashboard_name, tile_id))?;
+        pipe.hdel(TILES_UPDATED_KEY, self.get_tile_space(dashboard_name, tile_id))
+            .ignore()
+            .hdel(TILES_VERSION_KEY, self.get_tile_space(dashboard_name, tile_id))
+            .ignore()
+            .del(self.get_history_key(dashboard_name, tile_id))
+            .ignore()
+            .del(self.get_metrics_key(dashboard_name, tile_id))
+            .ignore()
+            .srem(METRIC_SERIES_KEY, series)
+            .ignore()
+            .srem(self.get_tile_ids_key(dashboard_name), tile_id)
+            .ignore()
+            .hdel(TILES_KEY, self.get_tile_space(dashboard_name, tile_id));
+        Ok(())
+    }
+
+    /// Queues appending of `sample` to history of `tile_id` with removal of
+    /// samples which don't fit in `retention`, results are ignored
+    fn pipe_add_tile_sample(
+        &self,
+        pipe: &mut redis::Pipeline,
+        dashboard_name: &str,
+        tile_id: &str,
+        sample: &TileSample,
+        retention: &Retention,
+    ) -> Result<(), DashboardError> {
+        let key = self.get_history_key(dashboard_name, tile_id);
+        let json = serde_json::to_string(sample)?;
+        pipe.zadd(&key, json, sample.timestamp).ignore();
+        if let Some(oldest) = retention.oldest_kept(sample.timestamp) {
+            pipe.zrembyscore(&key, "-inf", oldest - 1).ignore();
+        }
+        if let Some(max_samples) = retention.max_samples {
+            pipe.zrembyrank(&key, 0, -(max_samples as isize) - 1)
+                .ignore();
+        }
+        Ok(())
+    }
+
+    /// Returns key of sorted set (scored by timestamp) with tile history
+    fn get_history_key(&self, dashboard_name: &str, tile_id: &str) -> String {
+        format!(
+            "{}:{}",
+            HISTORY_KEY,
+            self.get_tile_space(dashboard_name, tile_id)
+        )
+    }
+
+    /// Returns key of hash with last uses of tokens of `dashboard_name`
+    fn get_api_tokens_used_key(&self, dashboard_name: &str) -> String {
+        format!("{}:{}", API_TOKENS_USED_KEY, dashboard_name)
+    }
+
+    /// Returns key of sorted set with nonces of `dashboard_name`
+    fn get_nonces_key(&self, dashboard_name: &str) -> String {
+        format!("{}:{}", NONCES_KEY, dashboard_name)
+    }
+
+    /// Returns key of sorted set (scored by timestamp) with tile metric,
+    /// members are JSONed (unique id, `MetricPoint`) pairs
+    fn get_metrics_key(&self, dashboard_name: &str, tile_id: &str) -> String {
+        format!(
+            "{}:{}",
+            METRICS_KEY,
+            self.get_tile_space(dashboard_name, tile_id)
+        )
+    }
+}
+
+impl Storage for RedisStorage {
+    fn create_dashboard(&self, dashboard: &Dashboard) -> Result<(), DashboardError> {
+        if self.connection
+            .hexists::<_, _, bool>(DASHBOARDS_KEY, &dashboard.name)?
+        {
+            return Err(DashboardError::Conflict(
+                format!("Dashboard {} already exists", dashboard.name),
+            ));
+        }
+        self.upsert_dashboard(dashboard)
+    }
+
+    fn upsert_dashboard(&self, dashboard: &Dashboard) -> Result<(), DashboardError> {
+        let json = serde_json::to_string(&dashboard)?;
+        self.connection
+            .hset::<_, _, _, u64>(DASHBOARDS_KEY, &dashboard.name, &json)?;
+        Ok(())
+    }
+
+    fn get_dashboards(&self) -> Result<Vec<Dashboard>, DashboardError> {
+        let collection: Vec<Dashboard> = self.connection
+            .hscan::<_, (String, Dashboard)>(DASHBOARDS_KEY)?
+            .map(|(_, dashboard_data)| dashboard_data)
+            .collect();
+        Ok(collection)
+    }
+
+    fn get_dashboard(&self, dashboard_name: &str) -> Result<Option<Dashboard>, DashboardError> {
+        let json_op = self.connection
+            .hget::<_, _, Option<String>>(DASHBOARDS_KEY, dashboard_name)?;
+        let json = match json_op {
+            None => return Ok(None),
+            Some(j) => j,
+        };
+        let dashboard: Dashboard = serde_json::from_str(&json)?;
+        Ok(Some(dashboard))
+    }
+
+    fn rename_dashboard(
+        &self,
+        dashboard_name: &str,
+        dashboard: &Dashboard,
+    ) -> Result<(), DashboardError> {
+        let (old, new) = (dashboard_name, dashboard.name.as_str());
+        let script = redis::Script::new(RENAME_DASHBOARD_SCRIPT);
+        for _ in 0..TRANSACTION_ATTEMPTS {
+            let tile_ids: Vec<String> = self.connection.smembers(self.get_tile_ids_key(old))?;
+            let mut invocation = script.prepare_invoke();
+            invocation
+                .key(DASHBOARDS_KEY)
+                .key(TILES_KEY)
+                .key(TILES_UPDATED_KEY)
+                .key(TILES_VERSION_KEY)
+                .key(METRIC_SERIES_KEY)
+                .key(self.get_tile_ids_key(old))
+                .key(self.get_tile_ids_key(new))
+                .key(self.get_api_tokens_used_key(old))
+                .key(self.get_api_tokens_used_key(new))
+                .key(self.get_nonces_key(old))
+                .key(self.get_nonces_key(new))
+                .arg(old)
+                .arg(new)
+                .arg(serde_json::to_string(&dashboard)?);
+            for tile_id in &tile_ids {
+                invocation
+                    .key(self.get_history_key(old, tile_id))
+                    .key(self.get_history_key(new, tile_id))
+                    .key(self.get_metrics_key(old, tile_id))
+                    .key(self.get_metrics_key(new, tile_id))
+                    .arg(tile_id)
+                    .arg(serde_json::to_string(&(old, tile_id))?)
+                    .arg(serde_json::to_string(&(new, tile_id))?);
+            }
+            let moved: u8 = invocation.invoke(&self.connection)?;
+            match moved {
+                0 => return Ok(()),
+                1 => return Err(DashboardError::NotFound("Dashboard doesn't exist".to_string())),
+                2 => {
+                    return Err(DashboardError::Conflict(
+                        format!("Dashboard {} already exists", new),
+                    ))
+                }
+                // tiles changed since their ids were read
+                _ => continue,
+            }
+        }
+        Err(DashboardError::Conflict(
+            format!("Dashboard {} keeps changing, try again", old),
+        ))
+    }
+
+    fn delete_dashboard(&self, dashboard_name: &str) -> Result<u64, DashboardError> {
+        let tile_ids_key = self.get_tile_ids_key(dashboard_name);
+        for _ in 0..TRANSACTION_ATTEMPTS {
+            // tile saved after its ids are read aborts the transaction, so it
+            // isn't left behind without dashboard
+            redis::cmd("WATCH")
+                .arg(&tile_ids_key)
+                .query::<()>(&self.connection)?;
+            let tile_ids: Vec<String> = self.connection.smembers(&tile_ids_key)?;
+            let mut pipe = redis::pipe();
+            pipe.atomic();
+            for tile_id in &tile_ids {
+                self.pipe_delete_tile(&mut pipe, dashboard_name, tile_id)?;
+                pipe.ignore();
+            }
+            pipe.del(&tile_ids_key)
+                .ignore()
+                .del(self.get_api_tokens_used_key(dashboard_name))
+                .ignore()
+                .del(self.get_nonces_key(dashboard_name))
+                .ignore();
+            let deleted: Option<(u64,)> = pipe.hdel(DASHBOARDS_KEY, dashboard_name)
+                .query(&self.connection)?;
+            if let Some((count,)) = deleted {
+                return Ok(count);
+            }
+        }
+        Err(DashboardError::Conflict(format!(
+            "Dashboard {} keeps changing, try again",
+            dashboard_name
+        )))
+    }
+
+    fn get_tile(
+        &self,
+        dashboard_name: &str,
+        tile_id: &str,
+    ) -> Result<Option<StoredTile>, DashboardError> {
+        let space = self.get_tile_space(dashboard_name, tile_id);
+        let (json, updated_at, version): (Option<String>, Option<i64>, Option<u64>) =
+            redis::pipe()
+                .hget(TILES_KEY, &space)
+                .hget(TILES_UPDATED_KEY, &space)
+                .hget(TILES_VERSION_KEY, &space)
+                .query(&self.connection)?;
+        Ok(json.map(|data| {
+            StoredTile {
+                tile_id: tile_id.to_string(),
+                data: data,
+                updated_at: updated_at,
+                version: version.unwrap_or(0),
+            }
+        }))
+    }
+
+    fn get_tiles(&self, dashboard_name: &str) -> Result<Vec<StoredTile>, DashboardError> {
+        let tile_ids: Vec<String> = self.connection
+            .smembers(self.get_tile_ids_key(dashboard_name))?;
+        if tile_ids.is_empty() {
+            return Ok(Vec::new());
+        }
+        let spaces: Vec<String> = tile_ids
+            .iter()
+            .map(|tile_id| self.get_tile_space(dashboard_name, tile_id))
+            .collect();
+        // `hget` would send HGET for single space, so HMGET is spelled out
+        let (jsons, updated, versions): (
+            Vec<Option<String>>,
+            Vec<Option<i64>>,
+            Vec<Option<u64>>,
+        ) = redis::pipe()
+            .cmd("HMGET")
+            .arg(TILES_KEY)
+            .arg(&spaces[..])
+            .cmd("HMGET")
+            .arg(TILES_UPDATED_KEY)
+            .arg(&spaces[..])
+            .cmd("HMGET")
+            .arg(TILES_VERSION_KEY)
+            .arg(&spaces[..])
+            .query(&self.connection)?;
+        let collection = tile_ids
+            .into_iter()
+            .zip(jsons.into_iter().zip(updated.into_iter().zip(versions)))
+            // tile deleted since its id was read
+            .filter_map(|(tile_id, (json, (updated_at, version)))| {
+                json.map(|data| {
+                    StoredTile {
+                        tile_id: tile_id,
+                        data: data,
+                        updated_at: updated_at,
+                        version: version.unwrap_or(0),
+                    }
+                })
+            })
+            .collect();
+        Ok(collection)
+    }
+
+    fn get_tiles_data(
+        &self,
+        dashboard_name: &str,
+    ) -> Result<Vec<(String, String)>, DashboardError> {
+        let tile_ids: Vec<String> = self.connection
+            .smembers(self.get_tile_ids_key(dashboard_name))?;
+        if tile_ids.is_empty() {
+            return Ok(Vec::new());
+        }
+        let spaces: Vec<String> = tile_ids
+            .iter()
+            .map(|tile_id| self.get_tile_space(dashboard_name, tile_id))
+            .collect();
+        let jsons: Vec<Option<String>> = redis::cmd("HMGET")
+            .arg(TILES_KEY)
+            .arg(&spaces[..])
+            .query(&self.connection)?;
+        Ok(tile_ids
+            .into_iter()
+            .zip(jsons)
+            // tile deleted since its id was read
+            .filter_map(|(tile_id, json)| json.map(|data| (tile_id, data)))
+            .collect())
+    }
+
+    fn upsert_tile(
+        &self,
+        dashboard_name: &str,
+        tile_id: &str,
+        tile_json: &str,
+        updated_at: i64,
+        expected_version: Option<u64>,
+    ) -> Result<Option<u64>, DashboardError> {
+        let expected = expected_version.map_or(String::new(), |v| v.to_string());
+        let version: u64 = redis::Script::new(UPSERT_TILE_SCRIPT)
+            .key(TILES_KEY)
+            .key(TILES_UPDATED_KEY)
+            .key(TILES_VERSION_KEY)
+            .key(self.get_tile_ids_key(dashboard_name))
+            .arg(self.get_tile_space(dashboard_name, tile_id))
+            .arg(tile_json)
+            .arg(updated_at)
+            .arg(expected)
+            .arg(tile_id)
+            .invoke(&self.connection)?;
+        match version {
+            0 => Ok(None),
+            v => Ok(Some(v)),
+        }
+    }
+
+    fn upsert_tiles(
+        &self,
+        dashboard_name: &str,
+        tiles: &[(String, TileSample)],
+        retention: &Retention,
+    ) -> Result<(), DashboardError> {
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        for &(ref tile_id, ref sample) in tiles {
+            let space = self.get_tile_space(dashboard_name, tile_id);
+            pipe.hset(TILES_KEY, &space, serde_json::to_string(&sample.data)?)
+                .ignore()
+                .hset(TILES_UPDATED_KEY, &space, sample.timestamp)
+                .ignore()
+                .hincr(TILES_VERSION_KEY, &space, 1)
+                .ignore()
+                .sadd(self.get_tile_ids_key(dashboard_name), tile_id)
+                .ignore();
+            self.pipe_add_tile_sample(&mut pipe, dashboard_name, tile_id, sample, retention)?;
+        }
+        pipe.query::<()>(&self.connection)?;
+        Ok(())
+    }
+
+    fn delete_tile(&self, dashboard_name: &str, tile_id: &str) -> Result<bool, DashboardError> {
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        self.pipe_delete_tile(&mut pipe, dashboard_name, tile_id)?;
+        let (count,): (u64,) = pipe.query(&self.connection)?;
+        Ok(count > 0)
+    }
+
+    fn add_tile_sample(
+        &self,
+        dashboard_name: &str,
+        tile_id: &str,
+        sample: &TileSample,
+        retention: &Retention,
+    ) -> Result<(), DashboardError> {
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        self.pipe_add_tile_sample(&mut pipe, dashboard_name, tile_id, sample, retention)?;
+        pipe.query::<()>(&self.connection)?;
+        Ok(())
+    }
+
+    fn get_tile_history(
+        &self,
+        dashboard_name: &str,
+        tile_id: &str,
+        from: i64,
+        to: i64,
+        limit: usize,
+    ) -> Result<Vec<TileSample>, DashboardError> {
+        let jsons = self.connection.zrevrangebyscore_limit::<_, _, _, Vec<String>>(
+            self.get_history_key(dashboard_name, tile_id),
+            to,
+            from,
+            0,
+            limit as isize,
+        )?;
+        let mut samples = Vec::with_capacity(jsons.len());
+        for json in jsons.iter().rev() {
+            samples.push(serde_json::from_str(json)?);
+        }
+        Ok(samples)
+    }
+
+    fn add_metric_point(
+        &self,
+        dashboard_name: &str,
+        tile_id: &str,
+        point: &MetricPoint,
+        max_points: usize,
+    ) -> Result<(), DashboardError> {
+        let key = self.get_metrics_key(dashboard_name, tile_id);
+        // unique id, so equal points don't collapse into single member
+        let member = serde_json::to_string(&(Uuid::new_v4().to_string(), point))?;
+        let series = serde_json::to_string(&(dashboard_name, tile_id))?;
+        redis::pipe()
+            .atomic()
+            .zadd(&key, member, point.timestamp)
+            .ignore()
+            .sadd(METRIC_SERIES_KEY, series)
+            .ignore()
+            .zrembyrank(&key, 0, -(max_points as isize) - 1)
+            .ignore()
+            .query::<()>(&self.connection)?;
+        Ok(())
+    }
+
+    fn get_metric_points(
+        &self,
+        dashboard_name: &str,
+        tile_id: &str,
+        from: i64,
+        to: i64,
+    ) -> Result<Vec<MetricPoint>, DashboardError> {
+        let members = self.connection.zrangebyscore::<_, _, _, Vec<String>>(
+            self.get_metrics_key(dashboard_name, tile_id),
+            from,
+            to,
+        )?;
+        let mut points = Vec::with_capacity(members.len());
+        for member in &members {
+            let (_, point): (String, MetricPoint) = serde_json::from_str(member)?;
+            points.push(point);
+        }
+        Ok(points)
+    }
+
+    fn compact_metric_points(
+        &self,
+        dashboard_name: &str,
+        tile_id: &str,
+        before: i64,
+        points: &[MetricPoint],
+    ) -> Result<(), DashboardError> {
+        let key = self.get_metrics_key(dashboard_name, tile_id);
+        let mut pipe = redis::pipe();
+        pipe.atomic()
+            .zrembyscore(&key, "-inf", before - 1)
+            .ignore();
+        for point in points {
+            let member = serde_json::to_string(&(Uuid::new_v4().to_string(), point))?;
+            pipe.zadd(&key, member, point.timestamp).ignore();
+        }
+        pipe.query::<()>(&self.connection)?;
+        Ok(())
+    }
+
+    fn get_metric_series(&self) -> Result<Vec<(String, String)>, DashboardError> {
+        let members = self.connection
+            .smembers::<_, Vec<String>>(METRIC_SERIES_KEY)?;
+        let mut series = Vec::with_capacity(members.len());
+        for member in &members {
+            series.push(serde_json::from_str(member)?);
+        }
+        Ok(series)
+    }
+
+    fn set_api_token_used(
+        &self,
+        dashboard_name: &str,
+        token_name: &str,
+        used_at: i64,
+    ) -> Result<(), DashboardError> {
+        self.connection.hset::<_, _, _, ()>(
+            self.get_api_tokens_used_key(dashboard_name),
+            token_name,
+            used_at,
+        )?;
+        Ok(())
+    }
+
+    fn get_api_tokens_used(
+        &self,
+        dashboard_name: &str,
+    ) -> Result<HashMap<String, i64>, DashboardError> {
+        Ok(self.connection
+            .hgetall(self.get_api_tokens_used_key(dashboard_name))?)
+    }
+
+    fn add_nonce(
+        &self,
+        dashboard_name: &str,
+        nonce: &str,
+        now: i64,
+        expires_at: i64,
+    ) -> Result<bool, DashboardError> {
+        let added: u8 = redis::Script::new(ADD_NONCE_SCRIPT)
+            .key(self.get_nonces_key(dashboard_name))
+            .arg(nonce)
+            .arg(now)
+            .arg(expires_at)
+            .invoke(&self.connection)?;
+        Ok(added == 1)
+    }
+
+    fn migrate(&self) -> Result<(), DashboardError> {
+        if self.connection.exists::<_, bool>(TILE_IDS_BUILT_KEY)? {
+            return Ok(());
+        }
+        let mut dashboard_names: Vec<String> = self.connection.hkeys(DASHBOARDS_KEY)?;
+        // longest first, so tiles of dashboard "a:b" don't go to dashboard "a"
+        dashboard_names.sort_by(|a, b| b.len().cmp(&a.len()));
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        for space in self.connection.hkeys::<_, Vec<String>>(TILES_KEY)? {
+            let dashboard_name = dashboard_names
+                .iter()
+                .find(|name| space.starts_with(&self.get_tile_space(name, "")));
+            if let Some(dashboard_name) = dashboard_name {
+                pipe.sadd(
+                    self.get_tile_ids_key(dashboard_name),
+                    &space[dashboard_name.len() + 1..],
+                ).ignore();
+            }
+        }
+        pipe.set(TILE_IDS_BUILT_KEY, 1)
+            .ignore()
+            .query::<()>(&self.connection)?;
+        Ok(())
+    }
+
+    fn publish(&self, channel: &str, message: &str) -> Result<(), DashboardError> {
+        self.connection.publish::<_, _, ()>(channel, message)?;
+        Ok(())
+    }
+
+    fn subscribe(&self, channel: &str) -> Result<Box<Subscription>, DashboardError> {
+        let client = redis::Client::open(
+            redis_url(from_config("DASHBOARD_REDIS_IP_PORT").as_str()).as_str(),
+        )?;
+        let mut pubsub = client.get_pubsub()?;
+        pubsub.subscribe(channel)?;
+        Ok(Box::new(RedisSubscription { pubsub: pubsub }))
+    }
+}
+
+
+struct RedisSubscription {
+    pubsub: redis::PubSub,
+}
+
+impl Subscription for RedisSubscription {
+    fn get_message(&mut self, timeout: Duration) -> Result<Option<String>, DashboardError> {
+        self.pubsub.set_read_timeout(Some(timeout))?;
+        match self.pubsub.get_message() {
+            Ok(msg) => Ok(Some(msg.get_payload()?)),
+            Err(ref e) if e.is_timeout() => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+
+impl redis::FromRedisValue for Dashboard {
+    fn from_redis_value(v: &redis::Value) -> redis::RedisResult<Dashboard> {
+        match *v {
+            redis::Value::Data(ref val) => {
+                match serde_json::from_slice(val) {
+                    Err(_) => Err((redis::ErrorKind::TypeError, "Can't unjson value").into()),
+                    Ok(v) => Ok(v),
+                }
+            }
+            _ => Err(
+                (
+                    redis::ErrorKind::ResponseError,
+                    "Response type not Dashboard compatible.",
+                ).into(),
+            ),
+        }
+    }
+}
@@ -0,0 +1,835 @@
+//! SQLite backend, everything is kept in a file at `DASHBOARD_SQLITE_PATH`
+//!
+//! SQLite has no pub/sub, so changes are announced through in-process
+//! `Broker`, which means only websockets of this process are notified.
+
+use db::{Dashboard, Retention, StoredTile, TileSample};
+use error::DashboardError;
+use metrics::MetricPoint;
+use rusqlite::{Connection, ErrorCode, OptionalExtension, Transaction, TransactionBehavior};
+use rusqlite;
+use serde_json;
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::Duration;
+use storage::{Storage, Subscription};
+use storage::broker::Broker;
+use utils::from_config;
+
+
+/// Schema changes applied in order, `user_version` pragma keeps count of
+/// already applied ones. Append only, never edit released migration.
+const MIGRATIONS: &'static [&'static str] = &[
+    "CREATE TABLE dashboards (
+        name TEXT PRIMARY KEY NOT NULL,
+        owner_email TEXT NOT NULL,
+        data TEXT NOT NULL
+    );
+    CREATE TABLE tiles (
+        dashboard_name TEXT NOT NULL,
+        tile_id TEXT NOT NULL,
+        data TEXT NOT NULL,
+        updated_at INTEGER NOT NULL,
+        PRIMARY KEY (dashboard_name, tile_id)
+    );",
+    "CREATE TABLE tile_samples (
+        dashboard_name TEXT NOT NULL,
+        tile_id TEXT NOT NULL,
+        timestamp INTEGER NOT NULL,
+        data TEXT NOT NULL
+    );
+    CREATE INDEX tile_samples_by_time
+        ON tile_samples (dashboard_name, tile_id, timestamp);",
+    "CREATE TABLE metric_points (
+        dashboard_name TEXT NOT NULL,
+        tile_id TEXT NOT NULL,
+        timestamp INTEGER NOT NULL,
+        count INTEGER NOT NULL,
+        sum REAL NOT NULL,
+        min REAL NOT NULL,
+        max REAL NOT NULL,
+        last REAL NOT NULL
+    );
+    CREATE INDEX metric_points_by_time
+        ON metric_points (dashboard_name, tile_id, timestamp);",
+    // tiles.updated_at was kept in seconds
+    "UPDATE tiles SET updated_at = updated_at * 1000;",
+    "ALTER TABLE tiles ADD COLUMN version INTEGER NOT NULL DEFAULT 0;",
+    "CREATE TABLE api_tokens_used (
+        dashboard_name TEXT NOT NULL,
+        token_name TEXT NOT NULL,
+        used_at INTEGER NOT NULL,
+        PRIMARY KEY (dashboard_name, token_name)
+    );",
+    "CREATE TABLE nonces (
+        dashboard_name TEXT NOT NULL,
+        nonce TEXT NOT NULL,
+        expires_at INTEGER NOT NULL,
+        PRIMARY KEY (dashboard_name, nonce)
+    );",
+];
+
+const BUSY_TIMEOUT_SECS: u64 = 5;
+
+
+lazy_static! {
+    static ref BROKER: Broker = Broker::new();
+}
+
+
+/// Returns count of migrations applied to `connection`
+fn applied_migrations(connection: &Connection) -> Result<usize, DashboardError> {
+    let applied: i64 = connection.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    Ok(applied as usize)
+}
+
+/// Brings schema of `connection` up to date, database is locked (and
+/// written) only when some migration is missing
+fn migrate(connection: &Connection) -> Result<(), DashboardError> {
+    if applied_migrations(connection)? == MIGRATIONS.len() {
+        return Ok(());
+    }
+    // immediate, so concurrent starts don't apply the same migration twice
+    let tx = Transaction::new_unchecked(connection, TransactionBehavior::Immediate)?;
+    let applied = applied_migrations(&tx)?;
+    for migration in MIGRATIONS.iter().skip(applied) {
+        tx.execute_batch(migration)?;
+    }
+    tx.execute_batch(&format!("PRAGMA user_version = {}", MIGRATIONS.len()))?;
+    tx.commit()?;
+    Ok(())
+}
+
+/// Saves tile and returns its bumped version
+fn upsert_tile_row(
+    connection: &Connection,
+    dashboard_name: &str,
+    tile_id: &str,
+    tile_json: &str,
+    updated_at: i64,
+) -> rusqlite::Result<u64> {
+    connection
+        .query_row(
+            "INSERT INTO tiles (dashboard_name, tile_id, data, updated_at, version)
+             VALUES (?1, ?2, ?3, ?4, 1)
+             ON CONFLICT (dashboard_name, tile_id) DO UPDATE
+             SET data = excluded.data, updated_at = excluded.updated_at,
+                 version = tiles.version + 1
+             RETURNING version",
+            (dashboard_name, tile_id, tile_json, updated_at),
+            |row| row.get::<_, i64>(0),
+        )
+        .map(|version| version as u64)
+}
+
+fn row_to_tile(row: &rusqlite::Row) -> rusqlite::Result<StoredTile> {
+    Ok(StoredTile {
+        tile_id: row.get(0)?,
+        data: row.get(1)?,
+        updated_at: Some(row.get(2)?),
+        version: row.get::<_, i64>(3)? as u64,
+    })
+}
+
+/// Appends `sample` to tile history and drops samples which don't fit in
+/// `retention`, should be run inside transaction
+fn insert_tile_sample(
+    connection: &Connection,
+    dashboard_name: &str,
+    tile_id: &str,
+    sample: &TileSample,
+    retention: &Retention,
+) -> Result<(), DashboardError> {
+    connection.execute(
+        "INSERT INTO tile_samples (dashboard_name, tile_id, timestamp, data)
+         VALUES (?1, ?2, ?3, ?4)",
+        (
+            dashboard_name,
+            tile_id,
+            sample.timestamp,
+            serde_json::to_string(&sample.data)?,
+        ),
+    )?;
+    if let Some(oldest) = retention.oldest_kept(sample.timestamp) {
+        connection.execute(
+            "DELETE FROM tile_samples
+             WHERE dashboard_name = ?1 AND tile_id = ?2 AND timestamp < ?3",
+            (dashboard_name, tile_id, oldest),
+        )?;
+    }
+    if let Some(max_samples) = retention.max_samples {
+        connection.execute(
+            "DELETE FROM tile_samples
+             WHERE dashboard_name = ?1 AND tile_id = ?2 AND rowid NOT IN (
+                 SELECT rowid FROM tile_samples
+                 WHERE dashboard_name = ?1 AND tile_id = ?2
+                 ORDER BY timestamp DESC, rowid DESC LIMIT ?3
+             )",
+            (dashboard_name, tile_id, max_samples as i64),
+        )?;
+    }
+    Ok(())
+}
+
+fn insert_metric_point(
+    connection: &Connection,
+    dashboard_name: &str,
+    tile_id: &str,
+    point: &MetricPoint,
+) -> rusqlite::Result<usize> {
+    connection.execute(
+        "INSERT INTO metric_points
+         (dashboard_name, tile_id, timestamp, count, sum, min, max, last)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        (
+            dashboard_name,
+            tile_id,
+            point.timestamp,
+            point.count as i64,
+            point.sum,
+            point.min,
+            point.max,
+            point.last,
+        ),
+    )
+}
+
+
+pub struct SqliteStorage {
+    connection: Connection,
+}
+
+impl SqliteStorage {
+    /// Opens database at `DASHBOARD_SQLITE_PATH`
+    pub fn new() -> Result<SqliteStorage, DashboardError> {
+        SqliteStorage::open(from_config("DASHBOARD_SQLITE_PATH"))
+    }
+
+    /// Opens (or creates) database at `path`, its schema is brought up to
+    /// date by `Storage::migrate` on startup
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<SqliteStorage, DashboardError> {
+        let connection = Connection::open(path)?;
+        connection.busy_timeout(Duration::from_secs(BUSY_TIMEOUT_SECS))?;
+        Ok(SqliteStorage { connection: connection })
+    }
+}
+
+impl Storage for SqliteStorage {
+    fn create_dashboard(&self, dashboard: &Dashboard) -> Result<(), DashboardError> {
+        let json = serde_json::to_string(&dashboard)?;
+        let inserted = self.connection.execute(
+            "INSERT INTO dashboards (name, owner_email, data) VALUES (?1, ?2, ?3)",
+            (&dashboard.name, &dashboard.owner_email, &json),
+        );
+        match inserted {
+            Err(rusqlite::Error::SqliteFailure(ref e, _))
+                if e.code == ErrorCode::ConstraintViolation => {
+                Err(DashboardError::Conflict(
+                    format!("Dashboard {} already exists", dashboard.name),
+                ))
+            }
+            Err(e) => Err(e.into()),
+            Ok(_) => Ok(()),
+        }
+    }
+
+    fn upsert_dashboard(&self, dashboard: &Dashboard) -> Result<(), DashboardError> {
+        let json = serde_json::to_string(&dashboard)?;
+        self.connection.execute(
+            "INSERT INTO dashboards (name, owner_email, data) VALUES (?1, ?2, ?3)
+             ON CONFLICT (name) DO UPDATE
+             SET owner_email = excluded.owner_email, data = excluded.data",
+            (&dashboard.name, &dashboard.owner_email, &json),
+        )?;
+        Ok(())
+    }
+
+    fn get_dashboards(&self) -> Result<Vec<Dashboard>, DashboardError> {
+        let mut stmt = self.connection.prepare("SELECT data FROM dashboards")?;
+        let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+        let mut collection = Vec::new();
+        for json in rows {
+            collection.push(serde_json::from_str(&json?)?);
+        }
+        Ok(collection)
+    }
+
+    fn get_dashboard(&self, dashboard_name: &str) -> Result<Option<Dashboard>, DashboardError> {
+        let json_op = self.connection
+            .query_row(
+                "SELECT data FROM dashboards WHERE name = ?1",
+                [dashboard_name],
+                |row| row.get::<_, String>(0),
+            )
+            .optional()?;
+        let json = match json_op {
+            None => return Ok(None),
+            Some(j) => j,
+        };
+        let dashboard: Dashboard = serde_json::from_str(&json)?;
+        Ok(Some(dashboard))
+    }
+
+    fn rename_dashboard(
+        &self,
+        dashboard_name: &str,
+        dashboard: &Dashboard,
+    ) -> Result<(), DashboardError> {
+        let json = serde_json::to_string(&dashboard)?;
+        let tx = self.connection.unchecked_transaction()?;
+        let updated = tx.execute(
+            "UPDATE dashboards SET name = ?1, owner_email = ?2, data = ?3 WHERE name = ?4",
+            (&dashboard.name, &dashboard.owner_email, &json, dashboard_name),
+        );
+        match updated {
+            Err(rusqlite::Error::SqliteFailure(ref e, _))
+                if e.code == ErrorCode::ConstraintViolation => {
+                return Err(DashboardError::Conflict(
+                    format!("Dashboard {} already exists", dashboard.name),
+                ))
+            }
+            Err(e) => return Err(e.into()),
+            Ok(0) => return Err(DashboardError::NotFound("Dashboard doesn't exist".to_string())),
+            Ok(_) => (),
+        }
+        for table in &["tiles", "tile_samples", "metric_points", "api_tokens_used", "nonces"] {
+            tx.execute(
+                &format!("UPDATE {} SET dashboard_name = ?1 WHERE dashboard_name = ?2", table),
+                (&dashboard.name, dashboard_name),
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn delete_dashboard(&self, dashboard_name: &str) -> Result<u64, DashboardError> {
+        let tx = self.connection.unchecked_transaction()?;
+        for table in &["tiles", "tile_samples", "metric_points", "api_tokens_used", "nonces"] {
+            tx.execute(
+                &format!("DELETE FROM {} WHERE dashboard_name = ?1", table),
+                [dashboard_name],
+            )?;
+        }
+        let count = tx.execute("DELETE FROM dashboards WHERE name = ?1", [dashboard_name])?;
+        tx.commit()?;
+        Ok(count as u64)
+    }
+
+    fn get_tile(
+        &self,
+        dashboard_name: &str,
+        tile_id: &str,
+    ) -> Result<Option<StoredTile>, DashboardError> {
+        let tile = self.connection
+            .query_row(
+                "SELECT tile_id, data, updated_at, version FROM tiles
+                 WHERE dashboard_name = ?1 AND tile_id = ?2",
+                [dashboard_name, tile_id],
+                row_to_tile,
+            )
+            .optional()?;
+        Ok(tile)
+    }
+
+    fn get_tiles(&self, dashboard_name: &str) -> Result<Vec<StoredTile>, DashboardError> {
+        let mut statement = self.connection.prepare(
+            "SELECT tile_id, data, updated_at, version FROM tiles WHERE dashboard_name = ?1",
+        )?;
+        let rows = statement.query_map([dashboard_name], row_to_tile)?;
+        let mut tiles = Vec::new();
+        for tile in rows {
+            tiles.push(tile?);
+        }
+        Ok(tiles)
+    }
+
+    fn upsert_tile(
+        &self,
+        dashboard_name: &str,
+        tile_id: &str,
+        tile_json: &str,
+        updated_at: i64,
+        expected_version: Option<u64>,
+    ) -> Result<Option<u64>, DashboardError> {
+        // immediate, so nobody writes between version check and upsert
+        let tx = Transaction::new_unchecked(&self.connection, TransactionBehavior::Immediate)?;
+        if let Some(expected) = expected_version {
+            let version: i64 = tx.query_row(
+                "SELECT coalesce(max(version), 0) FROM tiles
+                 WHERE dashboard_name = ?1 AND tile_id = ?2",
+                [dashboard_name, tile_id],
+                |row| row.get(0),
+            )?;
+            if version as u64 != expected {
+                return Ok(None);
+            }
+        }
+        let version = upsert_tile_row(&tx, dashboard_name, tile_id, tile_json, updated_at)?;
+        tx.commit()?;
+        Ok(Some(version))
+    }
+
+    fn upsert_tiles(
+        &self,
+        dashboard_name: &str,
+        tiles: &[(String, TileSample)],
+        retention: &Retention,
+    ) -> Result<(), DashboardError> {
+        let tx = self.connection.unchecked_transaction()?;
+        for &(ref tile_id, ref sample) in tiles {
+            upsert_tile_row(
+                &tx,
+                dashboard_name,
+                tile_id,
+                &serde_json::to_string(&sample.data)?,
+                sample.timestamp,
+            )?;
+            insert_tile_sample(&tx, dashboard_name, tile_id, sample, retention)?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn delete_tile(&self, dashboard_name: &str, tile_id: &str) -> Result<bool, DashboardError> {
+        let tx = self.connection.unchecked_transaction()?;
+        for table in &["tile_samples", "metric_points"] {
+            tx.execute(
+                &format!(
+                    "DELETE FROM {} WHERE dashboard_name = ?1 AND tile_id = ?2",
+                    table
+                ),
+                [dashboard_name, tile_id],
+            )?;
+        }
+        let count = tx.execute(
+            "DELETE FROM tiles WHERE dashboard_name = ?1 AND tile_id = ?2",
+            [dashboard_name, tile_id],
+        )?;
+        tx.commit()?;
+        Ok(count > 0)
+    }
+
+    fn add_tile_sample(
+        &self,
+        dashboard_name: &str,
+        tile_id: &str,
+        sample: &TileSample,
+        retention: &Retention,
+    ) -> Result<(), DashboardError> {
+        let tx = self.connection.unchecked_transaction()?;
+        insert_tile_sample(&tx, dashboard_name, tile_id, sample, retention)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn get_tile_history(
+        &self,
+        dashboard_name: &str,
+        tile_id: &str,
+        from: i64,
+        to: i64,
+        limit: usize,
+    ) -> Result<Vec<TileSample>, DashboardError> {
+        let mut stmt = self.connection.prepare(
+            "SELECT timestamp, data FROM tile_samples
+             WHERE dashboard_name = ?1 AND tile_id = ?2
+                 AND timestamp BETWEEN ?3 AND ?4
+             ORDER BY timestamp DESC, rowid DESC LIMIT ?5",
+        )?;
+        let rows = stmt.query_map(
+            (dashboard_name, tile_id, from, to, limit as i64),
+            |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?)),
+        )?;
+        let mut samples = Vec::new();
+        for row in rows {
+            let (timestamp, json) = row?;
+            samples.push(TileSample {
+                timestamp: timestamp,
+                data: serde_json::from_str(&json)?,
+            });
+        }
+        samples.reverse();
+        Ok(samples)
+    }
+
+    fn add_metric_point(
+        &self,
+        dashboard_name: &str,
+        tile_id: &str,
+        point: &MetricPoint,
+        max_points: usize,
+    ) -> Result<(), DashboardError> {
+        let tx = self.connection.unchecked_transaction()?;
+        insert_metric_point(&tx, dashboard_name, tile_id, point)?;
+        tx.execute(
+            "DELETE FROM metric_points
+             WHERE dashboard_name = ?1 AND tile_id = ?2 AND rowid NOT IN (
+                 SELECT rowid FROM metric_points
+                 WHERE dashboard_name = ?1 AND tile_id = ?2
+                 ORDER BY timestamp DESC, rowid DESC LIMIT ?3
+             )",
+            (dashboard_name, tile_id, max_points as i64),
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn get_metric_points(
+        &self,
+        dashboard_name: &str,
+        tile_id: &str,
+        from: i64,
+        to: i64,
+    ) -> Result<Vec<MetricPoint>, DashboardError> {
+        let mut stmt = self.connection.prepare(
+            "SELECT timestamp, count, sum, min, max, last FROM metric_points
+             WHERE dashboard_name = ?1 AND tile_id = ?2
+                 AND timestamp BETWEEN ?3 AND ?4
+             ORDER BY timestamp, rowid",
+        )?;
+        let rows = stmt.query_map((dashboard_name, tile_id, from, to), |row| {
+            Ok(MetricPoint {
+                timestamp: row.get(0)?,
+                count: row.get::<_, i64>(1)? as u64,
+                sum: row.get(2)?,
+                min: row.get(3)?,
+                max: row.get(4)?,
+                last: row.get(5)?,
+            })
+        })?;
+        let mut points = Vec::new();
+        for point in rows {
+            points.push(point?);
+        }
+        Ok(points)
+    }
+
+    fn compact_metric_points(
+        &self,
+        dashboard_name: &str,
+        tile_id: &str,
+        before: i64,
+        points: &[MetricPoint],
+    ) -> Result<(), DashboardError> {
+        let tx = self.connection.unchecked_transaction()?;
+        tx.execute(
+            "DELETE FROM metric_points
+             WHERE dashboard_name = ?1 AND tile_id = ?2 AND timestamp < ?3",
+            (dashboard_name, tile_id, before),
+        )?;
+        for point in points {
+            insert_metric_point(&tx, dashboard_name, tile_id, point)?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn get_metric_series(&self) -> Result<Vec<(String, String)>, DashboardError> {
+        let mut stmt = self.connection
+            .prepare("SELECT DISTINCT dashboard_name, tile_id FROM metric_points")?;
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        let mut series = Vec::new();
+        for pair in rows {
+            series.push(pair?);
+        }
+        Ok(series)
+    }
+
+    fn set_api_token_used(
+        &self,
+        dashboard_name: &str,
+        token_name: &str,
+        used_at: i64,
+    ) -> Result<(), DashboardError> {
+        self.connection.execute(
+            "INSERT INTO api_tokens_used (dashboard_name, token_name, used_at)
+             VALUES (?1, ?2, ?3)
+             ON CONFLICT (dashboard_name, token_name) DO UPDATE SET used_at = excluded.used_at",
+            (dashboard_name, token_name, used_at),
+        )?;
+        Ok(())
+    }
+
+    fn get_api_tokens_used(
+        &self,
+        dashboard_name: &str,
+    ) -> Result<HashMap<String, i64>, DashboardError> {
+        let mut stmt = self.connection.prepare(
+            "SELECT token_name, used_at FROM api_tokens_used WHERE dashboard_name = ?1",
+        )?;
+        let rows = stmt.query_map([dashboard_name], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        let mut used = HashMap::new();
+        for row in rows {
+            let (token_name, used_at) = row?;
+            used.insert(token_name, used_at);
+        }
+        Ok(used)
+    }
+
+    fn add_nonce(
+        &self,
+        dashboard_name: &str,
+        nonce: &str,
+        now: i64,
+        expires_at: i64,
+    ) -> Result<bool, DashboardError> {
+        let tx = self.connection.unchecked_transaction()?;
+        tx.execute("DELETE FROM nonces WHERE expires_at <= ?1", [now])?;
+        let added = tx.execute(
+            "INSERT OR IGNORE INTO nonces (dashboard_name, nonce, expires_at)
+             VALUES (?1, ?2, ?3)",
+            (dashboard_name, nonce, expires_at),
+        )?;
+        tx.commit()?;
+        Ok(added == 1)
+    }
+
+    fn migrate(&self) -> Result<(), DashboardError> {
+        migrate(&self.connection).map_err(|e| {
+            DashboardError::Internal(format!("Migrating sqlite schema FAILED ({})", e))
+        })
+    }
+
+    fn publish(&self, channel: &str, message: &str) -> Result<(), DashboardError> {
+        BROKER.publish(channel, message)
+    }
+
+    fn subscribe(&self, channel: &str) -> Result<Box<Subscription>, DashboardError> {
+        BROKER.subscribe(channel)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dashboard(name: &str) -> Dashboard {
+        Dashboard::new(
+            name.to_string(),
+            "login@email.com".to_string(),
+            "2x4".to_string(),
+        )
+    }
+
+    /// Returns new in-memory database with up to date schema
+    fn open_migrated() -> SqliteStorage {
+        let storage = SqliteStorage::open(":memory:").unwrap();
+        storage.migrate().unwrap();
+        storage
+    }
+
+    #[test]
+    fn migrate_applies_all_migrations() {
+        let storage = open_migrated();
+
+        let applied: i64 = storage
+            .connection
+            .query_row("PRAGMA user_version", [], |row| row.get(0))
+            .unwrap();
+
+        assert_eq!(applied as usize, MIGRATIONS.len());
+    }
+
+    #[test]
+    fn open_doesnt_touch_schema() {
+        let storage = SqliteStorage::open(":memory:").unwrap();
+
+        assert_eq!(applied_migrations(&storage.connection).unwrap(), 0);
+    }
+
+    #[test]
+    fn migrate_skips_applied_migrations() {
+        let storage = open_migrated();
+
+        migrate(&storage.connection).unwrap();
+    }
+
+    #[test]
+    fn create_dashboard_fails_when_name_is_taken() {
+        let storage = open_migrated();
+        storage.create_dashboard(&dashboard("taken")).unwrap();
+
+        let result = storage.create_dashboard(&dashboard("taken"));
+
+        assert_eq!(
+            result,
+            Err(DashboardError::Conflict(
+                "Dashboard taken already exists".to_string(),
+            ))
+        );
+    }
+
+    #[test]
+    fn upsert_dashboard_overwrites_existing_one() {
+        let storage = open_migrated();
+        let mut dashboard = dashboard("some-name");
+        storage.create_dashboard(&dashboard).unwrap();
+        dashboard.owner_email = "other@email.com".to_string();
+
+        storage.upsert_dashboard(&dashboard).unwrap();
+
+        let saved = storage.get_dashboard("some-name").unwrap().unwrap();
+        assert_eq!(saved.owner_email, "other@email.com");
+        assert_eq!(storage.get_dashboards().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn get_tile_returns_last_upserted_tile() {
+        let storage = open_migrated();
+        storage.upsert_tile("dashboard", "tile", "{}", 1, None).unwrap();
+
+        storage.upsert_tile("dashboard", "tile", "{\"a\":1}", 2, None).unwrap();
+
+        assert_eq!(
+            storage.get_tile("dashboard", "tile").unwrap().map(|tile| tile.data),
+            Some("{\"a\":1}".to_string())
+        );
+        assert_eq!(storage.get_tile("dashboard", "other").unwrap(), None);
+    }
+
+    #[test]
+    fn get_tiles_returns_last_update_of_each_tile() {
+        let storage = open_migrated();
+        storage.upsert_tile("dashboard", "tile", "{}", 1, None).unwrap();
+        storage.upsert_tile("dashboard", "tile", "{}", 2, None).unwrap();
+        storage.upsert_tile("other", "tile", "{}", 3, None).unwrap();
+
+        let tiles = storage.get_tiles("dashboard").unwrap();
+
+        assert_eq!(tiles.len(), 1);
+        assert_eq!(tiles[0].updated_at, Some(2));
+        assert_eq!(tiles[0].version, 2);
+    }
+
+    #[test]
+    fn upsert_tile_skips_write_when_version_is_not_expected_one() {
+        let storage = open_migrated();
+        storage.upsert_tile("dashboard", "tile", "{}", 1, None).unwrap();
+
+        let stale = storage.upsert_tile("dashboard", "tile", "{\"a\":1}", 2, Some(0));
+        let fresh = storage.upsert_tile("dashboard", "tile", "{\"a\":2}", 3, Some(1));
+
+        assert_eq!(stale.unwrap(), None);
+        assert_eq!(fresh.unwrap(), Some(2));
+        assert_eq!(
+            storage.get_tile("dashboard", "tile").unwrap().map(|tile| tile.data),
+            Some("{\"a\":2}".to_string())
+        );
+    }
+
+    #[test]
+    fn add_tile_sample_keeps_at_most_max_samples() {
+        let storage = open_migrated();
+        let retention = Retention {
+            max_samples: Some(2),
+            max_age: None,
+        };
+
+        for timestamp in 1..4 {
+            let sample = TileSample {
+                timestamp: timestamp,
+                data: json!({"value": timestamp}),
+            };
+            storage
+                .add_tile_sample("dashboard", "tile", &sample, &retention)
+                .unwrap();
+        }
+
+        let history = storage
+            .get_tile_history("dashboard", "tile", 0, 100, 10)
+            .unwrap();
+        let timestamps: Vec<i64> = history.iter().map(|s| s.timestamp).collect();
+        assert_eq!(timestamps, vec![2, 3]);
+        assert_eq!(history[1].data, json!({"value": 3}));
+    }
+
+    #[test]
+    fn compact_metric_points_replaces_only_old_points() {
+        let storage = open_migrated();
+        for timestamp in 1..5 {
+            storage
+                .add_metric_point(
+                    "dashboard",
+                    "tile",
+                    &MetricPoint::new(timestamp, 1.0),
+                    10,
+                )
+                .unwrap();
+        }
+        let mut rolled = MetricPoint::new(0, 1.0);
+        rolled.count = 2;
+
+        storage
+            .compact_metric_points("dashboard", "tile", 3, &[rolled.clone()])
+            .unwrap();
+
+        let points = storage
+            .get_metric_points("dashboard", "tile", 0, 10)
+            .unwrap();
+        let timestamps: Vec<i64> = points.iter().map(|p| p.timestamp).collect();
+        assert_eq!(timestamps, vec![0, 3, 4]);
+        assert_eq!(points[0], rolled);
+        assert_eq!(
+            storage.get_metric_series().unwrap(),
+            vec![("dashboard".to_string(), "tile".to_string())]
+        );
+    }
+
+    #[test]
+    fn rename_dashboard_moves_tiles() {
+        let storage = open_migrated();
+        storage.create_dashboard(&dashboard("old")).unwrap();
+        storage.upsert_tile("old", "tile", "{}", 1, None).unwrap();
+        storage.set_api_token_used("old", "ci", 1).unwrap();
+
+        storage.rename_dashboard("old", &dashboard("new")).unwrap();
+
+        assert!(storage.get_dashboard("old").unwrap().is_none());
+        assert!(storage.get_dashboard("new").unwrap().is_some());
+        assert_eq!(storage.get_tile("old", "tile").unwrap(), None);
+        assert_eq!(
+            storage.get_tile("new", "tile").unwrap().map(|tile| tile.version),
+            Some(1)
+        );
+        assert_eq!(storage.get_api_tokens_used("new").unwrap().get("ci"), Some(&1));
+        assert_eq!(
+            storage.rename_dashboard("old", &dashboard("other")),
+            Err(DashboardError::NotFound("Dashboard doesn't exist".to_string()))
+        );
+    }
+
+    #[test]
+    fn set_api_token_used_keeps_last_use() {
+        let storage = open_migrated();
+        storage.set_api_token_used("dashboard", "ci", 1).unwrap();
+        storage.set_api_token_used("dashboard", "ci", 2).unwrap();
+
+        let used = storage.get_api_tokens_used("dashboard").unwrap();
+
+        assert_eq!(used.get("ci"), Some(&2));
+    }
+
+    #[test]
+    fn add_nonce_fails_until_nonce_expires() {
+        let storage = open_migrated();
+
+        assert!(storage.add_nonce("dashboard", "n1", 0, 10).unwrap());
+        assert!(!storage.add_nonce("dashboard", "n1", 5, 15).unwrap());
+        assert!(storage.add_nonce("dashboard", "n1", 10, 20).unwrap());
+    }
+
+    #[test]
+    fn delete_dashboard_deletes_its_tiles_only() {
+        let storage = open_migrated();
+        storage.create_dashboard(&dashboard("gone")).unwrap();
+        storage.upsert_tile("gone", "tile", "{}", 1, None).unwrap();
+        storage.upsert_tile("kept", "tile", "{}", 1, None).unwrap();
+
+        assert_eq!(storage.delete_dashboard("gone"), Ok(1));
+
+        assert_eq!(storage.get_tile("gone", "tile").unwrap(), None);
+        assert!(storage.delete_tile("kept", "tile").unwrap());
+        assert!(!storage.delete_tile("kept", "tile").unwrap());
+    }
+}
@@ -1,4 +1,20 @@
 use db;
+use std::env;
+use storage::MemoryStorage;
+use utils;
+
+/// Loads default config but keeps data in memory, so no redis is needed
+pub fn load_config() {
+    env::set_var("DASHBOARD_STORAGE", "memory");
+    utils::load_config(None);
+}
+
+/// Works like `load_config`, but test gets empty storage of its own, which
+/// is used by handlers it calls in its thread
+pub fn load_config_with_own_storage() {
+    load_config();
+    MemoryStorage::use_own();
+}
 
 pub fn upsert_dashboard(db: &db::Db, dashboard_name: &str) -> db::Dashboard {
     let dashboard = db::Dashboard::new(
@@ -7,5 +23,6 @@ pub fn upsert_dashboard(db: &db::Db, dashboard_name: &str) -> db::Dashboard {
         "2x4".to_string(),
     );
     db.upsert_dashboard(&dashboard).unwrap();
-    db.get_dashboard(dashboard_name).unwrap().unwrap()
+    // dashboard read back from db wouldn't have plain token
+    dashboard
 }
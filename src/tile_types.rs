@@ -0,0 +1,196 @@
+//! Kinds of tiles known to server
+//!
+//! Every type has JSON Schema of its data and defaults for missing fields,
+//! tiles declared with a type (`Dashboard.tile_types`) are normalised and
+//! checked against it on ingest.
+
+use error::DashboardError;
+use schema;
+use serde_json;
+use serde_json::Value;
+
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TileType {
+    pub name: &'static str,
+    pub description: &'static str,
+    /// JSON Schema of tile data
+    pub schema: Value,
+    /// fields set on tile data when they are missing
+    pub defaults: Value,
+}
+
+impl TileType {
+    fn new(name: &'static str, description: &'static str, schema: &str, defaults: &str) -> TileType {
+        TileType {
+            name: name,
+            description: description,
+            schema: serde_json::from_str(schema).expect("tile type schema is not JSON"),
+            defaults: serde_json::from_str(defaults).expect("tile type defaults are not JSON"),
+        }
+    }
+
+    /// Fills missing fields of `tile_data` with defaults and fixes values
+    /// which are clearly meant (like number sent as string), then checks it
+    /// against type schema
+    pub fn normalize(&self, mut tile_data: Value) -> Result<Value, DashboardError> {
+        if let (Some(tile_obj), Some(defaults)) =
+            (tile_data.as_object_mut(), self.defaults.as_object())
+        {
+            for (key, value) in defaults {
+                tile_obj.entry(key.clone()).or_insert_with(|| value.clone());
+            }
+        }
+        match self.name {
+            "number" | "gauge" => {
+                let parsed = match tile_data.get("value") {
+                    Some(&Value::String(ref v)) => v.trim().parse::<f64>().ok(),
+                    _ => None,
+                };
+                if let Some(number) = parsed.and_then(serde_json::Number::from_f64) {
+                    tile_data["value"] = Value::Number(number);
+                }
+            }
+            "status-list" => {
+                if let Some(items) = tile_data.get_mut("items").and_then(|i| i.as_array_mut()) {
+                    for item in items {
+                        let status = match item.get("status") {
+                            Some(&Value::String(ref v)) => v.to_lowercase(),
+                            _ => continue,
+                        };
+                        item["status"] = Value::String(status);
+                    }
+                }
+            }
+            _ => (),
+        }
+        let violations = schema::validate(&self.schema, &tile_data);
+        if !violations.is_empty() {
+            return Err(DashboardError::SchemaMismatch(violations));
+        }
+        Ok(tile_data)
+    }
+}
+
+
+lazy_static! {
+    static ref TILE_TYPES: Vec<TileType> = vec![
+        TileType::new(
+            "text",
+            "Markdown text",
+            r#"{"type": "object", "required": ["text"],
+                "properties": {"title": {"type": "string"}, "text": {"type": "string"}}}"#,
+            r#"{"title": ""}"#,
+        ),
+        TileType::new(
+            "number",
+            "Single number with unit",
+            r#"{"type": "object", "required": ["value"],
+                "properties": {"title": {"type": "string"}, "value": {"type": "number"},
+                               "unit": {"type": "string"}}}"#,
+            r#"{"title": "", "unit": ""}"#,
+        ),
+        TileType::new(
+            "gauge",
+            "Number shown on scale between min and max",
+            r#"{"type": "object", "required": ["value"],
+                "properties": {"title": {"type": "string"}, "value": {"type": "number"},
+                               "min": {"type": "number"}, "max": {"type": "number"},
+                               "unit": {"type": "string"}}}"#,
+            r#"{"title": "", "min": 0, "max": 100, "unit": ""}"#,
+        ),
+        TileType::new(
+            "line-chart",
+            "Series of numbers drawn as lines",
+            r#"{"type": "object", "required": ["datasets"],
+                "properties": {
+                    "title": {"type": "string"},
+                    "labels": {"type": "array"},
+                    "datasets": {"type": "array", "items": {
+                        "type": "object", "required": ["data"],
+                        "properties": {"label": {"type": "string"},
+                                       "data": {"type": "array", "items": {"type": "number"}}}}}}}"#,
+            r#"{"title": "", "labels": []}"#,
+        ),
+        TileType::new(
+            "table",
+            "Rows of cells under column names",
+            r#"{"type": "object", "required": ["rows"],
+                "properties": {
+                    "title": {"type": "string"},
+                    "columns": {"type": "array", "items": {"type": "string"}},
+                    "rows": {"type": "array", "items": {"type": "array"}}}}"#,
+            r#"{"title": "", "columns": []}"#,
+        ),
+        TileType::new(
+            "status-list",
+            "Named items with status: ok, warning, error or unknown",
+            r#"{"type": "object", "required": ["items"],
+                "properties": {
+                    "title": {"type": "string"},
+                    "items": {"type": "array", "items": {
+                        "type": "object", "required": ["name", "status"],
+                        "properties": {"name": {"type": "string"},
+                                       "status": {"enum": ["ok", "warning", "error", "unknown"]}}}}}}"#,
+            r#"{"title": ""}"#,
+        ),
+        TileType::new(
+            "image",
+            "Image at url",
+            r#"{"type": "object", "required": ["url"],
+                "properties": {"title": {"type": "string"}, "url": {"type": "string"}}}"#,
+            r#"{"title": ""}"#,
+        ),
+    ];
+}
+
+
+/// Returns every known tile type
+pub fn all() -> &'static [TileType] {
+    &TILE_TYPES
+}
+
+/// Returns tile type called `name`
+pub fn get(name: &str) -> Option<&'static TileType> {
+    TILE_TYPES.iter().find(|tile_type| tile_type.name == name)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_fills_defaults_and_parses_numbers() {
+        let gauge = get("gauge").unwrap();
+
+        let tile_data = gauge.normalize(json!({"value": " 42.5"})).unwrap();
+
+        assert_eq!(
+            tile_data,
+            json!({"title": "", "value": 42.5, "min": 0, "max": 100, "unit": ""})
+        );
+    }
+
+    #[test]
+    fn normalize_fails_when_data_mismatches_type() {
+        let status_list = get("status-list").unwrap();
+
+        let result = status_list.normalize(json!({"items": [{"name": "db", "status": "BROKEN"}]}));
+
+        match result {
+            Err(DashboardError::SchemaMismatch(violations)) => {
+                assert_eq!(violations[0].path, "/items/0/status")
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn all_types_have_valid_schemas() {
+        for tile_type in all() {
+            assert!(schema::check_schema(&tile_type.schema).is_ok());
+            assert!(tile_type.defaults.is_object());
+        }
+    }
+}
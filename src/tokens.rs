@@ -0,0 +1,344 @@
+//! API tokens of dashboards
+//!
+//! Dashboard has a few named tokens, any of them which hasn't expired lets
+//! its holder do what its scope allows. Only salted SHA-256 hashes of tokens
+//! are stored, plain token is shown once when it's generated. Time of last
+//! use is kept apart from dashboard (see `Db::touch_api_token`), so using a
+//! token doesn't rewrite the whole dashboard.
+
+use error::DashboardError;
+use sha2::{Digest, Sha256};
+use utils::{constant_time_eq, to_hex};
+use uuid::Uuid;
+
+
+/// Name of token generated together with dashboard
+pub const DEFAULT_TOKEN_NAME: &'static str = "default";
+
+
+/// What token lets its holder do
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum TokenScope {
+    /// everything, managing dashboard and its tokens included
+    Full,
+    /// writing and reading tiles with ids matching `tiles` glob (`*` matches
+    /// any characters, `?` single one)
+    Write { tiles: String },
+    /// reading tiles and showing dashboard
+    Read,
+    /// reading history and metrics of tiles
+    History,
+}
+
+impl Default for TokenScope {
+    fn default() -> TokenScope {
+        TokenScope::Full
+    }
+}
+
+impl TokenScope {
+    /// Checks whether scope can be given to token
+    pub fn validate(&self) -> Result<(), DashboardError> {
+        match *self {
+            TokenScope::Write { ref tiles } if tiles.is_empty() => Err(
+                DashboardError::InvalidPayload("Glob of tiles can't be empty".to_string()),
+            ),
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks whether scope allows `access`
+    pub fn allows(&self, access: &Access) -> bool {
+        match (self, access) {
+            (_, &Access::ShowPage) => *self == TokenScope::Read,
+            (&TokenScope::Full, _) => true,
+            (&TokenScope::Write { ref tiles }, &Access::WriteTile(tile_id)) |
+            (&TokenScope::Write { ref tiles }, &Access::ReadTile(tile_id)) |
+            (&TokenScope::Write { ref tiles }, &Access::ReadHistory(tile_id)) => {
+                glob_matches(tiles, tile_id)
+            }
+            (&TokenScope::Write { .. }, &Access::ReadSchemas) |
+            (&TokenScope::Read, &Access::ReadTile(_)) |
+            (&TokenScope::Read, &Access::ShowDashboard) |
+            (&TokenScope::Read, &Access::ReadSchemas) => true,
+            (&TokenScope::History, &Access::ReadHistory(_)) => true,
+            _ => false,
+        }
+    }
+}
+
+
+/// What request wants to do with dashboard
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Access<'a> {
+    /// change dashboard itself or its tokens
+    Manage,
+    /// show dashboard page or listen to changes of all its tiles
+    ShowDashboard,
+    /// like `ShowDashboard`, but with token from URL, only read tokens are
+    /// taken there as URLs end up in browser history and access logs
+    ShowPage,
+    /// read schemas and tile types, so tiles can be validated before writing
+    ReadSchemas,
+    WriteTile(&'a str),
+    ReadTile(&'a str),
+    ReadHistory(&'a str),
+}
+
+impl<'a> Access<'a> {
+    /// Returns error raised when token scope doesn't allow access
+    pub fn denied(&self) -> DashboardError {
+        let msg = match *self {
+            Access::Manage => "Token can't manage dashboard".to_string(),
+            Access::ShowDashboard => "Token can't show dashboard".to_string(),
+            Access::ShowPage => "Only read token can be given in URL".to_string(),
+            Access::ReadSchemas => "Token can't read schemas".to_string(),
+            Access::WriteTile(tile_id) => format!("Token can't write tile {}", tile_id),
+            Access::ReadTile(tile_id) => format!("Token can't read tile {}", tile_id),
+            Access::ReadHistory(tile_id) => {
+                format!("Token can't read history of tile {}", tile_id)
+            }
+        };
+        DashboardError::AuthFailed(msg)
+    }
+}
+
+
+/// Checks whether whole `text` matches `pattern` where `*` matches any
+/// characters and `?` single one
+fn glob_matches(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // position of last `*` and of text matched by it, to backtrack there
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = star {
+            p = star_p + 1;
+            t = star_t + 1;
+            star = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ApiToken {
+    pub name: String,
+    /// plain token saved before tokens were hashed, it's hashed when
+    /// dashboard is read
+    #[serde(default, skip_serializing)]
+    token: Option<String>,
+    #[serde(default)]
+    salt: String,
+    /// hex of SHA-256 of salt followed by token
+    #[serde(default)]
+    hash: String,
+    /// milliseconds since unix epoch, 0 for tokens made before it was tracked
+    pub created_at: i64,
+    /// milliseconds since unix epoch, `None` for token which never expires
+    #[serde(default)]
+    pub expires_at: Option<i64>,
+    #[serde(default)]
+    pub scope: TokenScope,
+}
+
+impl ApiToken {
+    /// Returns token called `name` with newly generated secret, the secret
+    /// can't be read from token later
+    pub fn generate(name: String, created_at: i64, expires_at: Option<i64>) -> (ApiToken, String) {
+        let secret = Uuid::new_v4().to_string();
+        let api_token = ApiToken::from_plain(name, &secret, created_at, expires_at);
+        (api_token, secret)
+    }
+
+    /// Returns token called `name` which accepts `secret`
+    pub fn from_plain(
+        name: String,
+        secret: &str,
+        created_at: i64,
+        expires_at: Option<i64>,
+    ) -> ApiToken {
+        let salt = Uuid::new_v4().simple().to_string();
+        ApiToken {
+            name: name,
+            token: None,
+            hash: hash(&salt, secret),
+            salt: salt,
+            created_at: created_at,
+            expires_at: expires_at,
+            scope: TokenScope::Full,
+        }
+    }
+
+    /// Replaces plain token saved in old format with its hash, returns
+    /// `false` when there was nothing to hash
+    pub fn hash_plain(&mut self) -> bool {
+        let secret = match self.token.take() {
+            None => return false,
+            Some(v) => v,
+        };
+        self.salt = Uuid::new_v4().simple().to_string();
+        self.hash = hash(&self.salt, &secret);
+        true
+    }
+
+    /// Checks whether `secret` is this token, in time which doesn't depend on
+    /// how much of it matches
+    pub fn matches(&self, secret: &str) -> bool {
+        if self.hash.is_empty() {
+            return false;
+        }
+        constant_time_eq(hash(&self.salt, secret).as_bytes(), self.hash.as_bytes())
+    }
+
+    /// Checks whether token can be used at `now`
+    pub fn is_active(&self, now: i64) -> bool {
+        self.expires_at.map_or(true, |expires_at| now < expires_at)
+    }
+}
+
+/// Returns hex of SHA-256 of `salt` followed by `secret`
+fn hash(salt: &str, secret: &str) -> String {
+    let digest = Sha256::new()
+        .chain_update(salt.as_bytes())
+        .chain_update(secret.as_bytes())
+        .finalize();
+    to_hex(&digest)
+}
+
+
+/// Token as listed to dashboard owner, without its hash
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ApiTokenInfo {
+    pub name: String,
+    pub created_at: i64,
+    pub expires_at: Option<i64>,
+    pub scope: TokenScope,
+    /// milliseconds since unix epoch, `None` when token wasn't used yet
+    pub last_used_at: Option<i64>,
+}
+
+impl ApiTokenInfo {
+    /// Describes `token` last used at `used_at`, uses from before token was
+    /// created belong to revoked token of the same name
+    pub fn new(token: &ApiToken, used_at: Option<i64>) -> ApiTokenInfo {
+        ApiTokenInfo {
+            name: token.name.clone(),
+            created_at: token.created_at,
+            expires_at: token.expires_at,
+            scope: token.scope.clone(),
+            last_used_at: used_at.and_then(|at| if at >= token.created_at {
+                Some(at)
+            } else {
+                None
+            }),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+
+    #[test]
+    fn is_active_fails_when_token_has_expired() {
+        let (token, _) = ApiToken::generate("ci".to_string(), 10, Some(20));
+
+        assert!(token.is_active(19));
+        assert!(!token.is_active(20));
+        assert!(
+            ApiToken::generate("ci".to_string(), 10, None)
+                .0
+                .is_active(i64::max_value())
+        );
+    }
+
+    #[test]
+    fn generated_token_matches_only_its_secret() {
+        let (token, secret) = ApiToken::generate("ci".to_string(), 10, None);
+
+        assert!(token.matches(&secret));
+        assert!(!token.matches("other-secret"));
+        assert!(!serde_json::to_string(&token).unwrap().contains(&secret));
+    }
+
+    #[test]
+    fn hash_plain_hashes_token_saved_in_old_format() {
+        let mut token: ApiToken = serde_json::from_str(
+            r#"{"name": "ci", "token": "plain", "created_at": 10, "expires_at": null}"#,
+        ).unwrap();
+        assert!(!token.matches("plain"));
+
+        assert!(token.hash_plain());
+
+        assert!(token.matches("plain"));
+        assert!(!token.hash_plain());
+        assert!(!serde_json::to_string(&token).unwrap().contains("plain"));
+    }
+
+    #[test]
+    fn glob_matches_whole_tile_id() {
+        assert!(glob_matches("ci-*", "ci-build"));
+        assert!(glob_matches("ci-*", "ci-"));
+        assert!(glob_matches("*-status-?", "ci-build-status-1"));
+        assert!(glob_matches("*a*b", "xaxxab"));
+        assert!(!glob_matches("ci-*", "deploy-ci-build"));
+        assert!(!glob_matches("ci-?", "ci-12"));
+        assert!(!glob_matches("*a*b", "xaxxabc"));
+    }
+
+    #[test]
+    fn write_scope_allows_only_matching_tiles() {
+        let scope = TokenScope::Write { tiles: "ci-*".to_string() };
+
+        assert!(scope.allows(&Access::WriteTile("ci-build")));
+        assert!(scope.allows(&Access::ReadHistory("ci-build")));
+        assert!(!scope.allows(&Access::WriteTile("deploy")));
+        assert!(!scope.allows(&Access::Manage));
+        assert!(!scope.allows(&Access::ShowDashboard));
+        assert!(TokenScope::Read.allows(&Access::ShowDashboard));
+        assert!(!TokenScope::Read.allows(&Access::WriteTile("ci-build")));
+        assert!(!TokenScope::History.allows(&Access::ReadTile("ci-build")));
+        assert!(scope.allows(&Access::ReadSchemas));
+    }
+
+    #[test]
+    fn only_read_scope_allows_showing_page() {
+        assert!(TokenScope::Read.allows(&Access::ShowPage));
+        assert!(!TokenScope::Full.allows(&Access::ShowPage));
+        assert!(!TokenScope::History.allows(&Access::ShowPage));
+    }
+
+    #[test]
+    fn scope_is_full_when_token_has_none() {
+        let token: ApiToken = serde_json::from_str(
+            r#"{"name": "ci", "salt": "s", "hash": "h", "created_at": 10}"#,
+        ).unwrap();
+        let scope: TokenScope =
+            serde_json::from_str(r#"{"kind": "write", "tiles": "ci-*"}"#).unwrap();
+
+        assert_eq!(token.scope, TokenScope::Full);
+        assert_eq!(scope, TokenScope::Write { tiles: "ci-*".to_string() });
+    }
+
+    #[test]
+    fn info_skips_use_of_older_token() {
+        let (token, _) = ApiToken::generate("ci".to_string(), 10, None);
+
+        assert_eq!(ApiTokenInfo::new(&token, Some(5)).last_used_at, None);
+        assert_eq!(ApiTokenInfo::new(&token, Some(15)).last_used_at, Some(15));
+    }
+}
@@ -4,10 +4,12 @@ use iron::prelude::*;
 use iron::status::Status;
 use redis;
 use redis::Connection;
+use serde_json;
 use std::clone::Clone;
 use std::env;
 use std::iter::Iterator;
 use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 pub fn redis_url(ip_port: &str) -> String {
     format!("redis://{}/", ip_port)
@@ -34,6 +36,14 @@ pub fn from_config(key: &str) -> String {
     )
 }
 
+/// Returns milliseconds since unix epoch
+pub fn timestamp_ms() -> i64 {
+    let since_epoch = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("System clock is set before unix epoch");
+    since_epoch.as_secs() as i64 * 1000 + since_epoch.subsec_nanos() as i64 / 1_000_000
+}
+
 pub fn json_response(status: Status, payload: &str) -> IronResult<Response> {
     Ok(Response::with((ContentType::json().0, status, payload)))
 }
@@ -72,6 +82,47 @@ pub fn get_page_items<T: Clone + Iterator>(
     Ok((collection, max_page))
 }
 
+/// Returns lowercase hex of `bytes`
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Checks whether `a` equals `b` in time which doesn't depend on how much of
+/// them matches
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Applies JSON merge `patch` (RFC 7396) to `target`
+pub fn merge_patch(target: &mut serde_json::Value, patch: &serde_json::Value) {
+    let patch_obj = match patch.as_object() {
+        None => {
+            *target = patch.clone();
+            return;
+        }
+        Some(v) => v,
+    };
+    if !target.is_object() {
+        *target = serde_json::Value::Object(serde_json::Map::new());
+    }
+    let target_obj = target.as_object_mut().unwrap();
+    for (key, value) in patch_obj {
+        if value.is_null() {
+            target_obj.remove(key);
+        } else {
+            merge_patch(
+                target_obj
+                    .entry(key.clone())
+                    .or_insert(serde_json::Value::Null),
+                value,
+            );
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -101,6 +152,30 @@ mod tests {
         get_redis_con(from_config("DASHBOARD_REDIS_IP_PORT").as_str()).unwrap();
     }
 
+    #[test]
+    fn merge_patch_replaces_removes_and_nests_values() {
+        let mut target = json!({"a": "b", "c": {"d": "e", "f": "g"}, "h": [1]});
+
+        merge_patch(
+            &mut target,
+            &json!({"a": "z", "c": {"f": null, "x": 1}, "h": [2, 3]}),
+        );
+
+        assert_eq!(
+            target,
+            json!({"a": "z", "c": {"d": "e", "x": 1}, "h": [2, 3]})
+        );
+    }
+
+    #[test]
+    fn merge_patch_replaces_target_when_patch_is_not_object() {
+        let mut target = json!({"a": 1});
+
+        merge_patch(&mut target, &json!([1]));
+
+        assert_eq!(target, json!([1]));
+    }
+
     #[test]
     fn get_page_items_works_ok() {
         let iter = "0123456".chars();
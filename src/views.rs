@@ -1,14 +1,18 @@
 use db;
+use error::DashboardError;
 use handlebars::to_json;
 use hbs::Template;
 use iron::middleware;
 use iron::prelude::*;
 use iron::status;
+use layouts;
+use layouts::{DashboardLayout, Layout};
 use params;
 use router::Router;
 use serde_json::value::{Value, Map};
 use std::str::FromStr;
 use templating;
+use tokens::Access;
 use utils::{from_config, get_page_items};
 
 
@@ -23,6 +27,11 @@ fn views_router() -> Router {
     router.get("/", dashboard_list, "home");
     router.get("/dashboard/new", dashboard_new, "dashboard_new");
     router.get("/dashboard/list", dashboard_list, "dashboard_list");
+    router.get(
+        "/dashboard/edit/:dashboard_name",
+        dashboard_edit,
+        "dashboard_edit",
+    );
     router.get(
         "/dashboard/show/:dashboard_name",
         dashboard_show,
@@ -51,38 +60,158 @@ fn dashboard_show_data(dashboard: &db::Dashboard) -> Map<String, Value> {
     data
 }
 
+/// Returns `layout` with CSS grid placement (`"style"`) of every cell, cells
+/// with many tiles are marked to `"rotate"` them
+fn grid_data(layout: &Layout) -> Value {
+    let mut data = to_json(layout);
+    for (cell, cell_data) in layout.cells.iter().zip(data["cells"].as_array_mut().unwrap()) {
+        let style = format!(
+            "grid-row: {} / span {}; grid-column: {} / span {};",
+            cell.row,
+            cell.row_span,
+            cell.column,
+            cell.column_span
+        );
+        let rotate = cell.tiles.len() > 1;
+        if !rotate {
+            for tile_data in cell_data["tiles"].as_array_mut().unwrap() {
+                tile_data["style"] = Value::String(style.clone());
+            }
+        }
+        cell_data["style"] = Value::String(style);
+        cell_data["rotate"] = Value::Bool(rotate);
+    }
+    data
+}
+
+
+/// Returns dashboard at `dashboard_name` when it can be shown together with
+/// token from `token` query param, private dashboard needs its read token
+/// there
+fn get_shown_dashboard(
+    req: &mut Request,
+    dashboard_name: &str,
+) -> Result<(db::Dashboard, Option<String>), Response> {
+    let token = match req.get_ref::<params::Params>() {
+        Err(e) => return Err(Response::with((status::InternalServerError, e.to_string()))),
+        Ok(map) => match map.find(&["token"]) {
+            Some(&params::Value::String(ref v)) => Some(v.clone()),
+            _ => None,
+        },
+    };
+    let db = match db::Db::new() {
+        Err(e) => return Err(Response::with((status::InternalServerError, e.to_string()))),
+        Ok(d) => d,
+    };
+    match db.get_readable_dashboard(dashboard_name, token.as_deref(), Access::ShowPage) {
+        Err(DashboardError::NotFound(_)) => {
+            Err(Response::with((status::NotFound, "Dashboard missing")))
+        }
+        Err(e) => Err(Response::with((e.status(), e.public_message().to_string()))),
+        Ok(d) => Ok((d, token)),
+    }
+}
 
+/// Shows dashboard, private one needs its read token in `token` query param
+/// (it's passed on to websocket)
 pub fn dashboard_show(req: &mut Request) -> IronResult<Response> {
     let dashboard_name = req.extensions
         .get::<Router>()
         .unwrap()
         .find("dashboard_name")
-        .unwrap_or("demo");
+        .unwrap_or("demo")
+        .to_string();
 
-    let db = match db::Db::new() {
-        Err(e) => return Ok(Response::with((status::InternalServerError, e.to_string()))),
-        Ok(d) => d,
+    let (dashboard, token) = match get_shown_dashboard(req, &dashboard_name) {
+        Err(response) => return Ok(response),
+        Ok(v) => v,
     };
-    let dashboard = match db.get_dashboard(dashboard_name) {
+    let layout = match dashboard.layout.resolve() {
         Err(e) => return Ok(Response::with((status::InternalServerError, e.to_string()))),
-        Ok(None) => return Ok(Response::with((status::NotFound, "Dashboard missing"))),
-        Ok(Some(d)) => d,
+        Ok(None) => return Ok(Response::with((status::InternalServerError, "Layout missing"))),
+        Ok(Some(v)) => v,
     };
-    let template = Template::new(
-        &format!("dashboards/{}", &dashboard.layout),
-        dashboard_show_data(&dashboard),
-    );
+    let mut data = dashboard_show_data(&dashboard);
+    data.insert("layout".to_string(), grid_data(&layout));
+    if let Some(ref token) = token {
+        data.insert("token".to_string(), to_json(token));
+    }
+    let template = Template::new("dashboards/grid", data);
     Ok(Response::with((status::Ok, template)))
 }
 
+/// Returns named layouts for layout picker, the one called `checked` is
+/// picked
+fn layouts_data(layouts: &[(String, Layout)], checked: &str) -> Value {
+    let layouts_data: Vec<Value> = layouts
+        .iter()
+        .map(|&(ref name, ref layout)| {
+            let mut layout_data = Map::new();
+            layout_data.insert("name".to_string(), to_json(name));
+            layout_data.insert("checked".to_string(), to_json(&(name == checked)));
+            layout_data.insert("grid".to_string(), grid_data(layout));
+            Value::Object(layout_data)
+        })
+        .collect();
+    to_json(&layouts_data)
+}
+
 pub fn dashboard_new(_req: &mut Request) -> IronResult<Response> {
+    let layouts = match layouts::all() {
+        Err(e) => return Ok(Response::with((status::InternalServerError, e.to_string()))),
+        Ok(v) => v,
+    };
+    let first = layouts.first().map_or("", |&(ref name, _)| name.as_str());
+    let mut tmplt_data = Map::new();
+    tmplt_data.insert("layouts".to_string(), layouts_data(&layouts, first));
     let mut resp = Response::new();
-    resp.set_mut(Template::new("dashboard-new", Map::new()))
+    resp.set_mut(Template::new("dashboard-new", tmplt_data))
         .set_mut(status::Ok);
     Ok(resp)
 
 }
 
+/// Shows form changing name, owner email and layout of dashboard, changes are
+/// sent to `gui_api::dashboard_patch` with dashboard token. Private dashboard
+/// needs its read token in `token` query param like in `dashboard_show`.
+pub fn dashboard_edit(req: &mut Request) -> IronResult<Response> {
+    let dashboard_name = req.extensions
+        .get::<Router>()
+        .unwrap()
+        .find("dashboard_name")
+        .unwrap()
+        .to_string();
+
+    let dashboard = match get_shown_dashboard(req, &dashboard_name) {
+        Err(response) => return Ok(response),
+        Ok((d, _)) => d,
+    };
+    let layouts = match layouts::all() {
+        Err(e) => return Ok(Response::with((status::InternalServerError, e.to_string()))),
+        Ok(v) => v,
+    };
+    // dashboard with own grid keeps it unless other layout is picked
+    let checked = match dashboard.layout {
+        DashboardLayout::Named(ref name) => name.as_str(),
+        DashboardLayout::Grid(_) => "",
+    };
+    let mut tmplt_data = Map::new();
+    tmplt_data.insert("dashboard_name".to_string(), to_json(&dashboard.name));
+    tmplt_data.insert("owner_email".to_string(), to_json(&dashboard.owner_email));
+    tmplt_data.insert(
+        "push_interval_ms".to_string(),
+        to_json(&dashboard.push_interval_ms),
+    );
+    tmplt_data.insert(
+        "private".to_string(),
+        to_json(&(dashboard.visibility == db::Visibility::Private)),
+    );
+    tmplt_data.insert("layouts".to_string(), layouts_data(&layouts, checked));
+    Ok(Response::with(
+        (status::Ok, Template::new("dashboard-edit", tmplt_data)),
+    ))
+}
+
 pub fn dashboard_list(req: &mut Request) -> IronResult<Response> {
     let map = match req.get_ref::<params::Params>() {
         Err(e) => return Ok(Response::with((status::InternalServerError, e.to_string()))),
@@ -110,9 +239,11 @@ pub fn dashboard_list(req: &mut Request) -> IronResult<Response> {
         Ok(d) => d,
     };
     let per_page = 12; //TODO: user settings
-    let dashboards = match db.get_dashboards() {
+    let dashboards: Vec<db::Dashboard> = match db.get_dashboards() {
         Err(_) => return Ok(Response::with((status::InternalServerError, err_msg))),
-        Ok(v) => v,
+        Ok(v) => v.into_iter()
+            .filter(|d| d.visibility == db::Visibility::Public)
+            .collect(),
     };
     let (dashboards, page_max) = match get_page_items(dashboards.iter(), page_current, per_page) {
         Err(e) => return Ok(Response::with((status::InternalServerError, e.to_string()))),
@@ -135,11 +266,13 @@ mod tests {
     use iron::Headers;
     use iron::status::Status;
     use iron_test::{request, response};
-    use utils;
+    use layouts::{LayoutCell, LayoutTile};
+    use test_utils;
+    use tokens::TokenScope;
 
     #[test]
     fn test_dashboard_shows_created_dashboard() {
-        utils::load_config(None);
+        test_utils::load_config();
         let dashboard_name = "Uber-dashboard-name".to_string();
         let db = db::Db::new().unwrap();
         db.delete_dashboard(&dashboard_name).unwrap();
@@ -162,10 +295,84 @@ mod tests {
     }
 
     #[test]
-    fn test_dashboard_list_works_when_dashboards_count_0() {
-        utils::load_config(None);
+    fn test_dashboard_shows_tiles_of_own_layout() {
+        test_utils::load_config();
         let db = db::Db::new().unwrap();
-        db.run_cmd("flushall").unwrap();
+        let mut dashboard = db::Dashboard::new(
+            "dashboard-own-layout".to_string(),
+            "login@email.com".to_string(),
+            "2x4".to_string(),
+        );
+        dashboard.layout = DashboardLayout::Grid(Layout {
+            rows: 1,
+            columns: 2,
+            cells: vec![
+                LayoutCell {
+                    row: 1,
+                    column: 1,
+                    row_span: 1,
+                    column_span: 2,
+                    tiles: vec![
+                        LayoutTile {
+                            tile_id: "tile-wide".to_string(),
+                            element: "tile-chart".to_string(),
+                        },
+                    ],
+                    flip_time: 1000,
+                },
+            ],
+        });
+        db.upsert_dashboard(&dashboard).unwrap();
+
+        let resp = request::get(
+            &format!("http://localhost:3000/dashboard/show/{}", dashboard.name),
+            Headers::new(),
+            &get_handler(),
+        ).unwrap();
+
+        assert_eq!(resp.status, Some(Status::Ok));
+        let body = response::extract_body_to_string(resp);
+        assert!(body.contains(
+            "<tile-chart id=\"tile-wide\" style=\"grid-row: 1 / span 1; grid-column: 1 / span 2;\">",
+        ));
+    }
+
+    #[test]
+    fn test_dashboard_new_offers_layouts() {
+        test_utils::load_config();
+
+        let resp = request::get(
+            "http://localhost:3000/dashboard/new",
+            Headers::new(),
+            &get_handler(),
+        ).unwrap();
+
+        assert_eq!(resp.status, Some(Status::Ok));
+        let body = response::extract_body_to_string(resp);
+        assert!(body.contains("<input type=\"radio\" name=\"layout\" value=\"2x4\""));
+    }
+
+    #[test]
+    fn test_dashboard_edit_shows_current_settings() {
+        test_utils::load_config();
+        let db = db::Db::new().unwrap();
+        test_utils::upsert_dashboard(&db, "dashboard-edited");
+
+        let resp = request::get(
+            "http://localhost:3000/dashboard/edit/dashboard-edited",
+            Headers::new(),
+            &get_handler(),
+        ).unwrap();
+
+        assert_eq!(resp.status, Some(Status::Ok));
+        let body = response::extract_body_to_string(resp);
+        assert!(body.contains("value=\"login@email.com\""));
+        assert!(body.contains("<input type=\"radio\" name=\"layout\" value=\"2x4\" checked>"));
+    }
+
+    #[test]
+    fn test_dashboard_list_works_when_dashboards_count_0() {
+        test_utils::load_config_with_own_storage();
 
         let resp = request::get(
             "http://localhost:3000/dashboard/list",
@@ -180,9 +387,8 @@ mod tests {
 
     #[test]
     fn test_dashboard_list_shows_13th_elem_on_second_page() {
-        utils::load_config(None);
+        test_utils::load_config_with_own_storage();
         let db = db::Db::new().unwrap();
-        db.run_cmd("flushall").unwrap();
 
         for idx in 0..14 {
             let dashboard_name = format!("dashboard-{}", idx);
@@ -204,4 +410,61 @@ mod tests {
         let body = response::extract_body_to_string(resp);
         assert_eq!(body.contains("dashboard-13"), true);
     }
+
+    /// Saves private dashboard with read token, returns it with the token
+    fn upsert_private_dashboard(db: &db::Db, dashboard_name: &str) -> (db::Dashboard, String) {
+        let mut dashboard = test_utils::upsert_dashboard(db, dashboard_name);
+        dashboard.visibility = db::Visibility::Private;
+        let read_token = dashboard
+            .add_api_token("viewer", None, TokenScope::Read)
+            .unwrap();
+        db.upsert_dashboard(&dashboard).unwrap();
+        (dashboard, read_token)
+    }
+
+    #[test]
+    fn test_private_dashboard_is_shown_only_with_read_token() {
+        test_utils::load_config();
+        let db = db::Db::new().unwrap();
+        let (dashboard, read_token) = upsert_private_dashboard(&db, "dashboard-private-show");
+        let url = format!("http://localhost:3000/dashboard/show/{}", dashboard.name);
+
+        let anonymous = request::get(&url, Headers::new(), &get_handler()).unwrap();
+        let with_full_token = request::get(
+            &format!("{}?token={}", url, dashboard.get_api_token().unwrap()),
+            Headers::new(),
+            &get_handler(),
+        ).unwrap();
+        let with_read_token = request::get(
+            &format!("{}?token={}", url, read_token),
+            Headers::new(),
+            &get_handler(),
+        ).unwrap();
+
+        assert_eq!(anonymous.status, Some(Status::Forbidden));
+        assert_eq!(with_full_token.status, Some(Status::Forbidden));
+        assert_eq!(with_read_token.status, Some(Status::Ok));
+        let body = response::extract_body_to_string(with_read_token);
+        assert!(body.contains(&format!("?token={}", read_token)));
+    }
+
+    #[test]
+    fn test_private_dashboard_edit_needs_read_token() {
+        test_utils::load_config();
+        let db = db::Db::new().unwrap();
+        let (dashboard, read_token) = upsert_private_dashboard(&db, "dashboard-private-edit");
+        let url = format!("http://localhost:3000/dashboard/edit/{}", dashboard.name);
+
+        let anonymous = request::get(&url, Headers::new(), &get_handler()).unwrap();
+        let with_read_token = request::get(
+            &format!("{}?token={}", url, read_token),
+            Headers::new(),
+            &get_handler(),
+        ).unwrap();
+
+        assert_eq!(anonymous.status, Some(Status::Forbidden));
+        let body = response::extract_body_to_string(anonymous);
+        assert!(!body.contains("login@email.com"));
+        assert_eq!(with_read_token.status, Some(Status::Ok));
+    }
 }
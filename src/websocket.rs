@@ -1,25 +1,20 @@
 use db;
-use redis;
+use error::DashboardError;
+use fanout;
 use serde_json;
-use std::error::Error;
-use std::thread;
-use utils::{from_config, redis_url};
+use tokens::Access;
 use ws;
 
 
 // TODO: rewrite it! what would i think of? xD
-// TODO: rm val_or_send_msg_err, make functions which return error and use
-// case analysis to convert (or better implement From) it to to user/client etc.
 
+/// Returns value of `$expr` or sends its error (converted to `DashboardError`)
+/// to client and returns
 macro_rules! val_or_send_msg_err {
-    ($expr: expr, $ws_server: ident, $err_msg: expr) => (match $expr {
+    ($expr: expr, $ws_server: ident) => (match $expr {
         Ok(val) => val,
         Err(e) => {
-            send_or_log_err(&$ws_server.out,
-                            &Message {
-                                kind: String::from("error"),
-                                text: format!("{} ({})", $err_msg, e),
-                            });
+            send_err(&$ws_server.out, &DashboardError::from(e));
             return Ok(())
         }
     })
@@ -30,28 +25,31 @@ pub fn run_ws_listener(ip_port: &str) {
     ws::listen(ip_port, |out| {
         Server {
             out: out,
-            redis_url: redis_url(from_config("DASHBOARD_REDIS_IP_PORT").as_str()),
             dashboard_name: None,
+            snapshot: true,
+            watch: None,
         }
     }).expect("starting websocket FAILED");
 }
 
 #[derive(Serialize, Deserialize, Debug)]
-struct Message {
+pub struct Message {
     //TODO: replace String with &str?
-    kind: String,
-    text: String,
+    pub kind: String,
+    pub text: String,
 }
 
-fn send_err<M: AsRef<str>>(sender: &ws::Sender, msg: M) {
+/// Sends `error` as `{"code": .., "message": ..}` JSON in message of kind
+/// `"error"`
+pub fn send_err(sender: &ws::Sender, error: &DashboardError) {
     let msg = Message {
         kind: "error".to_string(),
-        text: msg.as_ref().to_string(),
+        text: error.to_json(),
     };
     send_or_log_err(sender, &msg);
 }
 
-fn send_or_log_err(sender: &ws::Sender, msg: &Message) {
+pub fn send_or_log_err(sender: &ws::Sender, msg: &Message) {
     match serde_json::to_string(&msg) {
         Ok(as_str) => {
             debug!("sending: {}", as_str);
@@ -63,38 +61,72 @@ fn send_or_log_err(sender: &ws::Sender, msg: &Message) {
     };
 }
 
-fn ws_msg2msg(ws_msg: &ws::Message) -> Result<Message, String> {
+fn ws_msg2msg(ws_msg: &ws::Message) -> Result<Message, DashboardError> {
     match ws_msg.as_text() {
         Ok(json) => {
             match serde_json::from_str(json) {
                 Ok(v) => Ok(v),
-                Err(e) => Err(format!("FAILED unjsoning {} ({})", json, e)),
+                Err(e) => Err(DashboardError::InvalidPayload(
+                    format!("FAILED unjsoning {} ({})", json, e),
+                )),
             }
         }
-        Err(e) => Err(format!("FAILED converting to text {} ({})", ws_msg, e)),
+        Err(e) => Err(DashboardError::InvalidPayload(
+            format!("FAILED converting to text {} ({})", ws_msg, e),
+        )),
     }
 }
 
+/// Splits resource of websocket request into path and query
+fn parse_resource(resource: &str) -> (&str, &str) {
+    let mut parts = resource.splitn(2, '?');
+    let path = parts.next().unwrap_or("");
+    (path, parts.next().unwrap_or(""))
+}
+
+/// Returns value of `name` param of `query`
+fn query_param<'a>(query: &'a str, name: &str) -> Option<&'a str> {
+    query.split('&').find_map(|param| {
+        let mut parts = param.splitn(2, '=');
+        if parts.next() == Some(name) {
+            Some(parts.next().unwrap_or(""))
+        } else {
+            None
+        }
+    })
+}
+
+/// Returns message with data of all tiles of `dashboard_name`
+fn snapshot_message(dashboard_name: &str) -> Result<Message, DashboardError> {
+    let jsons = db::Db::new()?.get_tiles_data(dashboard_name)?;
+    Ok(Message {
+        kind: String::from("snapshot"),
+        text: format!("[{}]", jsons.join(",")),
+    })
+}
+
 
-#[derive(Clone)]
 pub struct Server {
     pub out: ws::Sender,
-    pub redis_url: String,
     pub dashboard_name: Option<String>,
+    /// whether all tiles are sent right after connection is opened
+    pub snapshot: bool,
+    /// set while connection is open
+    watch: Option<fanout::Watch>,
 }
 
 impl Server {
-    fn publish_tiles(&mut self, tile_ids: Vec<String>, db: &db::Db) -> Result<(), Box<Error>> {
+    fn publish_tiles(&mut self, tile_ids: Vec<String>, db: &db::Db) -> Result<(), DashboardError> {
         for tile_id in &tile_ids {
-            let dashboard_name = self.dashboard_name
-                .clone()
-                .ok_or_else(|| "Can't find Dashboard name".to_string())?;
+            let dashboard_name = self.dashboard_name.clone().ok_or_else(|| {
+                DashboardError::InvalidPayload("Can't find Dashboard name".to_string())
+            })?;
             let (kind, text) = match db.get_tile(dashboard_name.as_str(), tile_id) {
-                Err(e) => (
+                Err(e) => ("error", e.to_json()),
+                Ok(None) => (
                     "error",
-                    format!("FAILED getting value for {} ({})", tile_id, e),
+                    DashboardError::NotFound(format!("Tile {} doesn't exist", tile_id)).to_json(),
                 ),
-                Ok(None) => ("error", "FAILED tile data doesn't exist".to_string()),
                 Ok(Some(json)) => ("tile", json),
             };
             send_or_log_err(
@@ -110,131 +142,82 @@ impl Server {
 }
 
 impl ws::Handler for Server {
+    /// Accepts connection at `/{dashboard_name}` when dashboard can be read,
+    /// private one needs its read token in `token` query param, `snapshot=false`
+    /// turns off sending of all tiles once connection is opened
     fn on_request(&mut self, req: &ws::Request) -> ws::Result<(ws::Response)> {
-        self.dashboard_name = {
-            debug!("dashboard_name: {}", req.resource().to_owned());
-            Some(
-                req.resource()
-                    .to_owned()
-                    .trim_left_matches('/')
-                    .trim_right_matches('/')
-                    .to_string(),
-            )
-        };
-        ws::Response::from_request(req)
+        debug!("resource: {}", req.resource());
+        let (path, query) = parse_resource(req.resource());
+        let token = query_param(query, "token");
+        let dashboard_name = path.trim_left_matches('/').trim_right_matches('/').to_string();
+        let mut response = ws::Response::from_request(req)?;
+        let readable = db::Db::new().and_then(|db| {
+            db.get_readable_dashboard(&dashboard_name, token, Access::ShowPage)
+        });
+        if let Err(e) = readable {
+            debug!("refusing connection to {} ({})", dashboard_name, e);
+            response.set_status(e.status().to_u16());
+            response.set_reason(e.public_message());
+            return Ok(response);
+        }
+        self.dashboard_name = Some(dashboard_name);
+        self.snapshot = query_param(query, "snapshot") != Some("false");
+        Ok(response)
     }
 
     fn on_open(&mut self, _shake: ws::Handshake) -> ws::Result<()> {
         debug!("on_open");
-        let redis_url = self.redis_url.clone();
 
         let dashboard_name = match self.dashboard_name.clone() {
             None => {
-                send_or_log_err(
+                send_err(
                     &self.out,
-                    &Message {
-                        kind: "error".to_string(),
-                        text: "Can't find dashboard name".to_string(),
-                    },
+                    &DashboardError::InvalidPayload("Can't find dashboard name".to_string()),
                 );
                 return Ok(());
             }
             Some(v) => v,
         };
 
-        let channel_name = db::get_dashboard_channel(&dashboard_name);
-        let cloned_server = self.clone();
-
-        // spawn redis listener
-        thread::spawn::<_, Result<(), ()>>(move || {
-            debug!("thread open");
-
-            let redis = val_or_send_msg_err!(
-                redis::Client::open(redis_url.as_str()),
-                cloned_server,
-                format!("FAILED opening redis {}", redis_url)
-            );
-            let mut pubsub =
-                val_or_send_msg_err!(redis.get_pubsub(), cloned_server, "FAILED getting pubsub");
-            if let Err(e) = pubsub.subscribe(channel_name.as_str()) {
-                send_or_log_err(
-                    &cloned_server.out,
-                    &Message {
-                        kind: String::from("error"),
-                        text: format!("can't subscribe {}", e),
-                    },
-                )
-            };
-            let db = match db::Db::new() {
-                Err(_) => {
-                    send_err(&cloned_server.out, "Can't get tile data");
-                    return Ok(());
-                }
-                Ok(v) => v,
-            };
-            loop {
-                let msg = val_or_send_msg_err!(
-                    pubsub.get_message(),
-                    cloned_server,
-                    "FAILED getting published message"
-                );
-                let tile_id: String = val_or_send_msg_err!(
-                    msg.get_payload(),
-                    cloned_server,
-                    "FAILED getting payload of published \
-                     message"
-                );
-
-                let json = match db.get_tile(dashboard_name.as_str(), &tile_id) {
-                    Err(_) => {
-                        send_err(&cloned_server.out, "Can't get tile data");
-                        return Ok(());
-                    }
-                    Ok(None) => {
-                        send_err(
-                            &cloned_server.out,
-                            format!("Tile doesn't exists: {}", &tile_id),
-                        );
-                        return Ok(());
-                    }
-                    Ok(Some(v)) => v,
-                };
-
-                send_or_log_err(
-                    &cloned_server.out,
-                    &Message {
-                        kind: String::from("tile"),
-                        text: json,
-                    },
-                );
+        // changes published while snapshot is read are pushed as well
+        match fanout::watch(&dashboard_name, self.out.clone()) {
+            Err(e) => {
+                send_err(&self.out, &e);
+                return self.out.close(ws::CloseCode::Error);
             }
-        });
-
+            Ok(watch) => self.watch = Some(watch),
+        }
+        if self.snapshot {
+            let msg = val_or_send_msg_err!(snapshot_message(&dashboard_name), self);
+            send_or_log_err(&self.out, &msg);
+        }
         Ok(())
     }
 
     fn on_message(&mut self, ws_msg: ws::Message) -> ws::Result<()> {
         debug!("on_message: {}", ws_msg);
 
-        let msg: Message = val_or_send_msg_err!(ws_msg2msg(&ws_msg), self, "");
+        let msg: Message = val_or_send_msg_err!(ws_msg2msg(&ws_msg), self);
 
         match msg.kind.as_str() {
             "update" => {
                 let tile_ids: Vec<String> = val_or_send_msg_err!(
-                    serde_json::from_str(&msg.text),
-                    self,
-                    format!("FAILED unjsoning: '{}'", &msg.text)
+                    serde_json::from_str(&msg.text).map_err(|e| {
+                        DashboardError::InvalidPayload(
+                            format!("FAILED unjsoning: '{}' ({})", &msg.text, e),
+                        )
+                    }),
+                    self
                 );
-                let db = val_or_send_msg_err!(db::Db::new(), self, "Can't get db::Db");
-                val_or_send_msg_err!(self.publish_tiles(tile_ids, &db), self, "Can't publish");
+                let db = val_or_send_msg_err!(db::Db::new(), self);
+                val_or_send_msg_err!(self.publish_tiles(tile_ids, &db), self);
             }
             _ => {
-                send_or_log_err(
+                send_err(
                     &self.out,
-                    &Message {
-                        kind: String::from("error"),
-                        text: format!("Unknown message kind: ({})", msg.kind),
-                    },
+                    &DashboardError::InvalidPayload(
+                        format!("Unknown message kind: ({})", msg.kind),
+                    ),
                 )
             }
         }
@@ -244,6 +227,7 @@ impl ws::Handler for Server {
 
     fn on_close(&mut self, _code: ws::CloseCode, reason: &str) {
         debug!("on_close: {:?}", reason);
+        self.watch = None;
     }
 }
 
@@ -253,28 +237,41 @@ mod tests {
     use super::*;
     use db;
     use serde_json::Value;
+    use std::collections::BTreeMap;
+    use std::sync::Once;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
     use test_utils;
-    use utils;
+    use utils::from_config;
+
+    static LISTENER: Once = Once::new();
+
+    /// Starts websocket listener shared by tests
+    fn start_listener() {
+        LISTENER.call_once(|| {
+            thread::spawn(move || {
+                let ws_ip_port = from_config("DASHBOARD_WEBSOCKET_IP_PORT");
+                run_ws_listener(&ws_ip_port);
+            });
+        });
+    }
 
     #[test]
     fn ws_sends_tile_data_when_it_is_requested() {
         // prepare data
-        utils::load_config(None);
+        test_utils::load_config();
         let db = db::Db::new().unwrap();
         let dashboard_name = "dashboard-test";
         test_utils::upsert_dashboard(&db, &dashboard_name);
         db.upsert_tile(&dashboard_name, "tile-test", "{\"tile-data\": {}}")
             .unwrap();
 
-        // websocket listener
-        thread::spawn(move || {
-            let ws_ip_port = from_config("DASHBOARD_WEBSOCKET_IP_PORT");
-            run_ws_listener(&ws_ip_port);
-        });
+        start_listener();
         // websocket client
         ws::connect(
             format!(
-                "ws://{}/{}",
+                "ws://{}/{}?snapshot=false",
                 from_config("DASHBOARD_WEBSOCKET_IP_PORT"),
                 dashboard_name
             ),
@@ -304,4 +301,167 @@ mod tests {
         ).unwrap()
     }
 
+    #[test]
+    fn ws_sends_snapshot_of_all_tiles_when_connection_is_opened() {
+        test_utils::load_config();
+        let db = db::Db::new().unwrap();
+        let dashboard_name = "dashboard-snapshot-test";
+        test_utils::upsert_dashboard(&db, dashboard_name);
+        db.upsert_tile(dashboard_name, "tile-1", "{\"value\": 1}")
+            .unwrap();
+        db.upsert_tile(dashboard_name, "tile-2", "{\"value\": 2}")
+            .unwrap();
+
+        start_listener();
+        ws::connect(
+            format!(
+                "ws://{}/{}",
+                from_config("DASHBOARD_WEBSOCKET_IP_PORT"),
+                dashboard_name
+            ),
+            |out| {
+                move |ws_msg| {
+                    let msg = ws_msg2msg(&ws_msg).unwrap();
+
+                    assert_eq!(msg.kind, "snapshot");
+                    assert_eq!(
+                        serde_json::from_str::<Value>(&msg.text).unwrap(),
+                        json!([
+                            {"tile-id": "tile-1", "value": 1},
+                            {"tile-id": "tile-2", "value": 2},
+                        ])
+                    );
+                    out.close(ws::CloseCode::Normal)
+                }
+            },
+        ).unwrap()
+    }
+
+    /// Waits at most 5 seconds for `condition` to hold
+    fn wait_until<F: Fn() -> bool>(condition: F) -> bool {
+        for _ in 0..500 {
+            if condition() {
+                return true;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        false
+    }
+
+    #[test]
+    fn ws_pushes_tile_update_to_every_screen_of_dashboard() {
+        test_utils::load_config();
+        let db = db::Db::new().unwrap();
+        let dashboard_name = "dashboard-fanout-test";
+        test_utils::upsert_dashboard(&db, dashboard_name);
+        let url = format!(
+            "ws://{}/{}?snapshot=false",
+            from_config("DASHBOARD_WEBSOCKET_IP_PORT"),
+            dashboard_name
+        );
+
+        start_listener();
+        let (sender, receiver) = mpsc::channel();
+        let screens: Vec<_> = (0..2)
+            .map(|_| {
+                let url = url.clone();
+                let sender = sender.clone();
+                thread::spawn(move || {
+                    ws::connect(url, |out| {
+                        let sender = sender.clone();
+                        move |ws_msg| {
+                            sender.send(ws_msg2msg(&ws_msg).unwrap()).unwrap();
+                            out.close(ws::CloseCode::Normal)
+                        }
+                    }).unwrap()
+                })
+            })
+            .collect();
+        assert!(wait_until(|| fanout::screens_count(dashboard_name) == Some(2)));
+        db.upsert_tile(dashboard_name, "tile-test", "{\"value\": 1}")
+            .unwrap();
+        for screen in screens {
+            screen.join().unwrap();
+        }
+
+        let msgs: Vec<Message> = receiver.try_iter().collect();
+        assert_eq!(msgs.len(), 2);
+        for msg in msgs {
+            assert_eq!(msg.kind, "tile");
+            assert_eq!(
+                serde_json::from_str::<Value>(&msg.text).unwrap(),
+                json!({"tile-id": "tile-test", "value": 1})
+            );
+        }
+    }
+
+    #[test]
+    fn ws_stops_thread_of_dashboard_when_its_last_screen_is_closed() {
+        test_utils::load_config();
+        let db = db::Db::new().unwrap();
+        let dashboard_name = "dashboard-fanout-stop-test";
+        test_utils::upsert_dashboard(&db, dashboard_name);
+
+        start_listener();
+        ws::connect(
+            format!(
+                "ws://{}/{}",
+                from_config("DASHBOARD_WEBSOCKET_IP_PORT"),
+                dashboard_name
+            ),
+            |out| {
+                move |_| {
+                    // snapshot is sent once screen is watched
+                    assert_eq!(fanout::screens_count(dashboard_name), Some(1));
+                    out.close(ws::CloseCode::Normal)
+                }
+            },
+        ).unwrap();
+
+        assert!(wait_until(|| fanout::screens_count(dashboard_name).is_none()));
+    }
+
+    #[test]
+    fn ws_snapshot_has_tiles_left_after_batch_upsert_delete_and_rename() {
+        test_utils::load_config();
+        let db = db::Db::new().unwrap();
+        let dashboard = test_utils::upsert_dashboard(&db, "dashboard-snapshot-old");
+        let mut tiles = BTreeMap::new();
+        tiles.insert("tile-1".to_string(), json!({"value": 1}));
+        tiles.insert("tile-2".to_string(), json!({"value": 2}));
+        db.upsert_tiles(&dashboard, &tiles).unwrap();
+        db.delete_tile("dashboard-snapshot-old", "tile-1").unwrap();
+        let mut renamed = dashboard.clone();
+        renamed.name = "dashboard-snapshot-new".to_string();
+        db.rename_dashboard("dashboard-snapshot-old", &renamed)
+            .unwrap();
+
+        let old = snapshot_message("dashboard-snapshot-old").unwrap();
+        let new = snapshot_message("dashboard-snapshot-new").unwrap();
+
+        assert_eq!(old.text, "[]");
+        assert_eq!(
+            serde_json::from_str::<Value>(&new.text).unwrap(),
+            json!([{"tile-id": "tile-2", "value": 2}])
+        );
+    }
+
+    #[test]
+    fn parse_resource_returns_path_and_query() {
+        assert_eq!(parse_resource("/dashboard-1/"), ("/dashboard-1/", ""));
+        assert_eq!(
+            parse_resource("/dashboard-1?lang=en&token=secret"),
+            ("/dashboard-1", "lang=en&token=secret")
+        );
+    }
+
+    #[test]
+    fn query_param_returns_value_of_param() {
+        let query = "lang=en&token=secret&snapshot";
+
+        assert_eq!(query_param(query, "token"), Some("secret"));
+        assert_eq!(query_param(query, "snapshot"), Some(""));
+        assert_eq!(query_param(query, "to"), None);
+        assert_eq!(query_param("", "token"), None);
+    }
 }